@@ -1,8 +1,12 @@
 use hyper_tls::HttpsConnector;
-use nerf::{IntoService, ReadyCall};
+use nerf::{
+    observe::{ObserveLayer, StatusLayer},
+    sign::SignLayer,
+    IntoService, ReadyCall,
+};
 use nerf_exchanges::{
-    binance::{self, BinanceSpotClient},
-    common::{CommonOpsService, Order, Side},
+    binance::{self, BinanceSpotPrivateClient},
+    common::{CommonOpsService, ExchangeLabels, Order, Side},
     KeySecretAuthentication,
 };
 use rust_decimal_macros::dec;
@@ -14,14 +18,21 @@ async fn main() -> Result<(), anyhow::Error> {
     let key = std::env::var("BINANCE_API_KEY").unwrap();
     let secret = std::env::var("BINANCE_API_SECRET").unwrap();
 
-    let mut svc = tower::ServiceBuilder::new()
-        .layer_fn(|svc| {
-            BinanceSpotClient::new(svc)
-                .with_auth(KeySecretAuthentication::new(&key, &secret))
-                .into_service()
-        })
+    let limiter = binance::spot_rate_limiter();
+    let transport = tower::ServiceBuilder::new()
+        .layer(limiter.feedback_layer(binance::UsageHeaders))
+        .layer(SignLayer::new(binance::HmacSigner::new(
+            KeySecretAuthentication::new(&key, &secret),
+        )))
+        .layer(StatusLayer)
         .service(hyper::Client::builder().build(HttpsConnector::new()));
 
+    let mut svc = tower::ServiceBuilder::new()
+        .layer(limiter.layer())
+        .layer(ObserveLayer::new(ExchangeLabels::new("binance")))
+        .layer_fn(|svc| BinanceSpotPrivateClient::new(svc).into_service())
+        .service(transport);
+
     let result = svc
         .ready_call(binance::GetApiV3Depth {
             symbol: "BTCBUSD".to_string(),
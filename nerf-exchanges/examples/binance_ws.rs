@@ -0,0 +1,30 @@
+use futures::StreamExt;
+use nerf::ws::Connector;
+use nerf_exchanges::binance::ws::{BookTickers, CombinedStreams, Trades, SPOT_ENDPOINT};
+use tracing::info;
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+
+    let connection = Connector::new(SPOT_ENDPOINT, CombinedStreams::default()).connect();
+    let trades = connection
+        .subscribe(&Trades {
+            symbol: "btcusdt".to_string(),
+        })
+        .unwrap();
+    let tickers = connection
+        .subscribe(&BookTickers {
+            symbol: "btcusdt".to_string(),
+        })
+        .unwrap();
+
+    let mut trades = trades.take(5);
+    while let Some(trade) = trades.next().await {
+        info!(trade = ?trade);
+    }
+    let mut tickers = tickers.take(5);
+    while let Some(ticker) = tickers.next().await {
+        info!(ticker = ?ticker);
+    }
+}
@@ -1,8 +1,8 @@
 use anyhow::anyhow;
 use hyper_tls::HttpsConnector;
-use nerf::IntoService;
+use nerf::{sign::SignLayer, IntoService};
 use nerf_exchanges::{
-    binance::BinanceSpotClient,
+    binance::{BinanceSpotPrivateClient, HmacSigner},
     common::{BoxCommonOpsService, Order, Side},
     KeySecretAuthentication,
 };
@@ -16,11 +16,10 @@ async fn main() -> Result<(), anyhow::Error> {
     let secret = std::env::var("BINANCE_API_SECRET").unwrap();
 
     let svc = tower::ServiceBuilder::new()
-        .layer_fn(|svc| {
-            BinanceSpotClient::new(svc)
-                .with_auth(KeySecretAuthentication::new(&key, &secret))
-                .into_service()
-        })
+        .layer_fn(|svc| BinanceSpotPrivateClient::new(svc).into_service())
+        .layer(SignLayer::new(HmacSigner::new(
+            KeySecretAuthentication::new(&key, &secret),
+        )))
         .service(hyper::Client::builder().build(HttpsConnector::new()));
 
     let mut svc = BoxCommonOpsService::new(svc);
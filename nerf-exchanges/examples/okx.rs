@@ -1,7 +1,7 @@
 use hyper_tls::HttpsConnector;
-use nerf::IntoService;
+use nerf::{sign::SignLayer, IntoService};
 use nerf_exchanges::common::CommonOpsService;
-use nerf_exchanges::okx::OkxClient;
+use nerf_exchanges::okx::{Authentication, OkxPrivateClient};
 use tracing::info;
 
 #[tokio::main]
@@ -13,15 +13,8 @@ async fn main() {
     let passphrase = std::env::var("OKX_API_PASSPHRASE").unwrap();
 
     let mut svc = tower::ServiceBuilder::new()
-        .layer_fn(|svc| {
-            OkxClient::new(svc)
-                .with_auth(nerf_exchanges::okx::Authentication::new(
-                    key.clone(),
-                    secret.clone(),
-                    passphrase.clone(),
-                ))
-                .into_service()
-        })
+        .layer_fn(|svc| OkxPrivateClient::new(svc).into_service())
+        .layer(SignLayer::new(Authentication::new(key, secret, passphrase)))
         .service(hyper::Client::builder().build(HttpsConnector::new()));
 
     let result = svc.get_balance().await;
@@ -10,7 +10,7 @@ async fn main() {
     let mut svc = tower::ServiceBuilder::new()
         .layer_fn(|svc| {
             UpbitClient::new(svc)
-                // .with_auth(KeySecretAuthentication::new(&key, &secret))
+                // .with_signer(JwtSigner::new(KeySecretAuthentication::new(&key, &secret)))
                 .into_service()
         })
         .service(hyper::Client::builder().build(HttpsConnector::new()));
@@ -1,20 +1,27 @@
-use std::{collections::HashMap, fmt::Debug, future::Future, pin::Pin};
+use std::collections::HashMap;
 
 use chrono::{serde::ts_milliseconds, DateTime, Utc};
-use nerf::{delete, get, post, tag, Client, HttpRequest, Request};
+use nerf::{
+    delete, get, post, rate_limited,
+    rest::{Codec, RestClient},
+    retry, Bytes, HttpRequest, Request, Validate,
+};
 use rust_decimal::Decimal;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 
 use crate::{
     common::{
-        self, CommonOps, Disabled, IntoCommon, Market, Orderbook, OrderbookItem, Private, Signer,
-        Ticker, Unsupported,
+        self, CommonOps, Deferred, Disabled, IntoCommon, InvalidParameter, Market, Orderbook,
+        OrderbookItem, Private, Public, Shim, Signer, Ticker, Unsupported, UnsupportedParameter,
     },
-    Error, KeySecretAuthentication as Authentication,
+    Error,
 };
 
-use super::{BinanceOrderbookItem, OrderType, Side, TimeInForce, __private::Sealed, split_end};
+use super::{
+    __private::Sealed, split_end, BinanceEnvelope, BinanceError, BinanceOrderbookItem, Environment,
+    OrderType, Side, TimeInForce,
+};
 
 fn bool_str<S>(x: &bool, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -63,8 +70,9 @@ pub enum PositionSide {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[get("https://fapi.binance.com/fapi/v1/ticker/bookTicker", response = GetFapiV1TickerBooktickerResponse)]
-#[tag(Signer = Disabled)]
+#[get("/fapi/v1/ticker/bookTicker", response = GetFapiV1TickerBooktickerResponse, error = BinanceError, signer = Disabled, cacheable)]
+#[rate_limited(weight = if self.symbol.is_some() { 1 } else { 2 })]
+#[retry(idempotent)]
 pub struct GetFapiV1TickerBookticker {
     pub symbol: Option<String>,
 }
@@ -87,8 +95,9 @@ pub struct GetFapiV1TickerBooktickerResponseItem {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[get("https://fapi.binance.com/fapi/v1/trades", response = GetFapiV1TradesResponse)]
-#[tag(Signer = Disabled)]
+#[get("/fapi/v1/trades", response = GetFapiV1TradesResponse, error = BinanceError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 #[serde(rename_all = "camelCase")]
 pub struct GetFapiV1Trades {
     pub symbol: String,
@@ -111,8 +120,14 @@ pub struct GetFapiV1TradesResponseItem {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[get("https://fapi.binance.com/fapi/v1/depth", response = GetFapiV1DepthResponse)]
-#[tag(Signer = Disabled)]
+#[get("/fapi/v1/depth", response = GetFapiV1DepthResponse, error = BinanceError, signer = Disabled, cacheable)]
+#[rate_limited(weight = match self.limit.unwrap_or(500) {
+    0..=50 => 2,
+    51..=100 => 5,
+    101..=500 => 10,
+    _ => 20,
+})]
+#[retry(idempotent)]
 #[serde(rename_all = "camelCase")]
 pub struct GetFapiV1Depth {
     pub symbol: String,
@@ -132,8 +147,9 @@ pub struct GetFapiV1DepthResponse {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[get("https://fapi.binance.com/fapi/v2/balance", response = GetFapiV2BalanceResponse)]
-#[tag(Signer = Private)]
+#[get("/fapi/v2/balance", response = GetFapiV2BalanceResponse, error = BinanceError, signer = Private)]
+#[rate_limited(weight = 5)]
+#[retry(idempotent)]
 pub struct GetFapiV2Balance {} // serde-urlencoded does not support serializing unit structs
 
 #[derive(Clone, Debug, Deserialize)]
@@ -155,8 +171,9 @@ pub struct GetFapiV2BalanceResponseItem {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[get("https://fapi.binance.com/fapi/v2/positionRisk", response = GetFapiV2PositionRiskResponse)]
-#[tag(Signer = Private)]
+#[get("/fapi/v2/positionRisk", response = GetFapiV2PositionRiskResponse, error = BinanceError, signer = Private)]
+#[rate_limited(weight = 5)]
+#[retry(idempotent)]
 #[skip_serializing_none]
 pub struct GetFapiV2PositionRisk {
     pub symbol: Option<String>,
@@ -190,8 +207,9 @@ pub struct GetFapiV2PositionRiskResponseItem {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[post("https://fapi.binance.com/fapi/v1/order", response = PostFapiV1OrderResponse)]
-#[tag(Signer = Private)]
+#[post("/fapi/v1/order", response = PostFapiV1OrderResponse, error = BinanceError, signer = Private, shim = common::PlaceOrder, builder(validate))]
+#[rate_limited(weight = 1, order_10s = 1, order_1m = 1)]
+#[retry(never)]
 #[serde(rename_all = "camelCase")]
 #[skip_serializing_none]
 pub struct PostFapiV1Order {
@@ -203,6 +221,7 @@ pub struct PostFapiV1Order {
     time_in_force: Option<TimeInForce>,
     quantity: Option<Decimal>,
     #[serde(serialize_with = "bool_str")]
+    #[optional]
     reduce_only: bool,
     price: Option<Decimal>,
     new_client_order_id: Option<String>,
@@ -212,6 +231,7 @@ pub struct PostFapiV1Order {
     callback_rate: Option<Decimal>,
     working_type: Option<String>,
     #[serde(serialize_with = "bool_str_screaming")]
+    #[optional]
     price_protect: bool,
     new_order_resp_type: Option<&'static str>,
 }
@@ -247,8 +267,9 @@ pub struct PostFapiV1OrderResponse {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[get("https://fapi.binance.com/fapi/v1/openOrder", response = GetFapiV1OpenOrderResponse)]
-#[tag(Signer = Private)]
+#[get("/fapi/v1/openOrder", response = GetFapiV1OpenOrderResponse, error = BinanceError, signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 #[serde(rename_all = "camelCase")]
 #[skip_serializing_none]
 pub struct GetFapiV1OpenOrder {
@@ -289,8 +310,9 @@ pub struct GetFapiV1OpenOrderResponse {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[get("https://fapi.binance.com/fapi/v1/openOrders", response = GetFapiV1OpenOrdersResponse)]
-#[tag(Signer = Private)]
+#[get("/fapi/v1/openOrders", response = GetFapiV1OpenOrdersResponse, error = BinanceError, signer = Private)]
+#[rate_limited(weight = if self.symbol.is_some() { 1 } else { 40 })]
+#[retry(idempotent)]
 #[serde(rename_all = "camelCase")]
 #[skip_serializing_none]
 pub struct GetFapiV1OpenOrders {
@@ -301,8 +323,9 @@ pub struct GetFapiV1OpenOrders {
 pub struct GetFapiV1OpenOrdersResponse(Vec<GetFapiV1OpenOrderResponse>);
 
 #[derive(Clone, Debug, Serialize)]
-#[delete("https://fapi.binance.com/fapi/v1/order", response = DeleteFapiV1OrderResponse)]
-#[tag(Signer = Private)]
+#[delete("/fapi/v1/order", response = DeleteFapiV1OrderResponse, error = BinanceError, signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(never)]
 #[serde(rename_all = "camelCase")]
 #[skip_serializing_none]
 pub struct DeleteFapiV1Order {
@@ -341,8 +364,9 @@ pub struct DeleteFapiV1OrderResponse {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[delete("https://fapi.binance.com/fapi/v1/allOpenOrders", response = DeleteFapiV1AllOpenOrdersResponse)]
-#[tag(Signer = Private)]
+#[delete("/fapi/v1/allOpenOrders", response = DeleteFapiV1AllOpenOrdersResponse, error = BinanceError, signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(never)]
 #[serde(rename_all = "camelCase")]
 #[skip_serializing_none]
 pub struct DeleteFapiV1AllOpenOrders {
@@ -390,8 +414,14 @@ pub enum CandleInterval {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[get("https://fapi.binance.com/fapi/v1/klines", response = GetFapiV1KlinesResponse)]
-#[tag(Signer = Disabled)]
+#[get("/fapi/v1/klines", response = GetFapiV1KlinesResponse, error = BinanceError, signer = Disabled, cacheable)]
+#[rate_limited(weight = match self.limit.unwrap_or(500) {
+    0..=99 => 1,
+    100..=499 => 2,
+    500..=1000 => 5,
+    _ => 10,
+})]
+#[retry(idempotent)]
 #[serde(rename_all = "camelCase")]
 #[skip_serializing_none]
 pub struct GetFapiV1Klines {
@@ -488,39 +518,66 @@ impl From<common::GetPosition> for GetFapiV2PositionRisk {
     }
 }
 
-impl From<common::PlaceOrder> for PostFapiV1Order {
-    fn from(x: common::PlaceOrder) -> Self {
-        Self {
-            symbol: format!("{}{}", x.market.base(), x.market.quote()),
-            side: match x.order.side() {
+impl Validate for PostFapiV1Order {
+    type Error = InvalidParameter;
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        use OrderType::*;
+
+        if matches!(
+            self.order_type,
+            Limit | StopLossLimit | TakeProfitLimit | LimitMaker
+        ) && self.price.is_none()
+        {
+            return Err(InvalidParameter("price is required for limit orders"));
+        }
+        if matches!(
+            self.order_type,
+            StopLoss | StopLossLimit | TakeProfit | TakeProfitLimit
+        ) && self.stop_price.is_none()
+        {
+            return Err(InvalidParameter("stop price is required for stop orders"));
+        }
+        if self.quantity.is_none() && self.close_position.is_none() {
+            return Err(InvalidParameter(
+                "quantity is required, or close position for stop orders",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Shim<common::PlaceOrder> for PostFapiV1Order {
+    type Error = Error<BinanceError>;
+
+    fn shim(x: common::PlaceOrder) -> Result<Self, Self::Error> {
+        let order_type = match x.order {
+            common::Order::Market { .. } => OrderType::Market,
+            common::Order::Limit { .. } => OrderType::Limit,
+            common::Order::StopMarket { .. } | common::Order::StopLimit { .. } => {
+                return Err(UnsupportedParameter("stop orders").into()); // FIXME
+            }
+        };
+        Ok(Self::builder()
+            .symbol(format!("{}{}", x.market.base(), x.market.quote()))
+            .side(match x.order.side() {
                 common::Side::Buy => Side::Buy,
                 common::Side::Sell => Side::Sell,
-            },
-            position_side: Some(PositionSide::Both), // TODO: can `common::PlaceOrder` support two-way mode?
-            order_type: match x.order {
-                common::Order::Market { .. } => OrderType::Market,
-                common::Order::Limit { .. } => OrderType::Limit,
-                common::Order::StopMarket { .. } => todo!(), // FIXME
-                common::Order::StopLimit { .. } => todo!(),  // FIXME
-            },
-            time_in_force: x.order.time_in_force().map(|tif| match tif {
+            })
+            .position_side(PositionSide::Both) // TODO: can `common::PlaceOrder` support two-way mode?
+            .order_type(order_type)
+            .time_in_force(x.order.time_in_force().map(|tif| match tif {
                 common::TimeInForce::GoodTilCancled => TimeInForce::GoodTilCanceled,
                 common::TimeInForce::ImmediateOrCancel => TimeInForce::ImmediateOrCancel,
                 common::TimeInForce::FillOrKill => TimeInForce::FillOrKill,
                 common::TimeInForce::GoodTilCrossing => TimeInForce::GoodTilCrossing,
-            }),
-            quantity: Some(x.order.quantity()),
-            reduce_only: false, // TODO
-            price: x.order.price(),
-            new_client_order_id: None,
-            stop_price: x.order.stop_price(),
-            close_position: None,
-            activation_price: None,
-            callback_rate: None,
-            working_type: None,
-            price_protect: false,
-            new_order_resp_type: Some("FULL"),
-        }
+            }))
+            .quantity(x.order.quantity())
+            .reduce_only(false) // TODO
+            .price(x.order.price())
+            .stop_price(x.order.stop_price())
+            .new_order_resp_type("FULL")
+            .build()?)
     }
 }
 
@@ -590,77 +647,29 @@ impl IntoCommon for GetFapiV1DepthResponse {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct BinanceFuturesClient<S>(S);
-
-impl<S> BinanceFuturesClient<S> {
-    pub fn new(x: S) -> Self {
-        Self(x)
-    }
-
-    pub fn with_auth(self, authentication: Authentication) -> BinanceFuturesPrivateClient<S> {
-        BinanceFuturesPrivateClient {
-            client: self,
-            authentication,
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct BinanceFuturesPrivateClient<S> {
-    client: BinanceFuturesClient<S>,
-    authentication: Authentication,
-}
+/// Encodes USD-M futures API requests for [`BinanceFuturesClient`], which sends them to the production
+/// unless another base URL is set, e.g. [`Environment::futures_base_url`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FuturesCodec;
 
-impl<T, S> Client<T> for BinanceFuturesClient<S>
+impl<T> Codec<T> for FuturesCodec
 where
-    T: Request + HttpRequest + Sealed + Signer<Signer = Disabled> + Serialize + Debug,
-    T::Response: DeserializeOwned,
+    T: Request + HttpRequest + Sealed + Signer + Serialize,
 {
-    type Service = S;
-
-    type Error = Error;
-
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
-
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.0
-    }
-
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        super::try_into_request(x)
-    }
+    type Error = Error<T::ErrorResponse>;
 
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
-        super::try_from_response::<T>(x)
+    fn encode(&mut self, x: &T) -> Result<nerf::http::Request<Bytes>, Self::Error> {
+        super::encode(&Environment::Production.futures_base_url(), x)
     }
 }
 
-impl<T, S> Client<T> for BinanceFuturesPrivateClient<S>
-where
-    T: Request + HttpRequest + Sealed + Signer + Serialize + Debug,
-    T::Response: DeserializeOwned,
-{
-    type Service = S;
-
-    type Error = Error;
-
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
-
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.client.0
-    }
-
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        super::try_into_request_signed(&self.authentication, x)
-    }
+pub type BinanceFuturesClient<S> = RestClient<FuturesCodec, BinanceEnvelope, Public, S>;
 
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
-        super::try_from_response::<T>(x)
-    }
-}
+/// [`BinanceFuturesClient`] accepting private requests, which must be signed by a [`SignLayer`] with
+/// [`HmacSigner`](super::HmacSigner) on the transport.
+///
+/// [`SignLayer`]: nerf::sign::SignLayer
+pub type BinanceFuturesPrivateClient<S> = RestClient<FuturesCodec, BinanceEnvelope, Deferred, S>;
 
 impl<S> CommonOps for BinanceFuturesClient<S> {
     type GetTickersRequest = GetFapiV1TickerBookticker;
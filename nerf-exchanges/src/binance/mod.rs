@@ -1,18 +1,26 @@
 mod futures;
 mod spot;
+#[cfg(feature = "ws")]
+pub mod ws;
 
 pub use self::futures::*;
 pub use spot::*;
 
 use std::{
-    fmt::{Debug, Write},
-    future::Future,
-    pin::Pin,
+    fmt::{Debug, Display, Write},
+    time::Duration,
 };
 
-use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use chrono::Utc;
 use hmac::{Hmac, Mac};
-use nerf::{http::StatusCode, HttpRequest, Request};
+use nerf::{
+    base_url::BaseUrl,
+    http::{header::CONTENT_TYPE, HeaderMap, Method, StatusCode, Uri},
+    rate_limit::{Bucket, ParseUsage, RateLimiter, RetryAfter, Usage},
+    rest::Envelope,
+    sign::Sign,
+    Bytes, Classify, ErrorClass, HttpRequest, Request,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sha2::Sha256;
 use tracing::trace;
@@ -55,158 +63,229 @@ pub enum TimeInForce {
     GoodTilCrossing,
 }
 
-fn try_into_request<T>(x: T) -> Result<hyper::Request<hyper::Body>, Error>
-where
-    T: Request + HttpRequest + Sealed + Signer + Serialize + Debug,
-    T::Response: DeserializeOwned,
-{
-    if x.method() == nerf::http::Method::GET {
-        let params = serde_urlencoded::to_string(&x).map_err(Error::SerializeUrlencodedBody)?;
-        let uri = x.uri();
-        assert!(uri.query().is_none()); // TODO
-        Ok(hyper::Request::builder()
-            .uri(format!("{uri}?{params}"))
-            .method(x.method())
-            .body(hyper::Body::empty())
-            .map_err(Error::ConstructHttpRequest)?)
-    } else {
-        let bytes = serde_json::to_vec(&x).map_err(Error::SerializeJsonBody)?;
-        Ok(hyper::Request::builder()
-            .uri(x.uri())
-            .method(x.method())
-            .body(bytes.into())
-            .map_err(Error::ConstructHttpRequest)?)
+/// Binance environments to send requests to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Environment {
+    #[default]
+    Production,
+    /// The [spot testnet](https://testnet.binance.vision) and the futures testnet, which do not
+    /// share API keys with the production.
+    Testnet,
+}
+
+impl Environment {
+    /// Returns the base URL of the spot API.
+    pub fn spot_base_url(self) -> BaseUrl {
+        match self {
+            Self::Production => BaseUrl::from_static("https://api.binance.com"),
+            Self::Testnet => BaseUrl::from_static("https://testnet.binance.vision"),
+        }
+    }
+
+    /// Returns the base URL of the USD-M futures API.
+    pub fn futures_base_url(self) -> BaseUrl {
+        match self {
+            Self::Production => BaseUrl::from_static("https://fapi.binance.com"),
+            Self::Testnet => BaseUrl::from_static("https://testnet.binancefuture.com"),
+        }
+    }
+}
+
+/// Returns a [`RateLimiter`] for the spot API request weight limit(1200 per minute) and order
+/// count limits(50 per 10 seconds, 160000 per day).
+pub fn spot_rate_limiter() -> RateLimiter {
+    RateLimiter::new([
+        Bucket::new("weight", 1200, Duration::from_secs(60)),
+        Bucket::new("order_10s", 50, Duration::from_secs(10)).listed_only(),
+        Bucket::new("order_1d", 160000, Duration::from_secs(24 * 60 * 60)).listed_only(),
+    ])
+}
+
+/// Returns a [`RateLimiter`] for the USD-M futures API request weight limit(2400 per minute) and
+/// order count limits(300 per 10 seconds, 1200 per minute).
+pub fn futures_rate_limiter() -> RateLimiter {
+    RateLimiter::new([
+        Bucket::new("weight", 2400, Duration::from_secs(60)),
+        Bucket::new("order_10s", 300, Duration::from_secs(10)).listed_only(),
+        Bucket::new("order_1m", 1200, Duration::from_secs(60)).listed_only(),
+    ])
+}
+
+/// Parses the usage headers of Binance responses to feed them back into [`RateLimiter`]s.
+///
+/// `X-MBX-USED-WEIGHT-1M` is reported as the `weight` bucket, and `X-MBX-ORDER-COUNT-(10S|1M|1D)`
+/// as the `order_10s`, `order_1m` and `order_1d` buckets respectively. `418 I'm a teapot` and
+/// `429 Too Many Requests` responses trigger a cool-down for the `Retry-After` seconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UsageHeaders;
+
+impl ParseUsage for UsageHeaders {
+    fn parse_usage(&self, status: StatusCode, headers: &HeaderMap) -> Vec<Usage> {
+        const HEADERS: [(&str, &str); 4] = [
+            ("x-mbx-used-weight-1m", "weight"),
+            ("x-mbx-order-count-10s", "order_10s"),
+            ("x-mbx-order-count-1m", "order_1m"),
+            ("x-mbx-order-count-1d", "order_1d"),
+        ];
+
+        let mut usages = RetryAfter::new(Duration::from_secs(60)).parse_usage(status, headers);
+        for (header, bucket) in HEADERS {
+            let used = headers
+                .get(header)
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.parse().ok());
+            if let Some(used) = used {
+                usages.push(Usage::Used { bucket, used });
+            }
+        }
+        usages
     }
 }
 
-fn try_into_request_signed<T>(
-    authentication: &KeySecretAuthentication,
-    x: T,
-) -> Result<hyper::Request<hyper::Body>, Error>
+/// Encodes a request against `base_url`, into the query string for `GET` requests or into the JSON
+/// body for others. Every parameter of private requests goes into the query string instead, to be
+/// signed by [`HmacSigner`].
+fn encode<T>(
+    base_url: &BaseUrl,
+    x: &T,
+) -> Result<nerf::http::Request<Bytes>, Error<T::ErrorResponse>>
 where
-    T: Request + HttpRequest + Sealed + Signer + Serialize + Debug,
-    T::Response: DeserializeOwned,
+    T: Request + HttpRequest + Signer + Serialize,
 {
-    if !<T::Signer as SignerKind>::is_private() {
-        if x.method() == nerf::http::Method::GET {
-            let params = serde_urlencoded::to_string(&x).map_err(Error::SerializeUrlencodedBody)?;
-            let uri = x.uri();
-            assert!(uri.query().is_none()); // TODO
-            return hyper::Request::builder()
-                .uri(format!("{uri}?{params}"))
-                .method(x.method())
-                .body(hyper::Body::empty())
-                .map_err(Error::ConstructHttpRequest);
+    let method = x.method();
+    let (uri, content_type, body) = if <T::Signer as SignerKind>::is_private() {
+        let content_type = if method == Method::GET {
+            None
+        } else if method == Method::POST || method == Method::DELETE {
+            Some("application/x-www-form-urlencoded")
         } else {
-            let bytes = serde_json::to_vec(&x).map_err(Error::SerializeJsonBody)?;
-            return hyper::Request::builder()
-                .uri(x.uri())
-                .method(x.method())
-                .body(bytes.into())
-                .map_err(Error::ConstructHttpRequest);
-        }
+            return Err(Error::UnsupportedHttpMethod(method));
+        };
+        let params = serde_urlencoded::to_string(x).map_err(Error::SerializeUrlencodedBody)?;
+        (x.uri_with_query(&params)?, content_type, Bytes::new())
+    } else if method == Method::GET {
+        let params = serde_urlencoded::to_string(x).map_err(Error::SerializeUrlencodedBody)?;
+        (x.uri_with_query(&params)?, None, Bytes::new())
+    } else {
+        let body = serde_json::to_vec(x).map_err(Error::SerializeJsonBody)?;
+        (x.uri()?, None, body.into())
+    };
+
+    let mut builder = nerf::http::Request::builder()
+        .uri(base_url.join(&uri)?)
+        .method(method);
+    if let Some(content_type) = content_type {
+        builder = builder.header(CONTENT_TYPE, content_type);
     }
+    let mut req = builder.body(body).map_err(Error::ConstructHttpRequest)?;
+    req.headers_mut().extend(x.headers()?);
+    Ok(req)
+}
 
-    type HmacSha256 = Hmac<Sha256>;
-    const SIGN_RECV_WINDOW_MILLIS: u64 = 5000;
-
-    #[derive(Serialize, Debug)]
-    #[serde(rename_all = "camelCase")]
-    struct SignedRequest<R>
-    where
-        R: Serialize,
-    {
-        #[serde(flatten)]
-        req: R,
-        recv_window: u64,
-        #[serde(with = "ts_milliseconds")]
-        timestamp: DateTime<Utc>,
-    }
-
-    let req = x;
-    let method = req.method();
-    let uri = req.uri();
-    let signed_req = SignedRequest {
-        req,
-        recv_window: SIGN_RECV_WINDOW_MILLIS,
-        timestamp: chrono::Utc::now(),
-    };
-    trace!(uri = uri.to_string(), signed_req = ?signed_req, api_key = authentication.key(), method = method.to_string());
-    let mut hmac = HmacSha256::new(authentication.secret().as_bytes().into());
-    let params =
-        serde_urlencoded::to_string(&signed_req).map_err(Error::SerializeUrlencodedBody)?;
-    hmac.update(params.as_bytes());
-    let signature = hmac.finalize().into_bytes();
-    let signature = if params.is_empty() {
-        let mut s = String::with_capacity(signature.len() * 2 + "signature=".len());
-        s.push_str("signature=");
-        for &b in signature.as_slice() {
-            write!(&mut s, "{:02x}", b).unwrap();
-        }
-        s
-    } else {
-        let mut s = String::with_capacity(signature.len() * 2 + "&signature=".len());
-        s.push_str("&signature=");
-        for &b in signature.as_slice() {
-            write!(&mut s, "{:02x}", b).unwrap();
+/// Signs private Binance requests with HMAC-SHA256 on a [`SignLayer`].
+///
+/// `recvWindow` and `timestamp` are appended to the query string when the request is sent.
+///
+/// [`SignLayer`]: nerf::sign::SignLayer
+#[derive(Clone, Debug)]
+pub struct HmacSigner {
+    authentication: KeySecretAuthentication,
+}
+
+impl HmacSigner {
+    pub fn new(authentication: KeySecretAuthentication) -> Self {
+        Self { authentication }
+    }
+}
+
+impl Sign for HmacSigner {
+    type Error = nerf::http::Error;
+
+    fn sign(&self, x: &mut nerf::http::Request<Bytes>) -> Result<(), Self::Error> {
+        const SIGN_RECV_WINDOW_MILLIS: u64 = 5000;
+
+        let timestamp = Utc::now().timestamp_millis();
+        let params = match x.uri().query() {
+            Some(query) if !query.is_empty() => {
+                format!("{query}&recvWindow={SIGN_RECV_WINDOW_MILLIS}&timestamp={timestamp}")
+            }
+            _ => format!("recvWindow={SIGN_RECV_WINDOW_MILLIS}&timestamp={timestamp}"),
+        };
+
+        let mut hmac = Hmac::<Sha256>::new_from_slice(self.authentication.secret().as_bytes())
+            .expect("HMAC can take key of any size");
+        hmac.update(params.as_bytes());
+        hmac.update(x.body());
+        let mut signature = String::with_capacity(64);
+        for &b in hmac.finalize().into_bytes().as_slice() {
+            write!(&mut signature, "{:02x}", b).unwrap();
         }
-        s
-    };
 
-    let full_uri = format!("{uri}?{params}{signature}");
-    trace!(full_uri = full_uri, method = ?method);
-    if method == nerf::http::Method::GET {
-        assert!(uri.query().is_none()); // TODO
-        Ok(hyper::Request::builder()
-            .uri(full_uri)
-            .method(method)
-            .header("X-MBX-APIKEY", authentication.key.clone())
-            .body(hyper::Body::empty())
-            .map_err(Error::ConstructHttpRequest)?)
-    } else if method == nerf::http::Method::POST || method == nerf::http::Method::DELETE {
-        Ok(hyper::Request::builder()
-            .uri(full_uri)
-            .method(method)
-            .header("X-MBX-APIKEY", authentication.key.clone())
-            .header("Content-Type", "x-www-form-urlencoded")
-            .body(hyper::Body::empty())
-            .map_err(Error::ConstructHttpRequest)?)
-    } else {
-        Err(Error::UnsupportedHttpMethod(method))
+        let uri = format!("{}?{params}&signature={signature}", x.uri().path());
+        let mut parts = x.uri().clone().into_parts();
+        parts.path_and_query = Some(uri.parse().map_err(nerf::http::Error::from)?);
+        *x.uri_mut() = Uri::from_parts(parts).map_err(nerf::http::Error::from)?;
+        trace!(uri = ?x.uri(), method = ?x.method(), api_key = self.authentication.key());
+        x.headers_mut()
+            .insert("X-MBX-APIKEY", self.authentication.key().parse()?);
+        Ok(())
     }
 }
 
-fn try_from_response<T>(
-    x: hyper::Response<hyper::Body>,
-) -> Pin<Box<dyn Future<Output = Result<T::Response, Error>> + Send + Sync + 'static>>
+/// Decodes Binance responses, which are not wrapped on success.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BinanceEnvelope;
+
+impl<T> Envelope<T> for BinanceEnvelope
 where
-    T: Request + HttpRequest + Sealed + Signer + Serialize + Debug,
+    T: Request + Sealed,
     T::Response: DeserializeOwned,
+    T::ErrorResponse: DeserializeOwned + Classify,
 {
-    Box::pin(async move {
-        let status = x.status();
-        let buf = hyper::body::to_bytes(x).await?;
-        if status != StatusCode::OK {
-            #[derive(Deserialize)]
-            struct ErrorResponse {
-                code: i64,
-                msg: String,
-            }
+    type Error = Error<T::ErrorResponse>;
 
-            let error: ErrorResponse = serde_json::from_slice(&buf).map_err(|e| {
-                Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-            })?;
-            Err(Error::RequestFailed {
-                code: Some(error.code.to_string()),
-                msg: Some(error.msg),
-            })
-        } else {
-            let resp = serde_json::from_slice(&buf).map_err(|e| {
-                Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-            })?;
-            Ok(resp)
+    fn decode(x: nerf::http::Response<Bytes>) -> Result<T::Response, Self::Error> {
+        if x.status() != StatusCode::OK {
+            return Err(crate::error_response(x.status(), x.body(), classify_status));
         }
-    })
+        crate::deserialize_body(x.body())
+    }
+}
+
+/// An error response of Binance APIs.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BinanceError {
+    pub code: i64,
+    pub msg: String,
+}
+
+impl Display for BinanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.msg, self.code)
+    }
+}
+
+impl Classify for BinanceError {
+    /// Classifies [error codes](https://binance-docs.github.io/apidocs/spot/en/#error-codes).
+    fn class(&self) -> ErrorClass {
+        match self.code {
+            -1003 | -1015 => ErrorClass::RateLimited,
+            -1021 | -1022 | -2014 | -2015 => ErrorClass::Authentication,
+            -1006 | -1007 => ErrorClass::OrderStateUnknown,
+            -1001 => ErrorClass::Retryable,
+            -1199..=-1100 | -2010 | -2011 | -2013 => ErrorClass::InvalidRequest,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
+fn classify_status(status: StatusCode) -> ErrorClass {
+    if status.is_server_error() {
+        // Binance does not know whether the request has been executed on internal errors
+        ErrorClass::OrderStateUnknown
+    } else {
+        ErrorClass::from_status(status)
+    }
 }
 
 fn split_end<'a>(symbol: &'a str, end: &'static str) -> Option<(&'a str, &'a str)> {
@@ -221,3 +300,205 @@ mod __private {
     pub trait Sealed {}
     impl Sealed for Unsupported {}
 }
+
+#[cfg(test)]
+mod tests {
+    use nerf::rest::Codec;
+
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let table = [
+            (-1003, ErrorClass::RateLimited),
+            (-1015, ErrorClass::RateLimited),
+            (-1021, ErrorClass::Authentication),
+            (-1022, ErrorClass::Authentication),
+            (-2014, ErrorClass::Authentication),
+            (-2015, ErrorClass::Authentication),
+            (-1006, ErrorClass::OrderStateUnknown),
+            (-1007, ErrorClass::OrderStateUnknown),
+            (-1001, ErrorClass::Retryable),
+            (-1100, ErrorClass::InvalidRequest),
+            (-1121, ErrorClass::InvalidRequest),
+            (-1199, ErrorClass::InvalidRequest),
+            (-2010, ErrorClass::InvalidRequest),
+            (-2011, ErrorClass::InvalidRequest),
+            (-2013, ErrorClass::InvalidRequest),
+            (-1000, ErrorClass::Other),
+            (-1200, ErrorClass::Other),
+        ];
+        for (code, class) in table {
+            let error = BinanceError {
+                code,
+                msg: String::new(),
+            };
+            assert_eq!(error.class(), class, "{code}");
+        }
+
+        assert_eq!(
+            classify_status(StatusCode::INTERNAL_SERVER_ERROR),
+            ErrorClass::OrderStateUnknown
+        );
+        assert_eq!(
+            classify_status(StatusCode::TOO_MANY_REQUESTS),
+            ErrorClass::RateLimited
+        );
+    }
+
+    #[test]
+    fn encodes_requests() {
+        let req = SpotCodec
+            .encode(&spot::GetApiV3Depth {
+                symbol: "BTCUSDT".to_string(),
+                limit: Some(5),
+            })
+            .unwrap();
+        assert_eq!(
+            req.uri(),
+            "https://api.binance.com/api/v3/depth?symbol=BTCUSDT&limit=5"
+        );
+
+        let req = FuturesCodec
+            .encode(&futures::GetFapiV1Depth {
+                symbol: "BTCUSDT".to_string(),
+                limit: None,
+            })
+            .unwrap();
+        assert_eq!(
+            req.uri(),
+            "https://fapi.binance.com/fapi/v1/depth?symbol=BTCUSDT"
+        );
+
+        // Private requests are sent with every parameter in the query string to be signed
+        let req = SpotCodec
+            .encode(&spot::DeleteApiV3Orders {
+                symbol: "BTCUSDT".to_string(),
+                order_id: Some(42),
+                orig_client_order_id: None,
+            })
+            .unwrap();
+        assert_eq!(req.method(), Method::DELETE);
+        assert_eq!(
+            req.uri(),
+            "https://api.binance.com/api/v3/order?symbol=BTCUSDT&orderId=42"
+        );
+        assert_eq!(
+            req.headers()[CONTENT_TYPE],
+            "application/x-www-form-urlencoded"
+        );
+        assert!(req.body().is_empty());
+    }
+
+    fn response(status: StatusCode, body: &'static str) -> nerf::http::Response<Bytes> {
+        let mut resp = nerf::http::Response::new(Bytes::from(body));
+        *resp.status_mut() = status;
+        resp
+    }
+
+    #[test]
+    fn decodes_errors() {
+        let resp = response(
+            StatusCode::BAD_REQUEST,
+            r#"{"code":-1121,"msg":"Invalid symbol."}"#,
+        );
+        match <BinanceEnvelope as Envelope<spot::GetApiV3Depth>>::decode(resp) {
+            Err(Error::ErrorResponse {
+                status,
+                class,
+                body,
+            }) => {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert_eq!(class, ErrorClass::InvalidRequest);
+                assert_eq!(body.code, -1121);
+                assert_eq!(body.msg, "Invalid symbol.");
+            }
+            x => panic!("unexpected result {x:?}"),
+        }
+
+        let resp = response(StatusCode::BAD_GATEWAY, "<html>502 Bad Gateway</html>");
+        match <BinanceEnvelope as Envelope<spot::GetApiV3Depth>>::decode(resp) {
+            Err(Error::RequestFailed {
+                status,
+                class,
+                payload,
+            }) => {
+                assert_eq!(status, StatusCode::BAD_GATEWAY);
+                assert_eq!(class, ErrorClass::OrderStateUnknown);
+                assert_eq!(payload, "<html>502 Bad Gateway</html>");
+            }
+            x => panic!("unexpected result {x:?}"),
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_usage_headers() {
+        let usages = UsageHeaders.parse_usage(
+            StatusCode::OK,
+            &headers(&[
+                ("X-MBX-USED-WEIGHT-1M", "27"),
+                ("X-MBX-ORDER-COUNT-10S", "3"),
+                ("X-MBX-ORDER-COUNT-1D", "1024"),
+            ]),
+        );
+        assert_eq!(
+            usages,
+            [
+                Usage::Used {
+                    bucket: "weight",
+                    used: 27
+                },
+                Usage::Used {
+                    bucket: "order_10s",
+                    used: 3
+                },
+                Usage::Used {
+                    bucket: "order_1d",
+                    used: 1024
+                },
+            ]
+        );
+
+        // Malformed values are ignored
+        let usages = UsageHeaders.parse_usage(
+            StatusCode::OK,
+            &headers(&[
+                ("X-MBX-USED-WEIGHT-1M", "-1"),
+                ("X-MBX-ORDER-COUNT-10S", "3.5"),
+                ("X-MBX-ORDER-COUNT-1M", ""),
+            ]),
+        );
+        assert_eq!(usages, []);
+    }
+
+    #[test]
+    fn cools_down_on_rate_limit_statuses() {
+        for status in [StatusCode::TOO_MANY_REQUESTS, StatusCode::IM_A_TEAPOT] {
+            let usages = UsageHeaders.parse_usage(
+                status,
+                &headers(&[("Retry-After", "120"), ("X-MBX-USED-WEIGHT-1M", "1201")]),
+            );
+            assert_eq!(
+                usages,
+                [
+                    Usage::CoolDown(Duration::from_secs(120)),
+                    Usage::Used {
+                        bucket: "weight",
+                        used: 1201
+                    },
+                ]
+            );
+
+            // Falls back to a minute without a valid Retry-After
+            let usages = UsageHeaders.parse_usage(status, &headers(&[("Retry-After", "soon")]));
+            assert_eq!(usages, [Usage::CoolDown(Duration::from_secs(60))]);
+        }
+    }
+}
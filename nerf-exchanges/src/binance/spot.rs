@@ -1,25 +1,30 @@
-use std::{collections::HashMap, fmt::Debug, future::Future, pin::Pin};
+use std::collections::HashMap;
 
 use chrono::{serde::ts_milliseconds, DateTime, Utc};
-use nerf::{delete, get, post, tag, Client, HttpRequest, Request};
+use nerf::{
+    delete, get, post, rate_limited,
+    rest::{Codec, RestClient},
+    retry, Bytes, HttpRequest, Request, Validate,
+};
 use rust_decimal::Decimal;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 
-use crate::{
-    common::{
-        self, CommonOps, Disabled, IntoCommon, Orderbook, OrderbookItem, Private, Signer,
-        Unsupported,
-    },
-    KeySecretAuthentication as Authentication,
+use crate::common::{
+    self, CommonOps, Deferred, Disabled, IntoCommon, InvalidParameter, Orderbook, OrderbookItem,
+    Private, Public, Shim, Signer, Unsupported, UnsupportedParameter,
 };
 
-use super::{Error, OrderType, Side, TimeInForce, __private::Sealed, split_end};
+use super::{
+    __private::Sealed, split_end, BinanceEnvelope, BinanceError, Environment, Error, OrderType,
+    Side, TimeInForce,
+};
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.binance.com/api/v3/ticker/bookTicker", response = GetApiV3BookTickerResponse)]
-#[tag(Signer = Disabled)]
+#[get("/api/v3/ticker/bookTicker", response = GetApiV3BookTickerResponse, error = BinanceError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 2)]
+#[retry(idempotent)]
 pub struct GetApiV3BookTicker {
     pub symbols: Option<Vec<String>>,
 }
@@ -39,8 +44,9 @@ pub struct GetApiV3BookTickerResponseItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.binance.com/api/v3/trades", response = GetApiV3TradesResponse)]
-#[tag(Signer = Disabled)]
+#[get("/api/v3/trades", response = GetApiV3TradesResponse, error = BinanceError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetApiV3Trades {
     pub symbol: String,
     /// Default 500, max 1000
@@ -65,8 +71,14 @@ pub struct GetApiV3TradesResponseItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.binance.com/api/v3/depth", response = GetApiV3DepthResponse)]
-#[tag(Signer = Disabled)]
+#[get("/api/v3/depth", response = GetApiV3DepthResponse, error = BinanceError, signer = Disabled, cacheable)]
+#[rate_limited(weight = match self.limit.unwrap_or(100) {
+    0..=100 => 1,
+    101..=500 => 5,
+    501..=1000 => 10,
+    _ => 50,
+})]
+#[retry(idempotent)]
 pub struct GetApiV3Depth {
     pub symbol: String,
     pub limit: Option<u64>,
@@ -101,8 +113,9 @@ impl<'de> Deserialize<'de> for BinanceOrderbookItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.binance.com/api/v3/account", response = GetApiV3AccountResponse)]
-#[tag(Signer = Private)]
+#[get("/api/v3/account", response = GetApiV3AccountResponse, error = BinanceError, signer = Private)]
+#[rate_limited(weight = 10)]
+#[retry(idempotent)]
 pub struct GetApiV3Account {}
 
 #[derive(Clone, Debug, Deserialize)]
@@ -132,8 +145,9 @@ pub struct GetApiV3AccountBalanceItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[post("https://api.binance.com/api/v3/order", response = PostApiV3OrderResponse)]
-#[tag(Signer = Private)]
+#[post("/api/v3/order", response = PostApiV3OrderResponse, error = BinanceError, signer = Private, shim = common::PlaceOrder, builder(validate))]
+#[rate_limited(weight = 1, order_10s = 1, order_1d = 1)]
+#[retry(never)]
 #[serde(rename_all = "camelCase")]
 pub struct PostApiV3Order {
     pub symbol: String,
@@ -176,8 +190,9 @@ pub struct PostApiV3OrderResponse {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[post("https://api.binance.com/api/v3/openOrders", response = GetApiV3OpenOrdersResponse)]
-#[tag(Signer = Private)]
+#[get("/api/v3/openOrders", response = GetApiV3OpenOrdersResponse, error = BinanceError, signer = Private)]
+#[rate_limited(weight = if self.symbol.is_some() { 3 } else { 40 })]
+#[retry(idempotent)]
 #[serde(rename_all = "camelCase")]
 pub struct GetApiV3OpenOrders {
     pub symbol: Option<String>,
@@ -214,8 +229,9 @@ pub struct GetApiV3OpenOrdersResponseItem {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[delete("https://api.binance.com/api/v3/order", response = DeleteApiV3OrdersResponse)]
-#[tag(Signer = Private)]
+#[delete("/api/v3/order", response = DeleteApiV3OrdersResponse, error = BinanceError, signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(never)]
 #[serde(rename_all = "camelCase")]
 pub struct DeleteApiV3Orders {
     pub symbol: String,
@@ -271,35 +287,75 @@ impl From<common::GetAllOrders> for GetApiV3OpenOrders {
     }
 }
 
-impl From<common::PlaceOrder> for PostApiV3Order {
-    fn from(x: common::PlaceOrder) -> Self {
-        PostApiV3Order {
-            symbol: format!("{}{}", x.market.base(), x.market.quote()),
-            side: match x.order.side() {
+impl Validate for PostApiV3Order {
+    type Error = InvalidParameter;
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        use OrderType::*;
+
+        let priced = matches!(
+            self.order_type,
+            Limit | StopLossLimit | TakeProfitLimit | LimitMaker
+        );
+        if priced && self.price.is_none() {
+            return Err(InvalidParameter("price is required for limit orders"));
+        }
+        if matches!(self.order_type, Limit | StopLossLimit | TakeProfitLimit)
+            && self.time_in_force.is_none()
+        {
+            return Err(InvalidParameter(
+                "time in force is required for limit orders",
+            ));
+        }
+        if matches!(
+            self.order_type,
+            StopLoss | StopLossLimit | TakeProfit | TakeProfitLimit
+        ) && self.stop_price.is_none()
+            && self.trailing_delta.is_none()
+        {
+            return Err(InvalidParameter(
+                "stop price or trailing delta is required for stop orders",
+            ));
+        }
+        if self.quantity.is_none() && !(self.order_type == Market && self.quote_order_qty.is_some())
+        {
+            return Err(InvalidParameter(
+                "quantity is required, or quote order quantity for market orders",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Shim<common::PlaceOrder> for PostApiV3Order {
+    type Error = Error<BinanceError>;
+
+    fn shim(x: common::PlaceOrder) -> Result<Self, Self::Error> {
+        let order_type = match x.order {
+            common::Order::Market { .. } => OrderType::Market,
+            common::Order::Limit { .. } => OrderType::Limit,
+            common::Order::StopMarket { .. } | common::Order::StopLimit { .. } => {
+                return Err(UnsupportedParameter("stop orders").into()); // FIXME
+            }
+        };
+        Ok(PostApiV3Order::builder()
+            .symbol(format!("{}{}", x.market.base(), x.market.quote()))
+            .side(match x.order.side() {
                 common::Side::Buy => Side::Buy,
                 common::Side::Sell => Side::Sell,
-            },
-            order_type: match x.order {
-                common::Order::Market { .. } => OrderType::Market,
-                common::Order::Limit { .. } => OrderType::Limit,
-                common::Order::StopMarket { .. } => todo!(), // FIXME
-                common::Order::StopLimit { .. } => todo!(),  // FIXME
-            },
-            time_in_force: x.order.time_in_force().map(|tif| match tif {
+            })
+            .order_type(order_type)
+            .time_in_force(x.order.time_in_force().map(|tif| match tif {
                 common::TimeInForce::GoodTilCancled => TimeInForce::GoodTilCanceled,
                 common::TimeInForce::ImmediateOrCancel => TimeInForce::ImmediateOrCancel,
                 common::TimeInForce::FillOrKill => TimeInForce::FillOrKill,
                 common::TimeInForce::GoodTilCrossing => TimeInForce::GoodTilCrossing,
-            }),
-            quantity: Some(x.order.quantity()),
-            quote_order_qty: None,
-            price: x.order.price(),
-            new_client_order_id: None,
-            stop_price: x.order.stop_price(),
-            trailing_delta: None,
-            iceberg_qty: None,
-            new_order_resp_type: Some("FULL"),
-        }
+            }))
+            .quantity(x.order.quantity())
+            .price(x.order.price())
+            .stop_price(x.order.stop_price())
+            .new_order_resp_type("FULL")
+            .build()?)
     }
 }
 
@@ -356,77 +412,29 @@ impl IntoCommon for GetApiV3DepthResponse {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct BinanceSpotClient<S>(S);
-
-impl<S> BinanceSpotClient<S> {
-    pub fn new(x: S) -> Self {
-        Self(x)
-    }
-
-    pub fn with_auth(self, authentication: Authentication) -> BinanceSpotPrivateClient<S> {
-        BinanceSpotPrivateClient {
-            client: self,
-            authentication,
-        }
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct BinanceSpotPrivateClient<S> {
-    client: BinanceSpotClient<S>,
-    authentication: Authentication,
-}
+/// Encodes spot API requests for [`BinanceSpotClient`], which sends them to the production
+/// unless another base URL is set, e.g. [`Environment::spot_base_url`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SpotCodec;
 
-impl<T, S> Client<T> for BinanceSpotClient<S>
+impl<T> Codec<T> for SpotCodec
 where
-    T: Request + HttpRequest + Sealed + Signer<Signer = Disabled> + Serialize + Debug,
-    T::Response: DeserializeOwned,
+    T: Request + HttpRequest + Sealed + Signer + Serialize,
 {
-    type Service = S;
-
-    type Error = Error;
-
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
-
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.0
-    }
-
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        super::try_into_request(x)
-    }
+    type Error = Error<T::ErrorResponse>;
 
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
-        super::try_from_response::<T>(x)
+    fn encode(&mut self, x: &T) -> Result<nerf::http::Request<Bytes>, Self::Error> {
+        super::encode(&Environment::Production.spot_base_url(), x)
     }
 }
 
-impl<T, S> Client<T> for BinanceSpotPrivateClient<S>
-where
-    T: Request + HttpRequest + Sealed + Signer + Serialize + Debug,
-    T::Response: DeserializeOwned,
-{
-    type Service = S;
-
-    type Error = Error;
-
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
-
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.client.0
-    }
-
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        super::try_into_request_signed(&self.authentication, x)
-    }
+pub type BinanceSpotClient<S> = RestClient<SpotCodec, BinanceEnvelope, Public, S>;
 
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
-        super::try_from_response::<T>(x)
-    }
-}
+/// [`BinanceSpotClient`] accepting private requests, which must be signed by a [`SignLayer`] with
+/// [`HmacSigner`](super::HmacSigner) on the transport.
+///
+/// [`SignLayer`]: nerf::sign::SignLayer
+pub type BinanceSpotPrivateClient<S> = RestClient<SpotCodec, BinanceEnvelope, Deferred, S>;
 
 impl<S> CommonOps for BinanceSpotClient<S> {
     type GetTickersRequest = GetApiV3BookTicker;
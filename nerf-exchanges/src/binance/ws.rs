@@ -0,0 +1,154 @@
+//! Market data streams of Binance, for [`nerf::ws::Connector`].
+//!
+//! Symbols of subscriptions must be lowercase, e.g. `btcusdt`.
+
+use chrono::{serde::ts_milliseconds, DateTime, Utc};
+use nerf::{
+    serde_json::{self, json, Value},
+    subscription,
+    ws::{Message, Protocol},
+};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::BinanceOrderbookItem;
+
+/// The combined stream endpoint of the spot API.
+pub const SPOT_ENDPOINT: &str = "wss://stream.binance.com:9443/stream";
+
+/// The combined stream endpoint of the USD-M futures API.
+pub const FUTURES_ENDPOINT: &str = "wss://fstream.binance.com/stream";
+
+/// The [`Protocol`] of combined streams, which wrap pushed events with their stream name.
+#[derive(Clone, Debug, Default)]
+pub struct CombinedStreams {
+    id: u64,
+}
+
+impl CombinedStreams {
+    fn request(&mut self, method: &str, topic: &str) -> Message {
+        self.id += 1;
+        Message::Text(json!({ "method": method, "params": [topic], "id": self.id }).to_string())
+    }
+}
+
+impl Protocol for CombinedStreams {
+    fn subscribe(&mut self, topic: &str, _params: &Value) -> Message {
+        self.request("SUBSCRIBE", topic)
+    }
+
+    fn unsubscribe(&mut self, topic: &str, _params: &Value) -> Option<Message> {
+        Some(self.request("UNSUBSCRIBE", topic))
+    }
+
+    fn demux(&self, text: &str) -> Option<(String, Value)> {
+        #[derive(Deserialize)]
+        struct Combined {
+            stream: String,
+            data: Value,
+        }
+
+        // Responses of subscriptions are `{"result":null,"id":1}`
+        let Combined { stream, data } = serde_json::from_str(text).ok()?;
+        Some((stream, data))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[subscription("{symbol}@trade", message = TradeEvent)]
+pub struct Trades {
+    pub symbol: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TradeEvent {
+    #[serde(rename = "E", with = "ts_milliseconds")]
+    pub event_time: DateTime<Utc>,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "t")]
+    pub id: i64,
+    #[serde(rename = "p")]
+    pub price: Decimal,
+    #[serde(rename = "q")]
+    pub qty: Decimal,
+    #[serde(rename = "T", with = "ts_milliseconds")]
+    pub time: DateTime<Utc>,
+    #[serde(rename = "m")]
+    pub is_buyer_maker: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[subscription("{symbol}@bookTicker", message = BookTickerEvent)]
+pub struct BookTickers {
+    pub symbol: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BookTickerEvent {
+    #[serde(rename = "u")]
+    pub update_id: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "b")]
+    pub bid_price: Decimal,
+    #[serde(rename = "B")]
+    pub bid_qty: Decimal,
+    #[serde(rename = "a")]
+    pub ask_price: Decimal,
+    #[serde(rename = "A")]
+    pub ask_qty: Decimal,
+}
+
+/// Top `levels` bids and asks of the spot orderbook, pushed every second.
+#[derive(Clone, Debug, Serialize)]
+#[subscription("{symbol}@depth{levels}", message = PartialDepthEvent)]
+pub struct PartialDepth {
+    pub symbol: String,
+    /// 5, 10 or 20
+    pub levels: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PartialDepthEvent {
+    pub last_update_id: u64,
+    pub bids: Vec<BinanceOrderbookItem>,
+    pub asks: Vec<BinanceOrderbookItem>,
+}
+
+#[cfg(test)]
+mod tests {
+    use nerf::ws::Subscription;
+
+    use super::*;
+
+    #[test]
+    fn demuxes_combined_streams() {
+        let trades = Trades {
+            symbol: "btcusdt".to_string(),
+        };
+        let tickers = BookTickers {
+            symbol: "btcusdt".to_string(),
+        };
+
+        let text = r#"{"stream":"btcusdt@trade","data":{"e":"trade","E":1681111200123,"s":"BTCUSDT","t":3052012345,"p":"28429.36000000","q":"0.00350000","b":20584318964,"a":20584318965,"T":1681111200122,"m":true,"M":true}}"#;
+        let (topic, data) = CombinedStreams::default().demux(text).unwrap();
+        assert_eq!(topic, trades.topic());
+        assert_ne!(topic, tickers.topic());
+        let event = TradeEvent::deserialize(&data).unwrap();
+        assert_eq!(event.symbol, "BTCUSDT");
+        assert_eq!(event.id, 3052012345);
+        assert!(event.is_buyer_maker);
+
+        // Streams nobody subscribed to are not routed to any subscription
+        let text = r#"{"stream":"btcusdt@kline_1m","data":{"e":"kline"}}"#;
+        let (topic, _) = CombinedStreams::default().demux(text).unwrap();
+        assert!(topic != trades.topic() && topic != tickers.topic());
+
+        // Responses of subscriptions are not pushed messages
+        assert!(CombinedStreams::default()
+            .demux(r#"{"result":null,"id":1}"#)
+            .is_none());
+    }
+}
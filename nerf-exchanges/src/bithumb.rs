@@ -1,14 +1,20 @@
-use std::{collections::HashMap, fmt::Debug, future::Future, pin::Pin};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+};
 
 use crate::{
-    common::{self, Disabled, Private, Signer, Unsupported},
+    common::{self, Disabled, Private, Public, Shim, Signer, Unsupported, UnsupportedParameter},
     ts_milliseconds_str, Error,
 };
 use __private::Sealed;
 
 use chrono::{DateTime, Utc};
-use http::Method;
-use nerf::{get, post, tag, Client, HttpRequest, Request};
+use nerf::{
+    get, post, rate_limited,
+    rest::{Envelope, Form, RestClient},
+    retry, Bytes, Classify, ErrorClass, Request,
+};
 use rust_decimal::Decimal;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::skip_serializing_none;
@@ -27,24 +33,23 @@ pub enum OrderStatus {
     Cancel,
 }
 
+#[get("https://api.bithumb.com/public/orderbook/{order_currency}_{payment_currency}", response = GetPublicOrderbookResponse, error = BithumbError, signer = Disabled, cacheable)]
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.upbit.com/public/orderbook/{order_currency}_{payment_currency}", response = GetPublicOrderbookResponse)]
-#[tag(Signer = Disabled)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetPublicOrderbook {
-    #[serde(skip)]
     pub order_currency: String,
-    #[serde(skip)]
     pub payment_currency: String,
     pub count: Option<u64>,
 }
 
+#[get("https://api.bithumb.com/public/orderbook/ALL_{payment_currency}", response = GetPublicOrderbookAllResponse, error = BithumbError, signer = Disabled, cacheable)]
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.bithumb.com/public/orderbook/ALL_{payment_currency}", response = GetPublicOrderbookAllResponse)]
-#[tag(Signer = Disabled)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetPublicOrderbookAll {
-    #[serde(skip)]
     pub payment_currency: String,
     pub count: Option<u64>,
 }
@@ -83,8 +88,9 @@ pub struct GetPublicOrderbookAllResponseItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[post("https://api.bithumb.com/info/orders", response = Vec<PostInfoOrdersResponseItem>)]
-#[tag(Signer = Private)]
+#[post("https://api.bithumb.com/info/orders", response = Vec<PostInfoOrdersResponseItem>, error = BithumbError, signer = Private, side_effects = false)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct PostInfoOrders {
     pub order_id: Option<String>,
     #[serde(rename = "type")]
@@ -114,8 +120,9 @@ pub struct PostInfoOrdersResponseItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[post("https://api.bithumb.com/info/order_detail", response = PostInfoOrderDetailResponse)]
-#[tag(Signer = Private)]
+#[post("https://api.bithumb.com/info/order_detail", response = PostInfoOrderDetailResponse, error = BithumbError, signer = Private, side_effects = false)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct PostInfoOrderDetail {
     order_id: String,
     order_currency: String,
@@ -141,10 +148,11 @@ pub struct PostInfoOrderDetailResponse {
     pub contract: Vec<serde_json::Value>,
 }
 
+#[post("https://api.bithumb.com/trade/{place_or_market}", response = PostTradeResponse, error = BithumbError, signer = Private, shim = common::PlaceOrder)]
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[post("https://api.bithumb.com/trade/{place_or_market}", response = PostTradeResponse)]
-#[tag(Signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(never)]
 pub struct PostTrade {
     pub place_or_market: String, // place, market_buy, market_sell
     pub order_currency: String,
@@ -162,8 +170,9 @@ pub struct PostTradeResponse {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[post("https://api.bithumb.com/trade/cancel", response = ())]
-#[tag(Signer = Private)]
+#[post("https://api.bithumb.com/trade/cancel", response = (), error = BithumbError, signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(never)]
 pub struct PostTradeCancel {
     #[serde(rename = "type")]
     pub order_type: OrderType,
@@ -172,53 +181,21 @@ pub struct PostTradeCancel {
     pub payment_currency: String,
 }
 
-#[derive(Clone, Debug)]
-pub struct BithumbClient<S>(S);
+pub type BithumbClient<S> = RestClient<Form, BithumbEnvelope, Public, S>;
 
-impl<S> BithumbClient<S> {
-    pub fn new(x: S) -> Self {
-        Self(x)
-    }
-}
+/// Unwraps `{"status": "0000", "data": ...}` of Bithumb responses.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BithumbEnvelope;
 
-impl<T, S> Client<T> for BithumbClient<S>
+impl<T> Envelope<T> for BithumbEnvelope
 where
-    T: Request + HttpRequest + Sealed + Signer<Signer = Disabled> + Serialize + Debug,
+    T: Request + Sealed,
     T::Response: DeserializeOwned,
+    T::ErrorResponse: DeserializeOwned + Classify,
 {
-    type Service = S;
-
-    type Error = Error;
-
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
-
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.0
-    }
+    type Error = Error<T::ErrorResponse>;
 
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        let query = serde_urlencoded::to_string(&x).map_err(Error::SerializeUrlencodedBody)?;
-        if x.method() == Method::GET {
-            let mut req = hyper::Request::new(hyper::Body::empty());
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = format!("{}?{}", uri, query).parse().unwrap();
-            Ok(req)
-        } else {
-            let mut req = hyper::Request::new(hyper::Body::from(query));
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = uri;
-            Ok(req)
-        }
-    }
-
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
+    fn decode(x: http::Response<Bytes>) -> Result<T::Response, Self::Error> {
         #[derive(Debug, Deserialize)]
         struct BithumbResponse<T> {
             #[allow(dead_code)]
@@ -226,32 +203,15 @@ where
             data: T,
         }
 
-        #[derive(Debug, Deserialize)]
-        struct BithumbError {
-            status: String,
-            message: String,
-        }
-
         if x.status().is_success() {
-            Box::pin(async {
-                let buf = hyper::body::to_bytes(x).await?;
-                let resp: BithumbResponse<T::Response> =
-                    serde_json::from_slice(&buf).map_err(|e| {
-                        Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-                    })?;
-                Ok(resp.data)
-            })
+            let resp: BithumbResponse<T::Response> = crate::deserialize_body(x.body())?;
+            Ok(resp.data)
         } else {
-            Box::pin(async {
-                let buf = hyper::body::to_bytes(x).await?;
-                let resp: BithumbError = serde_json::from_slice(&buf).map_err(|e| {
-                    Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-                })?;
-                Err(Error::RequestFailed {
-                    code: Some(resp.status),
-                    msg: Some(resp.message),
-                })
-            })
+            Err(crate::error_response(
+                x.status(),
+                x.body(),
+                ErrorClass::from_status,
+            ))
         }
     }
 }
@@ -297,12 +257,14 @@ impl From<common::GetOrders> for PostInfoOrders {
     }
 }
 
-impl From<common::PlaceOrder> for PostTrade {
-    fn from(x: common::PlaceOrder) -> Self {
+impl Shim<common::PlaceOrder> for PostTrade {
+    type Error = UnsupportedParameter;
+
+    fn shim(x: common::PlaceOrder) -> Result<Self, Self::Error> {
         let order_currency = x.market.base().to_string();
         let payment_currency = x.market.quote().to_string();
         match x.order {
-            common::Order::Market { side, quantity } => Self {
+            common::Order::Market { side, quantity } => Ok(Self {
                 place_or_market: if side == common::Side::Buy {
                     String::from("market_buy")
                 } else {
@@ -313,13 +275,13 @@ impl From<common::PlaceOrder> for PostTrade {
                 units: quantity,
                 price: None,
                 order_type: None,
-            },
+            }),
             common::Order::Limit {
                 side,
                 quantity,
                 price,
                 time_in_force: _,
-            } => Self {
+            } => Ok(Self {
                 place_or_market: String::from("place"),
                 order_currency,
                 payment_currency,
@@ -330,8 +292,8 @@ impl From<common::PlaceOrder> for PostTrade {
                 } else {
                     OrderType::Ask
                 }),
-            },
-            _ => todo!(),
+            }),
+            _ => Err(UnsupportedParameter("stop orders")),
         }
     }
 }
@@ -406,9 +368,60 @@ impl<S> common::CommonOps for BithumbClient<S> {
     type GetPositionRequest = Unsupported;
 }
 
+/// An error response of Bithumb APIs.
+#[derive(Clone, Debug, Deserialize)]
+pub struct BithumbError {
+    pub status: String,
+    pub message: String,
+}
+
+impl Display for BithumbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (status {})", self.message, self.status)
+    }
+}
+
+impl Classify for BithumbError {
+    fn class(&self) -> ErrorClass {
+        match self.status.as_str() {
+            "5200" | "5300" => ErrorClass::Authentication,
+            "5100" | "5302" | "5500" | "5600" => ErrorClass::InvalidRequest,
+            "5400" => ErrorClass::Retryable,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
 mod __private {
     use crate::common::Unsupported;
 
     pub trait Sealed {}
     impl Sealed for Unsupported {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let table = [
+            ("5200", ErrorClass::Authentication),
+            ("5300", ErrorClass::Authentication),
+            ("5100", ErrorClass::InvalidRequest),
+            ("5302", ErrorClass::InvalidRequest),
+            ("5500", ErrorClass::InvalidRequest),
+            ("5600", ErrorClass::InvalidRequest),
+            ("5400", ErrorClass::Retryable),
+            ("5900", ErrorClass::Other),
+            ("0000", ErrorClass::Other),
+        ];
+        for (status, class) in table {
+            let error = BithumbError {
+                status: status.to_string(),
+                message: String::new(),
+            };
+            assert_eq!(error.class(), class, "{status}");
+        }
+    }
+}
@@ -8,7 +8,10 @@ use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use thiserror::Error;
 
-use nerf::{ClientService, ReadyCall};
+use nerf::{
+    cache::Cache, coalesce::Coalesce, rate_limit::RateLimit, sign::SignatureRequired,
+    ClientService, ReadyCall,
+};
 use tower::buffer::Buffer;
 
 pub use crate::dynamic::*;
@@ -19,6 +22,24 @@ pub trait IntoCommon {
     fn into_common(self) -> Self::Output;
 }
 
+/// Conversion from common requests, which may fail on parameters the exchange does not support.
+///
+/// Requests with `shim = T` of the HTTP macros implement `TryFrom<T>` with this trait.
+pub trait Shim<T>: Sized {
+    type Error;
+    fn shim(x: T) -> Result<Self, Self::Error>;
+}
+
+/// A parameter of a common request is not supported by the exchange.
+#[derive(Error, Debug)]
+#[error("unsupported parameter: {0}")]
+pub struct UnsupportedParameter(pub &'static str);
+
+/// A request violates an invariant of the exchange, e.g. a limit order without a price.
+#[derive(Error, Debug)]
+#[error("invalid parameter: {0}")]
+pub struct InvalidParameter(pub &'static str);
+
 pub type Asset = String;
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -389,10 +410,11 @@ impl Future for Unsupported {
 
 impl nerf::Request for Unsupported {
     type Response = Unsupported;
+    type ErrorResponse = nerf::NoErrorResponse;
 }
 
 impl nerf::HttpRequest for Unsupported {
-    fn uri(&self) -> hyper::http::Uri {
+    fn uri(&self) -> Result<hyper::http::Uri, nerf::Error> {
         match *self {}
     }
 
@@ -429,9 +451,57 @@ impl Signer for Unsupported {
     type Signer = Disabled;
 }
 
+impl nerf::WeightedRateLimit for Unsupported {
+    fn weight(&self) -> u64 {
+        match *self {}
+    }
+}
+
+impl nerf::retry::Retryable for Unsupported {
+    fn retry_behavior(&self) -> nerf::retry::RetryBehavior {
+        match *self {}
+    }
+}
+
 pub struct Disabled;
 pub struct Private;
 
+/// A [`nerf::rest::Signer`] without credentials, which accepts public requests only.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Public;
+
+impl<T> nerf::rest::Signer<T> for Public
+where
+    T: Signer<Signer = Disabled>,
+{
+    type Error = Infallible;
+
+    fn sign(&mut self, _x: &mut http::Request<nerf::Bytes>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// A [`nerf::rest::Signer`] which accepts private requests too, marking them with
+/// [`SignatureRequired`] to be signed by a [`SignLayer`] on the transport.
+///
+/// [`SignLayer`]: nerf::sign::SignLayer
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Deferred;
+
+impl<T> nerf::rest::Signer<T> for Deferred
+where
+    T: Signer,
+{
+    type Error = Infallible;
+
+    fn sign(&mut self, x: &mut http::Request<nerf::Bytes>) -> Result<(), Self::Error> {
+        if <T::Signer as SignerKind>::is_private() {
+            x.extensions_mut().insert(SignatureRequired);
+        }
+        Ok(())
+    }
+}
+
 /// A trait to extract signer kind on run-time.
 pub trait SignerKind {
     fn is_private() -> bool;
@@ -449,6 +519,43 @@ impl SignerKind for Private {
     }
 }
 
+/// A [`Describe`](nerf::observe::Describe) labeling requests with the exchange, their signer kind
+/// and rate limit weight and costs, for [`ObserveLayer`](nerf::observe::ObserveLayer).
+#[cfg(feature = "observe")]
+#[derive(Clone, Copy, Debug)]
+pub struct ExchangeLabels {
+    exchange: &'static str,
+}
+
+#[cfg(feature = "observe")]
+impl ExchangeLabels {
+    /// Labels requests with `exchange`, e.g. `binance`.
+    pub fn new(exchange: &'static str) -> Self {
+        Self { exchange }
+    }
+}
+
+#[cfg(feature = "observe")]
+impl<T> nerf::observe::Describe<T> for ExchangeLabels
+where
+    T: nerf::HttpRequest + Signer + nerf::WeightedRateLimit,
+{
+    fn describe(&self, x: &T) -> nerf::observe::Labels {
+        nerf::observe::Labels {
+            exchange: self.exchange,
+            method: x.method(),
+            endpoint: x.endpoint(),
+            signer: if <T::Signer as SignerKind>::is_private() {
+                "private"
+            } else {
+                "public"
+            },
+            weight: Some(x.weight()),
+            costs: x.costs(),
+        }
+    }
+}
+
 pub trait IntoMarket {
     fn into_market(self) -> Market;
 }
@@ -478,31 +585,40 @@ pub trait CommonOps {
     type GetPositionRequest: TryFrom<GetPosition>;
 }
 
-impl<T> CommonOps for ClientService<T>
-where
-    T: CommonOps,
-{
-    type GetTickersRequest = <T as CommonOps>::GetTickersRequest;
+/// Implements [`CommonOps`] of a service wrapping a [`CommonOps`] implementor `T`, e.g. a
+/// middleware, with the requests of `T`.
+macro_rules! forward_common_ops {
+    ($(#[$attr:meta])* impl<T $(, $param:ident)*> for $ty:ty) => {
+        $(#[$attr])*
+        impl<T $(, $param)*> CommonOps for $ty
+        where
+            T: CommonOps,
+        {
+            type GetTickersRequest = <T as CommonOps>::GetTickersRequest;
 
-    type GetTradesRequest = <T as CommonOps>::GetTradesRequest;
+            type GetTradesRequest = <T as CommonOps>::GetTradesRequest;
 
-    type GetOrderbookRequest = <T as CommonOps>::GetOrderbookRequest;
+            type GetOrderbookRequest = <T as CommonOps>::GetOrderbookRequest;
 
-    type GetOrdersRequest = <T as CommonOps>::GetOrdersRequest;
+            type GetOrdersRequest = <T as CommonOps>::GetOrdersRequest;
 
-    type GetAllOrdersRequest = <T as CommonOps>::GetAllOrdersRequest;
+            type GetAllOrdersRequest = <T as CommonOps>::GetAllOrdersRequest;
 
-    type PlaceOrderRequest = <T as CommonOps>::PlaceOrderRequest;
+            type PlaceOrderRequest = <T as CommonOps>::PlaceOrderRequest;
 
-    type CancelOrderRequest = <T as CommonOps>::CancelOrderRequest;
+            type CancelOrderRequest = <T as CommonOps>::CancelOrderRequest;
 
-    type CancelAllOrdersRequest = <T as CommonOps>::CancelAllOrdersRequest;
+            type CancelAllOrdersRequest = <T as CommonOps>::CancelAllOrdersRequest;
 
-    type GetBalanceRequest = <T as CommonOps>::GetBalanceRequest;
+            type GetBalanceRequest = <T as CommonOps>::GetBalanceRequest;
 
-    type GetPositionRequest = <T as CommonOps>::GetPositionRequest;
+            type GetPositionRequest = <T as CommonOps>::GetPositionRequest;
+        }
+    };
 }
 
+forward_common_ops!(impl<T> for ClientService<T>);
+
 /// Constraints to ensure that a service support [`tower::Service`] for common requests
 pub trait CommonOpsService:
     CommonOps
@@ -744,6 +860,17 @@ where
     type GetPositionRequest = <T as CommonOps>::GetPositionRequest;
 }
 
+forward_common_ops!(impl<T> for RateLimit<T>);
+
+forward_common_ops!(
+    #[cfg(feature = "observe")]
+    impl<T, D> for nerf::observe::Observed<T, D>
+);
+
+forward_common_ops!(impl<T> for Cache<T>);
+
+forward_common_ops!(impl<T> for Coalesce<T>);
+
 macro_rules! impl_unsupported {
     ($name:ident, $($others:ident$(,)?)* ) => {
         impl From<$name> for $crate::common::Unsupported {
@@ -1,14 +1,23 @@
-use std::{collections::HashMap, fmt::Debug, future::Future, pin::Pin};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    time::Duration,
+};
 
 use crate::{
-    common::{self, Disabled, Signer, Unsupported},
+    common::{self, Disabled, Public, Signer, Unsupported},
     Error,
 };
 use __private::Sealed;
 
 use chrono::{serde::ts_milliseconds, DateTime, Utc};
-use http::Method;
-use nerf::{get, tag, Client, HttpRequest, Request};
+use nerf::{
+    get,
+    rate_limit::RetryAfter,
+    rate_limited,
+    rest::{Envelope, Form, RestClient},
+    retry, Bytes, Classify, ErrorClass, Request,
+};
 use rust_decimal::Decimal;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::skip_serializing_none;
@@ -20,10 +29,17 @@ pub enum Side {
     Sell,
 }
 
+/// Returns a [`RetryAfter`] usage parser to cool down rate limiters after Crypto.com responds with
+/// `429 Too Many Requests`.
+pub fn retry_after() -> RetryAfter {
+    RetryAfter::new(Duration::from_secs(1))
+}
+
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.crypto.com/v2/public/get-ticker", response = Vec<GetPublicGetTickerResponseItem>)]
-#[tag(Signer = Disabled)]
+#[get("https://api.crypto.com/v2/public/get-ticker", response = Vec<GetPublicGetTickerResponseItem>, error = CryptocomError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetPublicGetTicker {
     pub instrument_name: Option<String>,
 }
@@ -56,8 +72,9 @@ pub struct GetPublicGetTickerResponseItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.crypto.com/v2/public/get-trades", response = Vec<GetPublicGetTradesResponse>)]
-#[tag(Signer = Disabled)]
+#[get("https://api.crypto.com/v2/public/get-trades", response = Vec<GetPublicGetTradesResponse>, error = CryptocomError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetPublicGetTrades {
     pub instrument_name: String,
 }
@@ -80,8 +97,9 @@ pub struct GetPublicGetTradesResponse {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.crypto.com/v2/public/get-book", response = (GetPublicGetBookResponse,))]
-#[tag(Signer = Disabled)]
+#[get("https://api.crypto.com/v2/public/get-book", response = (GetPublicGetBookResponse,), error = CryptocomError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetPublicGetBook {
     pub instrument_name: String,
     pub depth: Option<u64>,
@@ -111,53 +129,21 @@ impl<'de> Deserialize<'de> for GetPublicGetBookResponseItem {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct CryptocomClient<S>(S);
+pub type CryptocomClient<S> = RestClient<Form, CryptocomEnvelope, Public, S>;
 
-impl<S> CryptocomClient<S> {
-    pub fn new(x: S) -> Self {
-        Self(x)
-    }
-}
+/// Unwraps `{"result": {"data": ...}}` of Crypto.com responses.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CryptocomEnvelope;
 
-impl<T, S> Client<T> for CryptocomClient<S>
+impl<T> Envelope<T> for CryptocomEnvelope
 where
-    T: Request + HttpRequest + Sealed + Signer<Signer = Disabled> + Serialize + Debug,
+    T: Request + Sealed,
     T::Response: DeserializeOwned,
+    T::ErrorResponse: DeserializeOwned + Classify,
 {
-    type Service = S;
-
-    type Error = Error;
-
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
+    type Error = Error<T::ErrorResponse>;
 
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.0
-    }
-
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        let query = serde_urlencoded::to_string(&x).map_err(Error::SerializeUrlencodedBody)?;
-        if x.method() == Method::GET {
-            let mut req = hyper::Request::new(hyper::Body::empty());
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = format!("{}?{}", uri, query).parse().unwrap();
-            Ok(req)
-        } else {
-            let mut req = hyper::Request::new(hyper::Body::from(query));
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = uri;
-            Ok(req)
-        }
-    }
-
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
+    fn decode(x: http::Response<Bytes>) -> Result<T::Response, Self::Error> {
         #[derive(Clone, Debug, Deserialize)]
         struct CryptocomResponse<T> {
             result: CryptocomResponseResult<T>,
@@ -168,32 +154,15 @@ where
             data: T,
         }
 
-        #[derive(Debug, Deserialize)]
-        struct CryptocomError {
-            code: String,
-            message: String,
-        }
-
         if x.status().is_success() {
-            Box::pin(async {
-                let buf = hyper::body::to_bytes(x).await?;
-                let resp: CryptocomResponse<T::Response> =
-                    serde_json::from_slice(&buf).map_err(|e| {
-                        Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-                    })?;
-                Ok(resp.result.data)
-            })
+            let resp: CryptocomResponse<T::Response> = crate::deserialize_body(x.body())?;
+            Ok(resp.result.data)
         } else {
-            Box::pin(async {
-                let buf = hyper::body::to_bytes(x).await?;
-                let resp: CryptocomError = serde_json::from_slice(&buf).map_err(|e| {
-                    Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-                })?;
-                Err(Error::RequestFailed {
-                    code: Some(resp.code),
-                    msg: Some(resp.message),
-                })
-            })
+            Err(crate::error_response(
+                x.status(),
+                x.body(),
+                ErrorClass::from_status,
+            ))
         }
     }
 }
@@ -306,9 +275,63 @@ impl<S> common::CommonOps for CryptocomClient<S> {
     type GetPositionRequest = Unsupported;
 }
 
+/// An error response of Crypto.com APIs.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CryptocomError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl Display for CryptocomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl Classify for CryptocomError {
+    /// Classifies [response codes](https://exchange-docs.crypto.com/spot/index.html#response-and-reason-codes).
+    fn class(&self) -> ErrorClass {
+        match self.code {
+            10006 => ErrorClass::RateLimited,
+            10002 | 10003 | 10007 => ErrorClass::Authentication,
+            10004 | 10008 | 10009 => ErrorClass::InvalidRequest,
+            10001 => ErrorClass::Retryable,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
 mod __private {
     use crate::common::Unsupported;
 
     pub trait Sealed {}
     impl Sealed for Unsupported {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let table = [
+            (10006, ErrorClass::RateLimited),
+            (10002, ErrorClass::Authentication),
+            (10003, ErrorClass::Authentication),
+            (10007, ErrorClass::Authentication),
+            (10004, ErrorClass::InvalidRequest),
+            (10008, ErrorClass::InvalidRequest),
+            (10009, ErrorClass::InvalidRequest),
+            (10001, ErrorClass::Retryable),
+            (10005, ErrorClass::Other),
+            (0, ErrorClass::Other),
+        ];
+        for (code, class) in table {
+            let error = CryptocomError {
+                code,
+                message: String::new(),
+            };
+            assert_eq!(error.class(), class, "{code}");
+        }
+    }
+}
@@ -1,5 +1,7 @@
-use std::fmt::Debug;
+use std::{convert::Infallible, fmt::Debug};
 
+use nerf::{Classify, ErrorClass, NoErrorResponse};
+use serde::de::DeserializeOwned;
 use thiserror::Error;
 
 pub mod binance;
@@ -10,8 +12,9 @@ mod dynamic;
 pub mod okx;
 pub mod upbit;
 
+/// Errors of exchange clients. `E` is the [`nerf::Request::ErrorResponse`] of the request.
 #[derive(Error, Debug)]
-pub enum Error {
+pub enum Error<E = NoErrorResponse> {
     #[error("cannot serialize request body into JSON: {0}")]
     SerializeJsonBody(serde_json::Error),
     #[error("cannot serialize request to URL-encoded parameters: {0}")]
@@ -22,10 +25,18 @@ pub enum Error {
     ConstructHttpRequest(nerf::http::Error),
     #[error("cannot deserialize response into JSON: {0}, payload: {1}")]
     DeserializeJsonBody(serde_json::Error, String),
-    #[error("request to API server returned error, code: {code:?}, message: {msg:?}")]
+    #[error("request to API server returned error, status: {status}, response: {body}")]
+    ErrorResponse {
+        status: http::StatusCode,
+        class: ErrorClass,
+        body: E,
+    },
+    /// The error response cannot be deserialized into the `ErrorResponse` of the request.
+    #[error("request to API server returned error, status: {status}, payload: {payload}")]
     RequestFailed {
-        code: Option<String>,
-        msg: Option<String>,
+        status: http::StatusCode,
+        class: ErrorClass,
+        payload: String,
     },
     #[error(transparent)]
     Hyper(#[from] hyper::Error),
@@ -33,6 +44,10 @@ pub enum Error {
     Jwt(jwt::Error),
     #[error("Unsupported HTTP method {0}")]
     UnsupportedHttpMethod(nerf::http::Method),
+    #[error(transparent)]
+    UnsupportedParameter(#[from] common::UnsupportedParameter),
+    #[error(transparent)]
+    InvalidParameter(#[from] common::InvalidParameter),
     /// A boxed error variant.
     /// [tower::buffer::Buffer] returns a Boxed error type so [Client]s must implement
     /// `From<Box<dyn StdError + Send + Sync + 'static>>` to support buffering.
@@ -43,15 +58,94 @@ pub enum Error {
     Boxed(Box<dyn std::error::Error + Send + Sync + 'static>),
 }
 
-impl From<Box<dyn std::error::Error + Send + Sync + 'static>> for Error {
+impl<E> From<Box<dyn std::error::Error + Send + Sync + 'static>> for Error<E> {
     fn from(x: Box<dyn std::error::Error + Send + Sync + 'static>) -> Self {
         match x.downcast::<hyper::Error>() {
             Ok(x) => Self::Hyper(*x),
-            Err(x) => Self::Boxed(x),
+            Err(x) => match x.downcast::<nerf::Error>() {
+                Ok(x) => Self::from(*x),
+                Err(x) => Self::Boxed(x),
+            },
+        }
+    }
+}
+
+impl<E> From<nerf::Error> for Error<E> {
+    fn from(x: nerf::Error) -> Self {
+        match x {
+            nerf::Error::SerializeRequest(e) => Self::SerializeJsonBody(e),
+            nerf::Error::SerializeUrlencoded(e) => Self::SerializeUrlencodedBody(e),
+            nerf::Error::ConstructRequest(e) => Self::ConstructHttpRequest(e),
+            x => Self::Boxed(Box::new(x)),
         }
     }
 }
 
+impl<E> From<Infallible> for Error<E> {
+    fn from(x: Infallible) -> Self {
+        match x {}
+    }
+}
+
+impl<E> Classify for Error<E> {
+    fn class(&self) -> ErrorClass {
+        match self {
+            Self::SerializeJsonBody(_)
+            | Self::SerializeUrlencodedBody(_)
+            | Self::SerializeUrlencodedBodyUpbit(_)
+            | Self::ConstructHttpRequest(_)
+            | Self::UnsupportedHttpMethod(_)
+            | Self::UnsupportedParameter(_)
+            | Self::InvalidParameter(_) => ErrorClass::InvalidRequest,
+            Self::Jwt(_) => ErrorClass::Authentication,
+            Self::ErrorResponse { class, .. } | Self::RequestFailed { class, .. } => *class,
+            // Nothing has been sent if the connection is not established
+            Self::Hyper(e) if e.is_connect() => ErrorClass::Retryable,
+            Self::Hyper(_) => ErrorClass::OrderStateUnknown,
+            Self::DeserializeJsonBody(..) | Self::Boxed(_) => ErrorClass::Other,
+        }
+    }
+}
+
+/// Deserializes an error response into [`Error::ErrorResponse`], or [`Error::RequestFailed`] if the
+/// payload does not match `E`.
+///
+/// The error is classified by the body first, and then by `classify_status` if the body is
+/// missing or classified as [`ErrorClass::Other`].
+fn error_response<E>(
+    status: http::StatusCode,
+    buf: &[u8],
+    classify_status: fn(http::StatusCode) -> ErrorClass,
+) -> Error<E>
+where
+    E: DeserializeOwned + Classify,
+{
+    match serde_json::from_slice::<E>(buf) {
+        Ok(body) => Error::ErrorResponse {
+            status,
+            class: match body.class() {
+                ErrorClass::Other => classify_status(status),
+                class => class,
+            },
+            body,
+        },
+        Err(_) => Error::RequestFailed {
+            status,
+            class: classify_status(status),
+            payload: String::from_utf8_lossy(buf).to_string(),
+        },
+    }
+}
+
+/// Deserializes a successful response body, keeping the payload on failures.
+fn deserialize_body<T, E>(buf: &[u8]) -> Result<T, Error<E>>
+where
+    T: DeserializeOwned,
+{
+    serde_json::from_slice(buf)
+        .map_err(|e| Error::DeserializeJsonBody(e, String::from_utf8_lossy(buf).to_string()))
+}
+
 #[derive(Clone)]
 pub struct KeySecretAuthentication {
     key: String,
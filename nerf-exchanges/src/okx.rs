@@ -1,15 +1,27 @@
-use std::{collections::HashMap, fmt::Debug, future::Future, pin::Pin};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    time::Duration,
+};
 
 use crate::{
-    common::{self, Disabled, Private, Signer, SignerKind, Unsupported},
+    common::{self, Deferred, Disabled, Private, Public, Signer, Unsupported},
     ts_milliseconds_str, Error,
 };
 use __private::Sealed;
 
 use chrono::{DateTime, Utc};
 use hmac::{Hmac, Mac};
-use http::Method;
-use nerf::{get, tag, Client, HttpRequest, Request};
+use nerf::{
+    base_url::BaseUrl,
+    get,
+    rate_limit::RetryAfter,
+    rate_limited,
+    rest::{Envelope, Form, RestClient},
+    retry,
+    sign::Sign,
+    Bytes, Classify, ErrorClass, Request,
+};
 use rust_decimal::Decimal;
 use serde::{
     de::{DeserializeOwned, IntoDeserializer},
@@ -20,10 +32,17 @@ use sha2::Sha256;
 
 use base64::prelude::*;
 
+/// Returns a [`RetryAfter`] usage parser to cool down rate limiters after OKX responds with
+/// `429 Too Many Requests`.
+pub fn retry_after() -> RetryAfter {
+    RetryAfter::new(Duration::from_secs(2))
+}
+
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://aws.okx.com/api/v5/market/ticker", response = (GetV5MarketTickerResponseItem,))]
-#[tag(Signer = Disabled)]
+#[get("https://aws.okx.com/api/v5/market/ticker", response = (GetV5MarketTickerResponseItem,), error = OkxError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 #[serde(rename_all = "camelCase")]
 pub struct GetV5MarketTicker {
     pub inst_id: String,
@@ -40,8 +59,9 @@ pub enum InstType {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://aws.okx.com/api/v5/market/tickers", response = Vec<GetV5MarketTickerResponseItem>)]
-#[tag(Signer = Disabled)]
+#[get("https://aws.okx.com/api/v5/market/tickers", response = Vec<GetV5MarketTickerResponseItem>, error = OkxError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 #[serde(rename_all = "camelCase")]
 pub struct GetV5MarketTickers {
     pub inst_type: InstType,
@@ -81,8 +101,9 @@ pub struct GetV5MarketTickerResponseItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://aws.okx.com/api/v5/market/books", response = (GetV5MarketBooksResponse,))]
-#[tag(Signer = Disabled)]
+#[get("https://aws.okx.com/api/v5/market/books", response = (GetV5MarketBooksResponse,), error = OkxError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 #[serde(rename_all = "camelCase")]
 pub struct GetV5MarketBooks {
     inst_id: String,
@@ -125,8 +146,9 @@ impl<'de> Deserialize<'de> for GetV5MarketBooksResponseItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://aws.okx.com/api/v5/account/balance", response = (GetV5AccountBalanceResponse,))]
-#[tag(Signer = Private)]
+#[get("https://aws.okx.com/api/v5/account/balance", response = (GetV5AccountBalanceResponse,), error = OkxError, signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetV5AccountBalance {
     pub ccy: Option<String>,
 }
@@ -203,22 +225,32 @@ pub struct GetV5AccountBalanceResponseDetails {
     pub spot_in_use_amt: Decimal,
 }
 
-#[derive(Clone, Debug)]
-pub struct OkxClient<S>(S);
+pub type OkxClient<S> = RestClient<Form, OkxEnvelope, Public, S>;
 
-impl<S> OkxClient<S> {
-    pub fn new(x: S) -> Self {
-        Self(x)
-    }
+/// OKX domains to send requests to, applied with [`RestClient::with_base_url`]. Endpoints are
+/// declared on [`Aws`](Environment::Aws), which is used when no base URL is set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Environment {
+    Production,
+    #[default]
+    Aws,
+}
 
-    pub fn with_auth(self, authentication: Authentication) -> OkxPrivateClient<S> {
-        OkxPrivateClient {
-            client: self,
-            authentication,
+impl Environment {
+    pub fn base_url(self) -> BaseUrl {
+        match self {
+            Self::Production => BaseUrl::from_static("https://www.okx.com"),
+            Self::Aws => BaseUrl::from_static("https://aws.okx.com"),
         }
     }
 }
 
+/// [`OkxClient`] accepting private requests, which must be signed by a [`SignLayer`] with
+/// [`Authentication`] on the transport.
+///
+/// [`SignLayer`]: nerf::sign::SignLayer
+pub type OkxPrivateClient<S> = RestClient<Form, OkxEnvelope, Deferred, S>;
+
 pub struct Authentication {
     key: String,
     secret: String,
@@ -235,160 +267,74 @@ impl Authentication {
     }
 }
 
-pub struct OkxPrivateClient<S> {
-    client: OkxClient<S>,
-    authentication: Authentication,
-}
-
-fn try_from_response<T>(
-    x: hyper::Response<hyper::Body>,
-) -> Pin<Box<dyn Future<Output = Result<T::Response, Error>> + Send + Sync + 'static>>
-where
-    T: Request,
-    T::Response: DeserializeOwned,
-{
-    #[derive(Debug, Deserialize)]
-    struct OkxResponse<T> {
-        data: T,
-    }
-
-    #[derive(Debug, Deserialize)]
-    struct OkxError {
-        code: String,
-        msg: String,
-    }
-
-    if x.status().is_success() {
-        Box::pin(async {
-            let buf = hyper::body::to_bytes(x).await?;
-            let resp: OkxResponse<T::Response> = serde_json::from_slice(&buf).map_err(|e| {
-                Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-            })?;
-            Ok(resp.data)
-        })
-    } else {
-        Box::pin(async {
-            let buf = hyper::body::to_bytes(x).await?;
-            let resp: OkxError = serde_json::from_slice(&buf).map_err(|e| {
-                Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-            })?;
-            Err(Error::RequestFailed {
-                code: Some(resp.code),
-                msg: Some(resp.msg),
-            })
-        })
+impl Sign for Authentication {
+    type Error = nerf::Error;
+
+    fn sign(&self, x: &mut http::Request<Bytes>) -> Result<(), Self::Error> {
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        let payload = format!(
+            "{}{}{}{}",
+            timestamp,
+            x.method(),
+            x.uri().path_and_query().unwrap(), // Schema always exists
+            String::from_utf8_lossy(x.body()),
+        );
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+            .expect("HMAC can take key of any size");
+        mac.update(payload.as_bytes());
+        let result = mac.finalize();
+
+        // Credentials may contain bytes which are not allowed in headers
+        let header = |x: &str| {
+            http::HeaderValue::from_str(x).map_err(|e| nerf::Error::ConstructRequest(e.into()))
+        };
+        let headers = x.headers_mut();
+        headers.insert("OK-ACCESS-KEY", header(&self.key)?);
+        headers.insert("OK-ACCESS-TIMESTAMP", header(&timestamp)?);
+        headers.insert("OK-ACCESS-PASSPHRASE", header(&self.passphrase)?);
+        headers.insert(
+            "OK-ACCESS-SIGN",
+            header(&BASE64_STANDARD.encode(result.into_bytes()))?,
+        );
+        Ok(())
     }
 }
 
-impl<T, S> Client<T> for OkxClient<S>
-where
-    T: Request + HttpRequest + Sealed + Signer<Signer = Disabled> + Serialize + Debug,
-    T::Response: DeserializeOwned,
-{
-    type Service = S;
-
-    type Error = Error;
-
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
-
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.0
-    }
-
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        let query = serde_urlencoded::to_string(&x).map_err(Error::SerializeUrlencodedBody)?;
-        if x.method() == Method::GET {
-            let mut req = hyper::Request::new(hyper::Body::empty());
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = format!("{}?{}", uri, query).parse().unwrap();
-            Ok(req)
-        } else {
-            let mut req = hyper::Request::new(hyper::Body::from(query));
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = uri;
-            Ok(req)
-        }
-    }
+/// Unwraps `{"code": "0", "data": ...}` of OKX responses.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OkxEnvelope;
 
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
-        try_from_response::<T>(x)
-    }
-}
-
-impl<T, S> Client<T> for OkxPrivateClient<S>
+impl<T> Envelope<T> for OkxEnvelope
 where
-    T: Request + HttpRequest + Sealed + Signer + Serialize + Debug,
+    T: Request + Sealed,
     T::Response: DeserializeOwned,
+    T::ErrorResponse: DeserializeOwned + Classify,
 {
-    type Service = S;
-
-    type Error = Error;
+    type Error = Error<T::ErrorResponse>;
 
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
-
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.client.0
-    }
+    fn decode(x: http::Response<Bytes>) -> Result<T::Response, Self::Error> {
+        #[derive(Debug, Deserialize)]
+        struct OkxResponse<T> {
+            data: T,
+        }
 
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        let query = serde_urlencoded::to_string(&x).map_err(Error::SerializeUrlencodedBody)?;
-        let mut req = if x.method() == Method::GET {
-            let mut req = hyper::Request::new(hyper::Body::empty());
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = format!("{}?{}", uri, query).parse().unwrap();
-            req
-        } else {
-            let mut req = hyper::Request::new(hyper::Body::from(query));
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = uri;
-            req
-        };
+        #[derive(Debug, Deserialize)]
+        struct OkxResponseCode {
+            code: String,
+        }
 
-        if <T::Signer as SignerKind>::is_private() {
-            req.headers_mut()
-                .insert("OK-ACCESS-KEY", self.authentication.key.parse().unwrap());
-            let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
-            req.headers_mut()
-                .insert("OK-ACCESS-TIMESTAMP", timestamp.parse().unwrap());
-            req.headers_mut().insert(
-                "OK-ACCESS-PASSPHRASE",
-                self.authentication.passphrase.parse().unwrap(),
-            );
-            let payload = dbg!(format!(
-                "{}{}{}",
-                timestamp,
-                x.method(),
-                x.uri().path_and_query().unwrap() // Schema always exists
+        // OKX responds with `200 OK` on failures of requests on multiple orders
+        if !x.status().is_success()
+            || matches!(serde_json::from_slice::<OkxResponseCode>(x.body()), Ok(x) if x.code != "0")
+        {
+            return Err(crate::error_response(
+                x.status(),
+                x.body(),
+                ErrorClass::from_status,
             ));
-            let mut mac = Hmac::<Sha256>::new_from_slice(self.authentication.secret.as_bytes())
-                .expect("HMAC can take key of any size");
-            mac.update(payload.as_bytes());
-            let result = mac.finalize();
-            req.headers_mut().insert(
-                "OK-ACCESS-SIGN",
-                BASE64_STANDARD.encode(result.into_bytes()).parse().unwrap(),
-            );
         }
-
-        Ok(req)
-    }
-
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
-        try_from_response::<T>(x)
+        let resp: OkxResponse<T::Response> = crate::deserialize_body(x.body())?;
+        Ok(resp.data)
     }
 }
 
@@ -545,9 +491,136 @@ impl<S> tower::Service<Unsupported> for OkxPrivateClient<S> {
         match req {}
     }
 }
+/// An error response of OKX APIs.
+///
+/// Requests on multiple orders report the error of each order in `data`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OkxError {
+    pub code: String,
+    pub msg: String,
+    #[serde(default)]
+    pub data: Vec<OkxErrorData>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OkxErrorData {
+    pub s_code: String,
+    pub s_msg: String,
+}
+
+impl Display for OkxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.msg, self.code)?;
+        for x in &self.data {
+            write!(f, ", {} (code {})", x.s_msg, x.s_code)?;
+        }
+        Ok(())
+    }
+}
+
+impl Classify for OkxError {
+    /// Classifies [error codes](https://www.okx.com/docs-v5/en/#error-code).
+    ///
+    /// Failures of requests on multiple orders have a generic `code`(e.g. `1`), so they are
+    /// classified by the first `sCode` of `data` which is not [`ErrorClass::Other`].
+    fn class(&self) -> ErrorClass {
+        match classify_code(&self.code) {
+            ErrorClass::Other => self
+                .data
+                .iter()
+                .map(|x| classify_code(&x.s_code))
+                .find(|&x| x != ErrorClass::Other)
+                .unwrap_or(ErrorClass::Other),
+            class => class,
+        }
+    }
+}
+
+fn classify_code(code: &str) -> ErrorClass {
+    match code.parse::<u32>() {
+        Ok(50011 | 50061) => ErrorClass::RateLimited,
+        Ok(50001 | 50013) => ErrorClass::Retryable,
+        Ok(50004) => ErrorClass::OrderStateUnknown,
+        Ok(50100..=50119) => ErrorClass::Authentication,
+        Ok(51000..=51999) => ErrorClass::InvalidRequest,
+        _ => ErrorClass::Other,
+    }
+}
+
 mod __private {
     use crate::common::Unsupported;
 
     pub trait Sealed {}
     impl Sealed for Unsupported {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let table = [
+            ("50011", ErrorClass::RateLimited),
+            ("50061", ErrorClass::RateLimited),
+            ("50001", ErrorClass::Retryable),
+            ("50013", ErrorClass::Retryable),
+            ("50004", ErrorClass::OrderStateUnknown),
+            ("50100", ErrorClass::Authentication),
+            ("50113", ErrorClass::Authentication),
+            ("50119", ErrorClass::Authentication),
+            ("51000", ErrorClass::InvalidRequest),
+            ("51008", ErrorClass::InvalidRequest),
+            ("51999", ErrorClass::InvalidRequest),
+            ("1", ErrorClass::Other),
+            ("50120", ErrorClass::Other),
+            ("", ErrorClass::Other),
+        ];
+        for (code, class) in table {
+            let error = OkxError {
+                code: code.to_string(),
+                msg: String::new(),
+                data: Vec::new(),
+            };
+            assert_eq!(error.class(), class, "{code:?}");
+        }
+    }
+
+    #[test]
+    fn decodes_errors_of_ok_responses() {
+        let body = r#"{"code":"1","msg":"All operations failed","data":[{"sCode":"51008","sMsg":"Order failed. Insufficient balance"}]}"#;
+        let resp = http::Response::new(Bytes::from_static(body.as_bytes()));
+        match <OkxEnvelope as Envelope<GetV5MarketBooks>>::decode(resp) {
+            Err(Error::ErrorResponse {
+                status,
+                class,
+                body,
+            }) => {
+                assert_eq!(status, http::StatusCode::OK);
+                assert_eq!(class, ErrorClass::InvalidRequest);
+                assert_eq!(body.code, "1");
+                assert_eq!(body.data[0].s_code, "51008");
+            }
+            x => panic!("unexpected result {x:?}"),
+        }
+
+        let body = r#"{"code":"0","msg":"","data":[{"asks":[],"bids":[],"ts":"1597026383085"}]}"#;
+        let resp = http::Response::new(Bytes::from_static(body.as_bytes()));
+        assert!(<OkxEnvelope as Envelope<GetV5MarketBooks>>::decode(resp).is_ok());
+    }
+
+    #[test]
+    fn rejects_credentials_invalid_in_headers() {
+        let authentication = Authentication::new(
+            "key\n".to_string(),
+            "secret".to_string(),
+            "passphrase".to_string(),
+        );
+        let mut req = http::Request::new(Bytes::new());
+        assert!(matches!(
+            authentication.sign(&mut req),
+            Err(nerf::Error::ConstructRequest(_))
+        ));
+    }
+}
@@ -1,30 +1,117 @@
 use crate::{
-    common::{self, CommonOps, Disabled, IntoCommon, Private, Signer, SignerKind, Unsupported},
+    common::{
+        self, CommonOps, Disabled, IntoCommon, Private, Public, Shim, Signer, SignerKind,
+        Unsupported, UnsupportedParameter,
+    },
     Error, KeySecretAuthentication,
 };
 
 use chrono::{serde::ts_milliseconds, DateTime, Utc};
 use hmac::{Hmac, Mac};
-use http::{Method, StatusCode, Uri};
+use http::{
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    HeaderMap, Method, StatusCode,
+};
 use jwt::SignWithKey;
-use nerf::{delete, get, post, tag, Client, HttpRequest, Request};
+use nerf::{
+    base_url::BaseUrl,
+    delete, get,
+    paginate::Paginated,
+    post,
+    rate_limit::{Bucket, ParseUsage, RateLimiter, RetryAfter, Usage},
+    rate_limited,
+    rest::{Codec, Envelope, RestClient},
+    retry, Bytes, Classify, ErrorClass, HttpRequest, Request,
+};
 use rust_decimal::Decimal;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use sha2::{Digest, Sha256, Sha512};
 use uuid::Uuid;
 
-use std::convert::Infallible;
-use std::fmt::{Debug, Write};
-use std::future::Future;
-use std::pin::Pin;
-use std::str::FromStr;
+use std::fmt::{Display, Write};
+use std::time::Duration;
 
 use self::__private::Sealed;
 
-impl From<Infallible> for Error {
-    fn from(x: Infallible) -> Self {
-        match x {}
+/// Returns a [`RateLimiter`] for the quotation API(10 per second, 600 per minute).
+pub fn quotation_rate_limiter() -> RateLimiter {
+    RateLimiter::new([
+        Bucket::new("second", 10, Duration::from_secs(1)),
+        Bucket::new("minute", 600, Duration::from_secs(60)),
+    ])
+}
+
+/// Returns a [`RateLimiter`] for the exchange API.
+///
+/// Order placement is limited to 8 per second, 200 per minute which is the tightest limit among
+/// the exchange API groups, so this limiter uses it for every exchange API request.
+pub fn exchange_rate_limiter() -> RateLimiter {
+    RateLimiter::new([
+        Bucket::new("second", 8, Duration::from_secs(1)),
+        Bucket::new("minute", 200, Duration::from_secs(60)),
+    ])
+}
+
+/// An error response of Upbit APIs.
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpbitError {
+    pub error: UpbitErrorDetail,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpbitErrorDetail {
+    pub name: String,
+    pub message: String,
+}
+
+impl Display for UpbitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.error.message, self.error.name)
+    }
+}
+
+impl Classify for UpbitError {
+    fn class(&self) -> ErrorClass {
+        match self.error.name.as_str() {
+            "invalid_query_payload"
+            | "jwt_verification"
+            | "expired_access_key"
+            | "nonce_used"
+            | "no_authorization_i_p"
+            | "out_of_scope" => ErrorClass::Authentication,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
+/// Parses the `Remaining-Req` header of Upbit responses to feed it back into [`RateLimiter`]s.
+///
+/// The header looks like `group=default; min=1800; sec=29`, whose `sec` and `min` are reported as
+/// the remaining weights of the `second` and `minute` buckets. `418 I'm a teapot` and
+/// `429 Too Many Requests` responses trigger a cool-down.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RemainingReq;
+
+impl ParseUsage for RemainingReq {
+    fn parse_usage(&self, status: StatusCode, headers: &HeaderMap) -> Vec<Usage> {
+        let mut usages = RetryAfter::new(Duration::from_secs(1)).parse_usage(status, headers);
+        let header = headers.get("remaining-req").and_then(|x| x.to_str().ok());
+        for (key, value) in header
+            .into_iter()
+            .flat_map(|x| x.split(';'))
+            .filter_map(|x| x.split_once('='))
+        {
+            let bucket = match key.trim() {
+                "sec" => "second",
+                "min" => "minute",
+                _ => continue,
+            };
+            if let Ok(remaining) = value.trim().parse() {
+                usages.push(Usage::Remaining { bucket, remaining });
+            }
+        }
+        usages
     }
 }
 
@@ -55,18 +142,20 @@ pub enum OrderState {
     Cancel,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum SortOrders {
     #[serde(rename = "asc")]
     Ascending,
     #[serde(rename = "desc")]
+    #[default]
     Descending,
 }
 
 #[skip_serializing_none]
 #[derive(Clone, Debug)]
-#[get("https://api.upbit.com/v1/orderbook", response = GetV1OrderbookResponse)]
-#[tag(Signer = Disabled)]
+#[get("/v1/orderbook", response = GetV1OrderbookResponse, error = UpbitError, signer = Disabled, cacheable)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetV1Orderbook {
     pub markets: Vec<String>,
 }
@@ -110,8 +199,9 @@ pub struct OrderbookUnit {
 }
 
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.upbit.com/v1/accounts", response = GetV1AccountsResponse)]
-#[tag(Signer = Private)]
+#[get("/v1/accounts", response = GetV1AccountsResponse, error = UpbitError, signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetV1Accounts;
 
 #[derive(Clone, Debug, Deserialize)]
@@ -129,8 +219,9 @@ pub struct GetV1AccountsResponseItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[post("https://api.upbit.com/v1/orders", response = PostV1OrdersResponse)]
-#[tag(Signer = Private)]
+#[post("/v1/orders", response = PostV1OrdersResponse, error = UpbitError, signer = Private, shim = common::PlaceOrder)]
+#[rate_limited(weight = 1)]
+#[retry(never)]
 pub struct PostV1Orders {
     pub market: String,
     pub side: Side,
@@ -162,19 +253,43 @@ pub struct PostV1OrdersResponse {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.upbit.com/v1/orders", response = GetV1OrdersResponse)]
-#[tag(Signer = Private)]
+#[get("/v1/orders", response = GetV1OrdersResponse, error = UpbitError, signer = Private, builder)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetV1Orders {
     pub market: String,
+    #[optional]
     pub uuids: Vec<Uuid>,
+    #[optional]
     pub identifiers: Vec<String>,
     pub state: Option<OrderState>,
     pub states: Option<Vec<OrderState>>,
     pub page: Option<Decimal>,
     pub limit: Option<Decimal>,
+    #[optional]
     pub order_by: SortOrders,
 }
 
+impl Paginated for GetV1Orders {
+    type Item = GetV1OrdersResponseItem;
+
+    /// Requests the next `page` until a page has less than `limit`(default 100) orders.
+    fn next_page(&self, response: &Self::Response) -> Option<Self> {
+        let limit = self.limit.unwrap_or(Decimal::ONE_HUNDRED);
+        if Decimal::from(response.0.len()) < limit {
+            return None;
+        }
+        Some(Self {
+            page: Some(self.page.unwrap_or(Decimal::ONE) + Decimal::ONE),
+            ..self.clone()
+        })
+    }
+
+    fn items(response: Self::Response) -> Vec<Self::Item> {
+        response.0
+    }
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct GetV1OrdersResponse(pub Vec<GetV1OrdersResponseItem>);
 
@@ -199,8 +314,9 @@ pub struct GetV1OrdersResponseItem {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[get("https://api.upbit.com/v1/order", response = GetV1OrderResponse)]
-#[tag(Signer = Private)]
+#[get("/v1/order", response = GetV1OrderResponse, error = UpbitError, signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(idempotent)]
 pub struct GetV1Order {
     pub uuid: Option<Uuid>,
     pub identifier: Option<Uuid>,
@@ -239,8 +355,9 @@ pub struct GetV1OrderTrade {
 
 #[skip_serializing_none]
 #[derive(Clone, Debug, Serialize)]
-#[delete("https://api.upbit.com/v1/order", response = DeleteV1OrderResponse)]
-#[tag(Signer = Private)]
+#[delete("/v1/order", response = DeleteV1OrderResponse, error = UpbitError, signer = Private)]
+#[rate_limited(weight = 1)]
+#[retry(never)]
 pub struct DeleteV1Order {
     pub uuid: Option<Uuid>,
     pub identifier: Option<String>,
@@ -265,239 +382,148 @@ pub struct DeleteV1OrderResponse {
     pub trades_count: u64,
 }
 
+/// The parameters covered by the query hash of [`JwtSigner`], which are in the body of non-`GET`
+/// requests.
 #[derive(Clone, Debug)]
-pub struct UpbitClient<S>(S);
-
-impl<S> UpbitClient<S> {
-    pub fn new(x: S) -> Self {
-        Self(x)
-    }
-
-    pub fn with_auth(self, authentication: KeySecretAuthentication) -> UpbitPrivateClient<S> {
-        UpbitPrivateClient {
-            client: self,
-            authentication,
-        }
-    }
-}
+struct QueryParams(String);
 
-#[derive(Clone, Debug)]
-pub struct UpbitPrivateClient<S> {
-    client: UpbitClient<S>,
-    authentication: KeySecretAuthentication,
-}
+/// Encodes Upbit requests, into the query string for `GET` requests or into the JSON body for
+/// others. Requests are sent to `https://api.upbit.com` unless another base URL is set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UpbitCodec;
 
-impl<T, S> Client<T> for UpbitClient<S>
+impl<T> Codec<T> for UpbitCodec
 where
-    T: Request + HttpRequest + Sealed + Signer<Signer = Disabled> + Serialize + Debug,
-    T::Response: DeserializeOwned,
+    T: Request + HttpRequest + Sealed + Serialize,
 {
-    type Service = S;
-
-    type Error = Error;
+    type Error = Error<T::ErrorResponse>;
 
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
-
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.0
-    }
-
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        let query = serde_urlencoded_upbit::to_string(&x)
+    fn encode(&mut self, x: &T) -> Result<http::Request<Bytes>, Self::Error> {
+        let base_url = BaseUrl::from_static("https://api.upbit.com");
+        let params = serde_urlencoded_upbit::to_string(x)
             .map_err(Error::SerializeUrlencodedBodyUpbit)?
             .replace("%5B", "[")
             .replace("%5D", "]");
-        if x.method() == Method::GET {
-            let mut req = hyper::Request::new(hyper::Body::empty());
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = Uri::from_str(&format!("{}?{}", uri, query)).unwrap();
-            Ok(req)
+        // The hash covers the parameters already in the URI too
+        let uri = x.uri_with_query(&params)?;
+        let params = QueryParams(uri.query().unwrap_or_default().to_string());
+        let builder = http::Request::builder()
+            .method(x.method())
+            .header(ACCEPT, "application/json");
+        let mut req = if x.method() == Method::GET {
+            builder.uri(base_url.join(&uri)?).body(Bytes::new())
         } else {
-            let mut req = hyper::Request::new(hyper::Body::from(query));
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            *req.uri_mut() = uri;
-            Ok(req)
+            let body = serde_json::to_vec(x).map_err(Error::SerializeJsonBody)?;
+            builder
+                .uri(base_url.join(&x.uri()?)?)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.into())
         }
+        .map_err(Error::ConstructHttpRequest)?;
+        req.extensions_mut().insert(params);
+        req.headers_mut().extend(x.headers()?);
+        Ok(req)
     }
+}
 
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
-        if x.status() == StatusCode::OK {
-            Box::pin(async {
-                let buf = hyper::body::to_bytes(x).await?;
-                let resp = serde_json::from_slice(&buf).map_err(|e| {
-                    Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-                })?;
-
-                Ok(resp)
-            })
-        } else {
-            #[derive(Deserialize)]
-            struct UpbitError {
-                error: UpbitErrorInner,
-            }
-
-            #[derive(Deserialize)]
-            struct UpbitErrorInner {
-                name: String,
-                message: String,
-            }
+/// Signs private Upbit requests with a JWT in the `Authorization` header.
+#[derive(Clone, Debug)]
+pub struct JwtSigner {
+    authentication: KeySecretAuthentication,
+}
 
-            Box::pin(async {
-                let s = hyper::body::to_bytes(x).await.map_err(Error::Hyper)?;
-                match serde_json::from_slice::<UpbitError>(&s) {
-                    Ok(error) => Err(Error::RequestFailed {
-                        code: Some(error.error.name),
-                        msg: Some(error.error.message),
-                    }),
-                    Err(_) => Err(Error::RequestFailed {
-                        code: None,
-                        msg: Some(String::from_utf8_lossy(&s).to_string()),
-                    }),
-                }
-            })
-        }
+impl JwtSigner {
+    pub fn new(authentication: KeySecretAuthentication) -> Self {
+        Self { authentication }
     }
 }
 
-impl<T, S> Client<T> for UpbitPrivateClient<S>
+impl<T> nerf::rest::Signer<T> for JwtSigner
 where
-    T: Request + HttpRequest + Sealed + Signer + Serialize + Debug,
-    T::Response: DeserializeOwned,
-    T::Signer: SignerKind,
+    T: Request + Signer,
 {
-    type Service = S;
+    type Error = Error<T::ErrorResponse>;
 
-    type Error = Error;
+    fn sign(&mut self, x: &mut http::Request<Bytes>) -> Result<(), Self::Error> {
+        #[skip_serializing_none]
+        #[derive(Serialize)]
+        struct AuthPayload {
+            access_key: String,
+            nonce: Uuid,
+            query_hash: Option<String>,
+            query_hash_alg: Option<&'static str>,
+        }
 
-    type TryFromResponseFuture =
-        Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
+        if !<T::Signer as SignerKind>::is_private() {
+            return Ok(());
+        }
 
-    fn service(&mut self) -> &mut Self::Service {
-        &mut self.client.0
-    }
+        let key: Hmac<Sha256> = Hmac::new_from_slice(self.authentication.secret().as_bytes())
+            .expect("upbit: cannot initialize authentication");
 
-    fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        let query =
-            serde_urlencoded_upbit::to_string(&x).map_err(Error::SerializeUrlencodedBodyUpbit)?;
-        let token = if <T::Signer>::is_private() {
-            #[skip_serializing_none]
-            #[derive(Serialize)]
-            struct AuthPayload {
-                access_key: String,
-                nonce: Uuid,
-                query_hash: Option<String>,
-                query_hash_alg: Option<&'static str>,
+        let query = x
+            .extensions()
+            .get::<QueryParams>()
+            .map_or("", |QueryParams(x)| x.as_str());
+        let query_hash = if query.is_empty() {
+            None
+        } else {
+            let mut hash = Sha512::new();
+            hash.update(query.as_bytes());
+            let result = hash.finalize();
+            let mut hash = String::with_capacity(64);
+            for &b in result.as_slice() {
+                write!(&mut hash, "{:02x}", b).expect("Writing hash to string failed");
             }
 
-            let key: Hmac<Sha256> = Hmac::new_from_slice(self.authentication.secret().as_bytes())
-                .expect("upbit: cannot initialize authentication");
-
-            let query_hash = if query.is_empty() {
-                None
-            } else {
-                let mut hash = Sha512::new();
-                hash.update(query.as_bytes());
-                let result = hash.finalize();
-                let mut hash = String::with_capacity(64);
-                for &b in result.as_slice() {
-                    write!(&mut hash, "{:02x}", b).expect("Writing hash to string failed");
-                }
-
-                Some(hash)
-            };
-
-            let payload = AuthPayload {
-                access_key: self.authentication.key().to_string(),
-                nonce: Uuid::new_v4(),
-                query_hash_alg: query_hash.as_ref().map(|_| "SHA512"),
-                query_hash,
-            };
+            Some(hash)
+        };
 
-            let token = payload.sign_with_key(&key).map_err(Error::Jwt)?;
-            Some(token)
-        } else {
-            None
+        let payload = AuthPayload {
+            access_key: self.authentication.key().to_string(),
+            nonce: Uuid::new_v4(),
+            query_hash_alg: query_hash.as_ref().map(|_| "SHA512"),
+            query_hash,
         };
-        if x.method() == Method::GET {
-            let mut req = hyper::Request::new(hyper::Body::empty());
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            *req.method_mut() = x.method();
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            if let Some(token) = token {
-                req.headers_mut()
-                    .append("Authorization", format!("Bearer {token}").parse().unwrap());
-            }
-            *req.uri_mut() = Uri::from_str(&format!("{}?{}", uri, query)).unwrap();
-            Ok(req)
-        } else {
-            let mut req = hyper::Request::new(hyper::Body::from(
-                serde_json::to_string(&x).map_err(Error::SerializeJsonBody)?,
-            ));
-            *req.method_mut() = x.method();
-            let uri = x.uri();
-            assert_eq!(uri.query(), None);
-            req.headers_mut()
-                .append("Accept", "application/json".parse().unwrap());
-            req.headers_mut()
-                .append("Content-Type", "application/json".parse().unwrap());
-            if let Some(token) = token {
-                req.headers_mut()
-                    .append("Authorization", format!("Bearer {token}").parse().unwrap());
-            }
-            *req.uri_mut() = uri;
-            Ok(req)
-        }
-    }
 
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
-        tracing::debug!(status = ?x.status());
-        if x.status().is_success() {
-            Box::pin(async {
-                let buf = hyper::body::to_bytes(x).await?;
-                let resp = serde_json::from_slice(&buf).map_err(|e| {
-                    Error::DeserializeJsonBody(e, String::from_utf8_lossy(&buf).to_string())
-                })?;
-
-                Ok(resp)
-            })
-        } else {
-            #[derive(Deserialize)]
-            struct UpbitError {
-                error: UpbitErrorInner,
-            }
+        let token = payload.sign_with_key(&key).map_err(Error::Jwt)?;
+        let token = format!("Bearer {token}")
+            .parse()
+            .map_err(|e| Error::ConstructHttpRequest(http::Error::from(e)))?;
+        x.headers_mut().insert(AUTHORIZATION, token);
+        Ok(())
+    }
+}
 
-            #[derive(Deserialize)]
-            struct UpbitErrorInner {
-                name: String,
-                message: String,
-            }
+/// Decodes Upbit responses, which are not wrapped on success.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UpbitEnvelope;
 
-            Box::pin(async {
-                let s = hyper::body::to_bytes(x).await.map_err(Error::Hyper)?;
-                match serde_json::from_slice::<UpbitError>(&s) {
-                    Ok(error) => Err(Error::RequestFailed {
-                        code: Some(error.error.name),
-                        msg: Some(error.error.message),
-                    }),
-                    Err(_) => Err(Error::RequestFailed {
-                        code: None,
-                        msg: Some(String::from_utf8_lossy(&s).to_string()),
-                    }),
-                }
-            })
+impl<T> Envelope<T> for UpbitEnvelope
+where
+    T: Request + Sealed,
+    T::Response: DeserializeOwned,
+    T::ErrorResponse: DeserializeOwned + Classify,
+{
+    type Error = Error<T::ErrorResponse>;
+
+    fn decode(x: http::Response<Bytes>) -> Result<T::Response, Self::Error> {
+        if !x.status().is_success() {
+            return Err(crate::error_response(
+                x.status(),
+                x.body(),
+                ErrorClass::from_status,
+            ));
         }
+        crate::deserialize_body(x.body())
     }
 }
 
+pub type UpbitClient<S> = RestClient<UpbitCodec, UpbitEnvelope, Public, S>;
+
+/// [`UpbitClient`] accepting private requests, which are signed with [`JwtSigner`].
+pub type UpbitPrivateClient<S> = RestClient<UpbitCodec, UpbitEnvelope, JwtSigner, S>;
+
 impl<S> tower::Service<Unsupported> for UpbitClient<S> {
     type Response = ::std::convert::Infallible;
 
@@ -550,11 +576,15 @@ impl From<common::GetBalance> for GetV1Accounts {
     }
 }
 
-impl From<common::PlaceOrder> for PostV1Orders {
-    fn from(x: common::PlaceOrder) -> Self {
-        assert_eq!(*x.market.kind(), common::MarketKind::Spot);
+impl Shim<common::PlaceOrder> for PostV1Orders {
+    type Error = UnsupportedParameter;
+
+    fn shim(x: common::PlaceOrder) -> Result<Self, Self::Error> {
+        if *x.market.kind() != common::MarketKind::Spot {
+            return Err(UnsupportedParameter("non-spot markets"));
+        }
         match x.order {
-            common::Order::Market { side, quantity } => Self {
+            common::Order::Market { side, quantity } => Ok(Self {
                 market: format!("{}-{}", x.market.quote(), x.market.base()),
                 side: match side {
                     common::Side::Buy => Side::Buy,
@@ -567,19 +597,17 @@ impl From<common::PlaceOrder> for PostV1Orders {
                     common::Side::Sell => OrderType::MarketSell,
                 },
                 identifier: None,
-            },
+            }),
             common::Order::Limit {
                 side,
                 quantity,
                 price,
                 time_in_force,
             } => {
-                assert_eq!(
-                    time_in_force,
-                    common::TimeInForce::GoodTilCancled,
-                    "Upbit does not support TIFs"
-                );
-                Self {
+                if time_in_force != common::TimeInForce::GoodTilCancled {
+                    return Err(UnsupportedParameter("time in force"));
+                }
+                Ok(Self {
                     market: format!("{}-{}", x.market.quote(), x.market.base()),
                     side: match side {
                         common::Side::Buy => Side::Buy,
@@ -589,25 +617,19 @@ impl From<common::PlaceOrder> for PostV1Orders {
                     price: Some(price),
                     ord_type: OrderType::Limit,
                     identifier: None,
-                }
+                })
             }
-            _ => todo!(),
+            _ => Err(UnsupportedParameter("stop orders")),
         }
     }
 }
 
 impl From<common::GetOrders> for GetV1Orders {
     fn from(x: common::GetOrders) -> Self {
-        Self {
-            market: format!("{}-{}", x.market.quote(), x.market.base()),
-            uuids: Vec::new(),
-            identifiers: Vec::new(),
-            state: Some(OrderState::Wait),
-            states: None,
-            page: None,
-            limit: None,
-            order_by: SortOrders::Descending,
-        }
+        Self::builder()
+            .market(format!("{}-{}", x.market.quote(), x.market.base()))
+            .state(OrderState::Wait)
+            .build()
     }
 }
 
@@ -711,3 +733,97 @@ mod __private {
     pub trait Sealed {}
     impl Sealed for Unsupported {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let table = [
+            ("invalid_query_payload", ErrorClass::Authentication),
+            ("jwt_verification", ErrorClass::Authentication),
+            ("expired_access_key", ErrorClass::Authentication),
+            ("nonce_used", ErrorClass::Authentication),
+            ("no_authorization_i_p", ErrorClass::Authentication),
+            ("out_of_scope", ErrorClass::Authentication),
+            ("insufficient_funds_bid", ErrorClass::Other),
+            ("create_ask_error", ErrorClass::Other),
+        ];
+        for (name, class) in table {
+            let error = UpbitError {
+                error: UpbitErrorDetail {
+                    name: name.to_string(),
+                    message: String::new(),
+                },
+            };
+            assert_eq!(error.class(), class, "{name}");
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|&(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_remaining_req() {
+        let usages = RemainingReq.parse_usage(
+            StatusCode::OK,
+            &headers(&[("Remaining-Req", "group=default; min=1799; sec=29")]),
+        );
+        assert_eq!(
+            usages,
+            [
+                Usage::Remaining {
+                    bucket: "minute",
+                    remaining: 1799
+                },
+                Usage::Remaining {
+                    bucket: "second",
+                    remaining: 29
+                },
+            ]
+        );
+
+        // Malformed values and unknown keys are ignored
+        let usages = RemainingReq.parse_usage(
+            StatusCode::OK,
+            &headers(&[("Remaining-Req", "group=order; min=; sec=x; day=3; garbage")]),
+        );
+        assert_eq!(usages, []);
+    }
+
+    #[test]
+    fn cools_down_on_rate_limit_statuses() {
+        for status in [StatusCode::TOO_MANY_REQUESTS, StatusCode::IM_A_TEAPOT] {
+            let usages = RemainingReq.parse_usage(
+                status,
+                &headers(&[
+                    ("Retry-After", "3"),
+                    ("Remaining-Req", "group=default; min=0; sec=0"),
+                ]),
+            );
+            assert_eq!(
+                usages,
+                [
+                    Usage::CoolDown(Duration::from_secs(3)),
+                    Usage::Remaining {
+                        bucket: "minute",
+                        remaining: 0
+                    },
+                    Usage::Remaining {
+                        bucket: "second",
+                        remaining: 0
+                    },
+                ]
+            );
+
+            // Falls back to a second without a valid Retry-After
+            let usages = RemainingReq.parse_usage(status, &headers(&[("Retry-After", "")]));
+            assert_eq!(usages, [Usage::CoolDown(Duration::from_secs(1))]);
+        }
+    }
+}
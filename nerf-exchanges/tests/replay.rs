@@ -0,0 +1,168 @@
+//! Replays cassettes of exchange APIs through the clients, to test requests and responses of each
+//! exchange without network access.
+
+use nerf::{
+    sign::SignLayer,
+    testing::{Cassette, Replay},
+    IntoService, ReadyCall,
+};
+use nerf_exchanges::{
+    binance::{self, BinanceFuturesClient, BinanceSpotClient, BinanceSpotPrivateClient},
+    bithumb::BithumbClient,
+    common::{CommonOpsService, IntoCommon, OrderbookItem},
+    cryptocom::CryptocomClient,
+    okx::OkxClient,
+    upbit::UpbitClient,
+    KeySecretAuthentication,
+};
+use rust_decimal_macros::dec;
+use tower::Layer;
+
+fn replay(name: &str) -> Replay {
+    let path = format!("{}/tests/cassettes/{name}.json", env!("CARGO_MANIFEST_DIR"));
+    Replay::new(Cassette::load(path).unwrap())
+}
+
+#[tokio::test]
+async fn binance_spot() {
+    let replay = replay("binance_spot");
+
+    let mut svc = BinanceSpotClient::new(replay.clone()).into_service();
+    let orderbook = svc
+        .get_orderbook("spot:BTC/USDT", Some(5))
+        .await
+        .unwrap()
+        .into_common();
+    assert_eq!(
+        orderbook.bids()[0],
+        OrderbookItem::new(dec!(28429.36), dec!(4.2193))
+    );
+    assert_eq!(
+        orderbook.asks()[0],
+        OrderbookItem::new(dec!(28429.37), dec!(1.46618))
+    );
+
+    // Signatures and timestamps are not recorded
+    let signer = binance::HmacSigner::new(KeySecretAuthentication::new("key", "secret"));
+    let mut svc =
+        BinanceSpotPrivateClient::new(SignLayer::new(signer).layer(replay.clone())).into_service();
+    let resp = svc
+        .ready_call(binance::DeleteApiV3Orders {
+            symbol: "BTCUSDT".to_string(),
+            order_id: Some(20584318964),
+            orig_client_order_id: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(resp.order_id, 20584318964);
+    assert_eq!(resp.status, "CANCELED");
+
+    assert!(replay.pending().is_empty());
+}
+
+#[tokio::test]
+async fn binance_futures() {
+    let replay = replay("binance_futures");
+
+    let mut svc = BinanceFuturesClient::new(replay.clone()).into_service();
+    let orderbook = svc
+        .get_orderbook("swap:BTC/USDT", Some(5))
+        .await
+        .unwrap()
+        .into_common();
+    assert_eq!(
+        orderbook.bids()[0],
+        OrderbookItem::new(dec!(28415.10), dec!(12.518))
+    );
+    assert_eq!(
+        orderbook.asks()[0],
+        OrderbookItem::new(dec!(28415.20), dec!(3.067))
+    );
+
+    assert!(replay.pending().is_empty());
+}
+
+#[tokio::test]
+async fn upbit() {
+    let replay = replay("upbit");
+
+    let mut svc = UpbitClient::new(replay.clone()).into_service();
+    let orderbook = svc
+        .get_orderbook("spot:BTC/KRW", None)
+        .await
+        .unwrap()
+        .into_common();
+    assert_eq!(
+        orderbook.bids()[0],
+        OrderbookItem::new(dec!(37670000), dec!(0.27463823))
+    );
+    assert_eq!(
+        orderbook.asks()[0],
+        OrderbookItem::new(dec!(37680000), dec!(0.04214021))
+    );
+
+    assert!(replay.pending().is_empty());
+}
+
+#[tokio::test]
+async fn okx() {
+    let replay = replay("okx");
+
+    let mut svc = OkxClient::new(replay.clone()).into_service();
+    let (orderbook,) = svc.get_orderbook("spot:BTC/USDT", Some(5)).await.unwrap();
+    let orderbook = orderbook.into_common();
+    assert_eq!(
+        orderbook.bids()[0],
+        OrderbookItem::new(dec!(28430), dec!(1.00937213))
+    );
+    assert_eq!(
+        orderbook.asks()[0],
+        OrderbookItem::new(dec!(28430.1), dec!(0.37124542))
+    );
+
+    assert!(replay.pending().is_empty());
+}
+
+#[tokio::test]
+async fn bithumb() {
+    let replay = replay("bithumb");
+
+    let mut svc = BithumbClient::new(replay.clone()).into_service();
+    let orderbook = svc
+        .get_orderbook("spot:BTC/KRW", Some(5))
+        .await
+        .unwrap()
+        .into_common();
+    assert_eq!(
+        orderbook.bids()[0],
+        OrderbookItem::new(dec!(37660000), dec!(0.0734))
+    );
+    assert_eq!(
+        orderbook.asks()[0],
+        OrderbookItem::new(dec!(37677000), dec!(0.0416))
+    );
+
+    assert!(replay.pending().is_empty());
+}
+
+#[tokio::test]
+async fn cryptocom() {
+    let replay = replay("cryptocom");
+
+    let mut svc = CryptocomClient::new(replay.clone()).into_service();
+    let orderbook = svc
+        .get_orderbook("spot:BTC/USDT", Some(5))
+        .await
+        .unwrap()
+        .into_common();
+    assert_eq!(
+        orderbook.bids()[0],
+        OrderbookItem::new(dec!(28427.17), dec!(0.17589))
+    );
+    assert_eq!(
+        orderbook.asks()[0],
+        OrderbookItem::new(dec!(28428.70), dec!(0.00500))
+    );
+
+    assert!(replay.pending().is_empty());
+}
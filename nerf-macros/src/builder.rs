@@ -0,0 +1,199 @@
+//! Typed builders of requests, generated with `builder` of the HTTP macros.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{spanned::Spanned, Data, DeriveInput, Fields};
+
+use crate::fields::RoutedField;
+
+/// Generates `#ident::builder()` and `#identBuilder`.
+///
+/// Required fields are type parameters of the builder, `()` until they are set and `(T,)` after,
+/// so that `build` exists only when every required field is set. `Option` and `#[optional]`
+/// fields can be set in any state.
+pub(crate) fn builder(
+    item: &DeriveInput,
+    fields: &[RoutedField],
+    validate: bool,
+    span: Span,
+) -> syn::Result<TokenStream> {
+    match &item.data {
+        Data::Struct(x) if matches!(x.fields, Fields::Named(_) | Fields::Unit) => (),
+        _ => {
+            return Err(syn::Error::new(
+                span,
+                "builders are supported for structs with named fields only",
+            ))
+        }
+    }
+    if !item.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            item.generics.span(),
+            "builders are not supported for generic structs",
+        ));
+    }
+
+    let ident = &item.ident;
+    let vis = &item.vis;
+    let builder = format_ident!("{}Builder", ident);
+    let required = fields
+        .iter()
+        .filter(|x| !x.optional && !x.is_option())
+        .collect::<Vec<_>>();
+    let params = (0..required.len())
+        .map(|i| format_ident!("__R{}", i))
+        .collect::<Vec<_>>();
+    let unset = required.iter().map(|_| quote!(())).collect::<Vec<_>>();
+
+    let slots = fields.iter().map(|x| {
+        let name = &x.ident;
+        let ty = &x.ty;
+        match required.iter().position(|y| y.ident == x.ident) {
+            Some(i) => {
+                let param = &params[i];
+                quote!(#name: #param)
+            }
+            None => quote!(#name: #ty),
+        }
+    });
+    let inits = fields.iter().map(|x| {
+        let name = &x.ident;
+        if required.iter().any(|y| y.ident == x.ident) {
+            quote!(#name: ())
+        } else {
+            quote!(#name: ::std::default::Default::default())
+        }
+    });
+
+    let required_setters = required.iter().enumerate().map(|(i, x)| {
+        let name = &x.ident;
+        let ty = &x.ty;
+        let others = params
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .map(|(_, x)| x);
+        let state = |set: TokenStream| {
+            let args = params.iter().enumerate().map(
+                |(j, x)| {
+                    if i == j {
+                        set.clone()
+                    } else {
+                        quote!(#x)
+                    }
+                },
+            );
+            quote!(#builder<#(#args),*>)
+        };
+        let from = state(quote!(()));
+        let to = state(quote!((#ty,)));
+        let moved = fields
+            .iter()
+            .filter(|y| y.ident != x.ident)
+            .map(|y| &y.ident)
+            .collect::<Vec<_>>();
+        quote! {
+            impl<#(#others),*> #from {
+                pub fn #name(self, #name: impl ::std::convert::Into<#ty>) -> #to {
+                    #builder {
+                        #name: (#name.into(),),
+                        #(#moved: self.#moved,)*
+                    }
+                }
+            }
+        }
+    });
+    let optional_setters = fields
+        .iter()
+        .filter(|x| !required.iter().any(|y| y.ident == x.ident))
+        .map(|x| {
+            let name = &x.ident;
+            match x.option_inner() {
+                // Takes `Option<T>` too, to pass optional values through
+                Some(inner) if !x.optional => quote! {
+                    pub fn #name(
+                        mut self,
+                        #name: impl ::std::convert::Into<::std::option::Option<#inner>>,
+                    ) -> Self {
+                        self.#name = #name.into();
+                        self
+                    }
+                },
+                _ => {
+                    let ty = &x.ty;
+                    quote! {
+                        pub fn #name(mut self, #name: impl ::std::convert::Into<#ty>) -> Self {
+                            self.#name = #name.into();
+                            self
+                        }
+                    }
+                }
+            }
+        });
+
+    let set = required.iter().map(|x| {
+        let ty = &x.ty;
+        quote!((#ty,))
+    });
+    let values = fields.iter().map(|x| {
+        let name = &x.ident;
+        if required.iter().any(|y| y.ident == x.ident) {
+            quote!(#name: self.#name.0)
+        } else {
+            quote!(#name: self.#name)
+        }
+    });
+    let construct = match &item.data {
+        Data::Struct(x) if matches!(x.fields, Fields::Unit) => quote!(#ident),
+        _ => quote!(#ident { #(#values),* }),
+    };
+    let build = if validate {
+        quote! {
+            /// Builds the request, checking its invariants with `nerf::Validate`.
+            pub fn build(
+                self,
+            ) -> ::std::result::Result<#ident, <#ident as ::nerf::Validate>::Error> {
+                let x = #construct;
+                ::nerf::Validate::validate(&x)?;
+                ::std::result::Result::Ok(x)
+            }
+        }
+    } else {
+        quote! {
+            /// Builds the request.
+            pub fn build(self) -> #ident {
+                #construct
+            }
+        }
+    };
+
+    let doc = format!(
+        "A builder of [`{ident}`], created with [`{ident}::builder`]. `build` is available after \
+         every required field is set."
+    );
+    Ok(quote! {
+        impl #ident {
+            /// Returns a builder of the request.
+            #vis fn builder() -> #builder<#(#unset),*> {
+                #builder {
+                    #(#inits),*
+                }
+            }
+        }
+
+        #[doc = #doc]
+        #vis struct #builder<#(#params = ()),*> {
+            #(#slots),*
+        }
+
+        #(#required_setters)*
+
+        impl<#(#params),*> #builder<#(#params),*> {
+            #(#optional_setters)*
+        }
+
+        impl #builder<#(#set),*> {
+            #build
+        }
+    })
+}
@@ -0,0 +1,231 @@
+//! Field attributes of the HTTP macros: `#[path]`, `#[query]`, `#[header("...")]` and `#[body]`,
+//! and `#[optional]` for builders.
+
+use proc_macro2::{Ident, Span};
+use syn::{
+    spanned::Spanned, Attribute, Data, DeriveInput, Fields, GenericArgument, LitStr, Meta,
+    NestedMeta, PathArguments, Type,
+};
+
+/// Where a field is sent.
+pub(crate) enum Location {
+    /// Formatted into the endpoint, implied by `{field}` placeholders.
+    Path,
+    /// Appended to the query string with its name.
+    Query(LitStr),
+    /// Sent as a header with its name.
+    Header(LitStr),
+    /// Serialized by the client into the body.
+    Body,
+}
+
+pub(crate) struct RoutedField {
+    pub ident: Ident,
+    pub ty: Type,
+    /// `None` if the field is serialized by the client, as the query string of `GET` requests
+    /// and as the body of others.
+    pub location: Option<Location>,
+    /// The span of the name of the field attribute, or the field.
+    pub span: Span,
+    /// `#[optional]`, to be left to its `Default` by builders.
+    pub optional: bool,
+}
+
+impl RoutedField {
+    /// Returns `true` if the field is an `Option`, so that it is omitted when `None`.
+    pub fn is_option(&self) -> bool {
+        self.option_inner().is_some()
+    }
+
+    /// Returns `T` of `Option<T>`.
+    pub fn option_inner(&self) -> Option<&Type> {
+        let last = match &self.ty {
+            Type::Path(x) => x.path.segments.last()?,
+            _ => return None,
+        };
+        if last.ident != "Option" {
+            return None;
+        }
+        match &last.arguments {
+            PathArguments::AngleBracketed(x) => match x.args.first()? {
+                GenericArgument::Type(x) => Some(x),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Takes the field attributes out of `item`, which are not real attributes.
+pub(crate) fn take_fields(item: &mut DeriveInput) -> syn::Result<Vec<RoutedField>> {
+    let fields = match &mut item.data {
+        Data::Struct(x) => &mut x.fields,
+        _ => return Ok(Vec::new()),
+    };
+
+    let mut routed = Vec::new();
+    for field in fields.iter_mut() {
+        let mut location = None;
+        let mut span = field.span();
+        let mut optional = false;
+        let mut rest = Vec::new();
+        for attr in field.attrs.drain(..) {
+            if attr.path.is_ident("optional") {
+                parse_flag(&attr)?;
+                if optional {
+                    return Err(syn::Error::new(
+                        attr.path.span(),
+                        "Duplicated attribute item",
+                    ));
+                }
+                optional = true;
+                continue;
+            }
+            let parsed = if attr.path.is_ident("path") {
+                parse_flag(&attr).map(|_| Location::Path)
+            } else if attr.path.is_ident("body") {
+                parse_flag(&attr).map(|_| Location::Body)
+            } else if attr.path.is_ident("query") {
+                let name = if attr.tokens.is_empty() {
+                    let name = field.ident.as_ref().map(unraw).unwrap_or_default();
+                    LitStr::new(&name, attr.span())
+                } else {
+                    attr.parse_args()?
+                };
+                Ok(Location::Query(name))
+            } else if attr.path.is_ident("header") {
+                let name: LitStr = attr.parse_args().map_err(|e| {
+                    syn::Error::new(
+                        e.span(),
+                        "`#[header]` requires a name, e.g. `#[header(\"x-request-id\")]`",
+                    )
+                })?;
+                if http::header::HeaderName::from_bytes(name.value().as_bytes()).is_err() {
+                    return Err(syn::Error::new(name.span(), "invalid header name"));
+                }
+                Ok(Location::Header(LitStr::new(
+                    &name.value().to_ascii_lowercase(),
+                    name.span(),
+                )))
+            } else {
+                rest.push(attr);
+                continue;
+            };
+
+            if location.replace(parsed?).is_some() {
+                return Err(syn::Error::new(
+                    attr.path.span(),
+                    "Duplicated attribute item",
+                ));
+            }
+            span = attr.path.span();
+        }
+        field.attrs = rest;
+
+        if (location.is_some() || optional) && field.ident.is_none() {
+            return Err(syn::Error::new(
+                span,
+                "field attributes are supported for named fields only",
+            ));
+        }
+        if let Some(ident) = &field.ident {
+            routed.push(RoutedField {
+                ident: ident.clone(),
+                ty: field.ty.clone(),
+                location,
+                span,
+                optional,
+            });
+        }
+    }
+
+    Ok(routed)
+}
+
+/// Excludes `ident` from serialization, if the item derives `Serialize` and the field is not
+/// skipped already.
+pub(crate) fn skip_serializing(item: &mut DeriveInput, ident: &Ident) {
+    if !item.attrs.iter().any(derives_serialize) {
+        return;
+    }
+    let fields = match &mut item.data {
+        Data::Struct(x) => &mut x.fields,
+        _ => return,
+    };
+    let field = match fields {
+        Fields::Named(x) => x.named.iter_mut().find(|x| x.ident.as_ref() == Some(ident)),
+        _ => None,
+    };
+    if let Some(field) = field {
+        if !field.attrs.iter().any(is_serde_skip) {
+            field.attrs.push(syn::parse_quote!(#[serde(skip)]));
+        }
+    }
+}
+
+/// Fails if `field` is not skipped from serialization, which `#[derive(HttpRequest)]` cannot add
+/// unlike [`skip_serializing`].
+pub(crate) fn ensure_skipped(item: &DeriveInput, field: &RoutedField) -> syn::Result<()> {
+    let skipped = match &item.data {
+        Data::Struct(x) => x
+            .fields
+            .iter()
+            .filter(|x| x.ident.as_ref() == Some(&field.ident))
+            .any(|x| x.attrs.iter().any(is_serde_skip)),
+        _ => true,
+    };
+    let location = match field.location {
+        Some(Location::Query(_)) => "a query parameter",
+        Some(Location::Header(_)) => "a header",
+        _ => "a path parameter",
+    };
+    if skipped {
+        Ok(())
+    } else {
+        Err(syn::Error::new(
+            field.span,
+            format!(
+                "field `{}` is {location}, add `#[serde(skip)]` not to serialize it again",
+                field.ident
+            ),
+        ))
+    }
+}
+
+fn parse_flag(attr: &Attribute) -> syn::Result<()> {
+    if attr.tokens.is_empty() {
+        Ok(())
+    } else {
+        Err(syn::Error::new(
+            attr.tokens.span(),
+            "unexpected arguments of the field attribute",
+        ))
+    }
+}
+
+fn unraw(x: &Ident) -> String {
+    let x = x.to_string();
+    x.strip_prefix("r#").map(String::from).unwrap_or(x)
+}
+
+fn derives_serialize(attr: &Attribute) -> bool {
+    match attr.parse_meta() {
+        Ok(Meta::List(x)) if x.path.is_ident("derive") => x.nested.iter().any(|x| match x {
+            NestedMeta::Meta(Meta::Path(x)) => {
+                x.segments.last().is_some_and(|x| x.ident == "Serialize")
+            }
+            _ => false,
+        }),
+        _ => false,
+    }
+}
+
+fn is_serde_skip(attr: &Attribute) -> bool {
+    match attr.parse_meta() {
+        Ok(Meta::List(x)) if x.path.is_ident("serde") => x.nested.iter().any(|x| match x {
+            NestedMeta::Meta(Meta::Path(x)) => x.is_ident("skip") || x.is_ident("skip_serializing"),
+            _ => false,
+        }),
+        _ => false,
+    }
+}
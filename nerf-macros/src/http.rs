@@ -1,46 +1,45 @@
 use once_cell::sync::Lazy;
 use proc_macro::TokenStream;
-use proc_macro2::Ident;
+use proc_macro2::{Ident, Span};
 use quote::quote;
 use regex::Regex;
 use syn::{
+    parenthesized,
     parse::{Parse, ParseStream},
     parse_macro_input,
     spanned::Spanned,
-    LitBool, LitStr, Path, Token, Type,
+    token::Paren,
+    Data, DeriveInput, LitBool, LitStr, Path, Token, Type,
 };
 
-use crate::{NamedItem, PunctuatedExt};
-
-#[derive(Clone, Debug)]
-enum Shim {
-    Bool(LitBool),
-    Path(Path),
-}
-
-impl Parse for Shim {
-    fn parse(input: ParseStream) -> syn::Result<Self> {
-        if input.lookahead1().peek(LitBool) {
-            input.parse().map(Self::Bool)
-        } else {
-            input.parse().map(Self::Path)
-        }
-    }
-}
-
-impl Spanned for Shim {
-    fn span(&self) -> proc_macro2::Span {
-        match self {
-            Shim::Bool(x) => x.span(),
-            Shim::Path(x) => x.span(),
-        }
-    }
-}
+use crate::{
+    builder,
+    fields::{ensure_skipped, skip_serializing, take_fields, Location, RoutedField},
+    PunctuatedExt,
+};
 
 struct HttpAttr {
+    /// Set with `get = "..."` and so on by `#[derive(HttpRequest)]`, which does not have the
+    /// method in its name.
+    method: Option<Ident>,
     endpoint: LitStr,
     response: Type,
-    shim: Option<Shim>,
+    error: Option<Type>,
+    signer: Option<Type>,
+    shim: Option<Path>,
+    timeout: Option<LitStr>,
+    side_effects: Option<LitBool>,
+    sealed: Option<Path>,
+    builder: Option<BuilderAttr>,
+    /// `cacheable`, to implement `nerf::cache::Cacheable`.
+    cacheable: Option<Ident>,
+}
+
+/// `builder`, or `builder(validate)` to check the invariants of the request on `build`.
+#[derive(Clone)]
+struct BuilderAttr {
+    key: Ident,
+    validate: bool,
 }
 
 impl Parse for HttpAttr {
@@ -48,15 +47,21 @@ impl Parse for HttpAttr {
         let attrs = input.parse_terminated::<_, Token![,]>(HttpAttrKind::parse)?;
         // FIXME: `input.span()` may be incorrect?
         let endpoint = attrs
+            .find_at_most_once(|x| match x {
+                HttpAttrKind::Endpoint(x) | HttpAttrKind::Method(_, x) => Some(x),
+                _ => None,
+            })?
+            .ok_or_else(|| syn::Error::new(input.span(), "endpoint is required"))?
+            .clone();
+        let method = attrs
             .find_at_most_once(|x| {
-                if let HttpAttrKind::Endpoint(x) = x {
+                if let HttpAttrKind::Method(x, _) = x {
                     Some(x)
                 } else {
                     None
                 }
             })?
-            .ok_or_else(|| syn::Error::new(input.span(), "endpoint is required"))?
-            .clone();
+            .cloned();
         let response = attrs
             .find_at_most_once(|x| {
                 if let HttpAttrKind::Response(x) = x {
@@ -67,6 +72,24 @@ impl Parse for HttpAttr {
             })?
             .ok_or_else(|| syn::Error::new(input.span(), "response is required"))?
             .clone();
+        let error = attrs
+            .find_at_most_once(|x| {
+                if let HttpAttrKind::Error(x) = x {
+                    Some(x)
+                } else {
+                    None
+                }
+            })?
+            .cloned();
+        let signer = attrs
+            .find_at_most_once(|x| {
+                if let HttpAttrKind::Signer(x) = x {
+                    Some(x)
+                } else {
+                    None
+                }
+            })?
+            .cloned();
         let shim = attrs
             .find_at_most_once(|x| {
                 if let HttpAttrKind::Shim(x) = x {
@@ -76,27 +99,94 @@ impl Parse for HttpAttr {
                 }
             })?
             .cloned();
+        let timeout = attrs
+            .find_at_most_once(|x| {
+                if let HttpAttrKind::Timeout(x) = x {
+                    Some(x)
+                } else {
+                    None
+                }
+            })?
+            .cloned();
+        let side_effects = attrs
+            .find_at_most_once(|x| {
+                if let HttpAttrKind::SideEffects(x) = x {
+                    Some(x)
+                } else {
+                    None
+                }
+            })?
+            .cloned();
+        let sealed = attrs
+            .find_at_most_once(|x| {
+                if let HttpAttrKind::Sealed(x) = x {
+                    Some(x)
+                } else {
+                    None
+                }
+            })?
+            .cloned();
+        let builder = attrs
+            .find_at_most_once(|x| {
+                if let HttpAttrKind::Builder(x) = x {
+                    Some(x)
+                } else {
+                    None
+                }
+            })?
+            .cloned();
+        let cacheable = attrs
+            .find_at_most_once(|x| {
+                if let HttpAttrKind::Cacheable(x) = x {
+                    Some(x)
+                } else {
+                    None
+                }
+            })?
+            .cloned();
 
-        endpoint.value().parse::<http::uri::Uri>().map_err(|e| {
+        let uri = endpoint.value().parse::<http::uri::Uri>().map_err(|e| {
             syn::Error::new(
                 endpoint.span(),
                 format!("endpoint is not a valid HTTP URI: {e}"),
             )
         })?;
+        if uri.scheme().is_none() && !endpoint.value().starts_with('/') {
+            return Err(syn::Error::new(
+                endpoint.span(),
+                "endpoint must be an absolute URI, or a path starting with `/` to be resolved against the base URL of the client",
+            ));
+        }
 
         Ok(HttpAttr {
+            method,
             endpoint,
             response,
+            error,
+            signer,
             shim,
+            timeout,
+            side_effects,
+            sealed,
+            builder,
+            cacheable,
         })
     }
 }
 
 enum HttpAttrKind {
     Endpoint(LitStr),
+    /// `get = "..."`, `post = "..."` and so on.
+    Method(Ident, LitStr),
     Response(Type),
-    Signer(Ident),
-    Shim(Shim),
+    Error(Type),
+    Signer(Type),
+    Shim(Path),
+    Timeout(LitStr),
+    SideEffects(LitBool),
+    Sealed(Path),
+    Builder(BuilderAttr),
+    Cacheable(Ident),
 }
 
 impl Parse for HttpAttrKind {
@@ -108,24 +198,85 @@ impl Parse for HttpAttrKind {
 
         let key: Ident = input.parse()?;
         match key.to_string().as_str() {
+            "get" | "post" | "put" | "delete" => {
+                input
+                    .parse::<Token![=]>()
+                    .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
+                Ok(HttpAttrKind::Method(key, input.parse()?))
+            }
             "response" => {
                 input
                     .parse::<Token![=]>()
                     .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
                 Ok(HttpAttrKind::Response(input.parse()?))
             }
+            "error" => {
+                input
+                    .parse::<Token![=]>()
+                    .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
+                Ok(HttpAttrKind::Error(input.parse()?))
+            }
             "signer" => {
                 input
                     .parse::<Token![=]>()
                     .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
-                Ok(HttpAttrKind::Signer(input.parse()?))
+                let signer: Type = input.parse()?;
+                if !matches!(signer, Type::Path(_)) {
+                    return Err(syn::Error::new(
+                        signer.span(),
+                        "`signer` takes the type signing the request, e.g. `signer = Private`",
+                    ));
+                }
+                Ok(HttpAttrKind::Signer(signer))
             }
             "shim" => {
                 input
                     .parse::<Token![=]>()
                     .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
+                if input.peek(LitBool) {
+                    return Err(syn::Error::new(
+                        input.span(),
+                        "`shim` takes the common request to convert from, e.g. `shim = common::GetTickers`",
+                    ));
+                }
                 Ok(HttpAttrKind::Shim(input.parse()?))
             }
+            "timeout" => {
+                input
+                    .parse::<Token![=]>()
+                    .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
+                Ok(HttpAttrKind::Timeout(input.parse()?))
+            }
+            "side_effects" => {
+                input
+                    .parse::<Token![=]>()
+                    .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
+                Ok(HttpAttrKind::SideEffects(input.parse()?))
+            }
+            "sealed" => {
+                input
+                    .parse::<Token![=]>()
+                    .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
+                Ok(HttpAttrKind::Sealed(input.parse()?))
+            }
+            "builder" => {
+                let validate = if input.peek(Paren) {
+                    let content;
+                    parenthesized!(content in input);
+                    let option: Ident = content.parse()?;
+                    if option != "validate" {
+                        return Err(syn::Error::new(
+                            option.span(),
+                            format!("unexpected option {option}, expected `validate`"),
+                        ));
+                    }
+                    true
+                } else {
+                    false
+                };
+                Ok(HttpAttrKind::Builder(BuilderAttr { key, validate }))
+            }
+            "cacheable" => Ok(HttpAttrKind::Cacheable(key)),
             other => Err(syn::Error::new(
                 key.span(),
                 format!("unexpected key {other}"),
@@ -138,15 +289,22 @@ impl Spanned for HttpAttrKind {
     fn span(&self) -> proc_macro2::Span {
         match self {
             HttpAttrKind::Endpoint(x) => x.span(),
+            HttpAttrKind::Method(x, _) => x.span(),
             HttpAttrKind::Response(x) => x.span(),
+            HttpAttrKind::Error(x) => x.span(),
             HttpAttrKind::Signer(x) => x.span(),
             HttpAttrKind::Shim(x) => x.span(),
+            HttpAttrKind::Timeout(x) => x.span(),
+            HttpAttrKind::SideEffects(x) => x.span(),
+            HttpAttrKind::Sealed(x) => x.span(),
+            HttpAttrKind::Builder(x) => x.key.span(),
+            HttpAttrKind::Cacheable(x) => x.span(),
         }
     }
 }
 
 /// Parses raw endpoint string into `format!`-able string and subsequent parameteres.
-fn parse_endpoint(mut raw: String) -> (String, Vec<String>) {
+pub(crate) fn parse_endpoint(mut raw: String) -> (String, Vec<String>) {
     static RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\{[a-zA-Z_][a-zA-Z0-9_]*?\}"#).unwrap());
     let mut fields = Vec::new();
     while let Some(m) = RE.find(&raw) {
@@ -158,6 +316,29 @@ fn parse_endpoint(mut raw: String) -> (String, Vec<String>) {
     (raw, fields)
 }
 
+/// Parses a duration like `800ms`, `5s` or `1m` into milliseconds.
+fn parse_timeout(raw: &str) -> Option<u64> {
+    let split = raw.find(|c: char| !c.is_ascii_digit())?;
+    let (value, unit) = raw.split_at(split);
+    let value: u64 = value.parse().ok()?;
+    match unit {
+        "ms" => Some(value),
+        "s" => value.checked_mul(1000),
+        "m" => value.checked_mul(60 * 1000),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_parse_timeout() {
+    assert_eq!(parse_timeout("800ms"), Some(800));
+    assert_eq!(parse_timeout("5s"), Some(5000));
+    assert_eq!(parse_timeout("1m"), Some(60000));
+    assert_eq!(parse_timeout("5"), None);
+    assert_eq!(parse_timeout("ms"), None);
+    assert_eq!(parse_timeout("1h"), None);
+}
+
 #[test]
 fn test_parse_endpoint() {
     fn case(s: &'static str, t: &'static str, u: &[&'static str]) {
@@ -180,71 +361,326 @@ fn test_parse_endpoint() {
     );
 }
 
-pub fn entrypoint(
-    attr: TokenStream,
-    item: TokenStream,
-    method: proc_macro2::TokenStream,
-) -> TokenStream {
+pub fn entrypoint(attr: TokenStream, item: TokenStream, method: &str) -> TokenStream {
+    let attr = parse_macro_input!(attr as HttpAttr);
+    let mut item = parse_macro_input!(item as DeriveInput);
+    if let Some(x) = &attr.method {
+        return syn::Error::new(
+            x.span(),
+            format!(
+                "`{x}` is a key of `#[derive(HttpRequest)]`, this macro sets the method already"
+            ),
+        )
+        .into_compile_error()
+        .into();
+    }
+    let sealed = attr
+        .sealed
+        .clone()
+        .unwrap_or_else(|| syn::parse_quote!(Sealed));
+    match expand(attr, method, &mut item, Some(sealed), Mode::Attribute) {
+        Ok(impls) => quote! {
+            #item
+
+            #impls
+        }
+        .into(),
+        Err(e) => e.into_compile_error().into(),
+    }
+}
+
+/// `#[derive(HttpRequest)]` with `#[http(...)]` helper attributes on the item and its fields.
+pub fn derive(item: TokenStream) -> TokenStream {
+    let mut item = parse_macro_input!(item as DeriveInput);
+    match derive_http_request(&mut item) {
+        Ok(x) => x.into(),
+        Err(e) => e.into_compile_error().into(),
+    }
+}
+
+fn derive_http_request(item: &mut DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut attrs = item.attrs.iter().filter(|x| x.path.is_ident("http"));
+    let attr = attrs.next().ok_or_else(|| {
+        syn::Error::new(
+            item.ident.span(),
+            "`#[http(...)]` is required, e.g. `#[http(get = \"/v1/orders\", response = T)]`",
+        )
+    })?;
+    if let Some(x) = attrs.next() {
+        return Err(syn::Error::new(x.span(), "Duplicated attribute item"));
+    }
+    let attr: HttpAttr = attr.parse_args()?;
+    let method =
+        match attr.method.as_ref().map(|x| x.to_string()).as_deref() {
+            Some("get") => "GET",
+            Some("post") => "POST",
+            Some("put") => "PUT",
+            Some("delete") => "DELETE",
+            _ => return Err(syn::Error::new(
+                attr.endpoint.span(),
+                "the method is required, e.g. `get = \"/v1/orders\"` instead of `\"/v1/orders\"`",
+            )),
+        };
+    unwrap_field_helpers(item)?;
+    let sealed = attr.sealed.clone();
+    expand(attr, method, item, sealed, Mode::Derive)
+}
+
+/// Rewrites `#[http(query("name"))]` and so on of fields into the field attributes of the
+/// attribute macros, which cannot be derive helpers as `#[path]` is a built-in attribute.
+fn unwrap_field_helpers(item: &mut DeriveInput) -> syn::Result<()> {
+    let fields = match &mut item.data {
+        Data::Struct(x) => &mut x.fields,
+        _ => return Ok(()),
+    };
+    for field in fields.iter_mut() {
+        for attr in &mut field.attrs {
+            if !attr.path.is_ident("http") {
+                continue;
+            }
+            let (path, tokens) = attr.parse_args_with(|input: ParseStream| {
+                let path = input.call(Path::parse_mod_style)?;
+                Ok((path, input.parse::<proc_macro2::TokenStream>()?))
+            })?;
+            if !["path", "query", "header", "body", "optional"]
+                .iter()
+                .any(|x| path.is_ident(x))
+            {
+                return Err(syn::Error::new(
+                    path.span(),
+                    "unknown field attribute, expected `path`, `query`, `header`, `body` or `optional`",
+                ));
+            }
+            attr.path = path;
+            attr.tokens = tokens;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// `#[get]` and so on, which re-emit the item with `#[serde(skip)]` on routed fields.
+    Attribute,
+    /// `#[derive(HttpRequest)]`, which cannot modify the item.
+    Derive,
+}
+
+/// Implements the traits of a request, without the item itself.
+fn expand(
+    attr: HttpAttr,
+    method: &str,
+    item: &mut DeriveInput,
+    sealed: Option<Path>,
+    mode: Mode,
+) -> syn::Result<proc_macro2::TokenStream> {
     let HttpAttr {
         endpoint,
         response,
-        shim: _shim,
-    } = parse_macro_input!(attr as HttpAttr);
-    let item_ = item.clone();
-    let NamedItem { ident } = parse_macro_input!(item_ as NamedItem);
-    let item = proc_macro2::TokenStream::from(item);
-
-    // let shim = match shim {
-    //     Some(Shim::Bool(bool)) => {
-    //         if bool.value {
-    //             return syn::Error::new(bool.span(), "attribute parameter `shim` cannot be `true`")
-    //                 .into_compile_error()
-    //                 .into();
-    //         }
-
-    //         None
-    //     }
-    //     Some(Shim::Path(path)) => Some(path.to_token_stream()),
-    //     None => Some(quote! { __private }),
-    // };
+        error,
+        signer,
+        shim,
+        timeout,
+        side_effects,
+        builder,
+        cacheable,
+        ..
+    } = attr;
+    let side_effects = side_effects.map_or(method != "GET", |x| x.value);
+    let error = error
+        .map(|x| quote!(#x))
+        .unwrap_or_else(|| quote!(::nerf::NoErrorResponse));
+    let ident = item.ident.clone();
+    let fields = take_fields(item)?;
 
     if endpoint.value().contains("{}") {
-        return syn::Error::new(endpoint.span(), "endpoint must not contain `{}`\nIf you meant a place for format arguments, use `{field_name}` instead")
-            .into_compile_error()
-            .into();
+        return Err(syn::Error::new(endpoint.span(), "endpoint must not contain `{}`\nIf you meant a place for format arguments, use `{field_name}` instead"));
     }
 
+    let timeout = match timeout {
+        Some(x) => match parse_timeout(&x.value()) {
+            Some(millis) => quote! {
+                ::std::option::Option::Some(::std::time::Duration::from_millis(#millis))
+            },
+            None => {
+                return Err(syn::Error::new(
+                    x.span(),
+                    "timeout must be an integer with a unit of `ms`, `s` or `m`, e.g. `800ms`",
+                ));
+            }
+        },
+        None => quote!(::std::option::Option::None),
+    };
+
+    let template = endpoint.clone();
     let (sub, args) = parse_endpoint(endpoint.value());
+    for arg in &args {
+        match fields.iter().find(|x| x.ident == arg) {
+            Some(RoutedField {
+                location: None | Some(Location::Path),
+                ..
+            }) => (),
+            Some(field) => {
+                return Err(syn::Error::new(
+                    field.span,
+                    format!(
+                        "field `{arg}` is a placeholder of the endpoint, which is a path parameter"
+                    ),
+                ));
+            }
+            None => {
+                return Err(syn::Error::new(
+                    endpoint.span(),
+                    format!("no field `{arg}` in `{ident}`"),
+                ));
+            }
+        }
+    }
+
+    let mut queries = Vec::new();
+    let mut headers = Vec::new();
+    let mut skipped = Vec::new();
+    for field in &fields {
+        let name = &field.ident;
+        match &field.location {
+            Some(Location::Path) if !args.iter().any(|x| field.ident == x) => {
+                return Err(syn::Error::new(
+                    field.span,
+                    format!("field `{name}` is not a placeholder of the endpoint"),
+                ));
+            }
+            Some(Location::Body) if method == "GET" => {
+                return Err(syn::Error::new(
+                    field.span,
+                    "`#[body]` is not allowed for GET requests, whose parameters are encoded into the query string",
+                ));
+            }
+            Some(Location::Query(key)) => {
+                queries.push(quote! {
+                    ::nerf::__private::append_query(&mut uri, #key, &self.#name)?;
+                });
+                skipped.push(field);
+            }
+            Some(Location::Header(key)) => {
+                headers.push(if field.is_option() {
+                    quote! {
+                        if let ::std::option::Option::Some(x) = &self.#name {
+                            ::nerf::__private::insert_header(&mut headers, #key, x)?;
+                        }
+                    }
+                } else {
+                    quote! {
+                        ::nerf::__private::insert_header(&mut headers, #key, &self.#name)?;
+                    }
+                });
+                skipped.push(field);
+            }
+            _ => (),
+        }
+    }
+    skipped.extend(
+        fields
+            .iter()
+            .filter(|x| args.iter().any(|arg| x.ident == arg)),
+    );
+    for x in skipped {
+        match mode {
+            Mode::Attribute => skip_serializing(item, &x.ident),
+            Mode::Derive => ensure_skipped(item, x)?,
+        }
+    }
+
     let args = args
         .into_iter()
         .map(|arg| {
             let ident = Ident::new(&arg, endpoint.span());
-            quote!(self.#ident)
+            quote!(::nerf::__private::PathParam(&self.#ident))
         })
         .collect::<Vec<_>>();
     let sub = LitStr::new(&sub, endpoint.span());
 
-    let endpoint = quote! {
-        format!(#sub, #(#args),*)
+    let uri = if queries.is_empty() {
+        quote! {
+            let uri = format!(#sub, #(#args),*);
+        }
+    } else {
+        quote! {
+            let mut uri = format!(#sub, #(#args),*);
+            #(#queries)*
+        }
     };
+    let headers = if headers.is_empty() {
+        quote!()
+    } else {
+        quote! {
+            fn headers(&self) -> ::std::result::Result<::nerf::http::HeaderMap, ::nerf::Error> {
+                let mut headers = ::nerf::http::HeaderMap::new();
+                #(#headers)*
+                ::std::result::Result::Ok(headers)
+            }
+        }
+    };
+    let method = Ident::new(method, Span::call_site());
+    let signer = signer.map(|x| {
+        quote! {
+            impl Signer for #ident {
+                type Signer = #x;
+            }
+        }
+    });
+    // `TryFrom` cannot be implemented for every `Shim` implementor at once, as it overlaps with
+    // the blanket implementation of `core`.
+    let shim = shim.map(|x| {
+        quote! {
+            impl ::std::convert::TryFrom<#x> for #ident {
+                type Error = <Self as Shim<#x>>::Error;
 
-    quote! {
-        #item
+                fn try_from(x: #x) -> ::std::result::Result<Self, Self::Error> {
+                    <Self as Shim<#x>>::shim(x)
+                }
+            }
+        }
+    });
 
+    let sealed = sealed.map(|x| quote!(impl #x for #ident {}));
+    let cacheable = cacheable.map(|_| quote!(impl ::nerf::cache::Cacheable for #ident {}));
+    let builder = builder
+        .map(|x| builder::builder(item, &fields, x.validate, x.key.span()))
+        .transpose()?;
+
+    Ok(quote! {
         impl ::nerf::Request for #ident {
             type Response = #response;
+            type ErrorResponse = #error;
         }
 
         impl ::nerf::HttpRequest for #ident {
             fn method(&self) -> ::nerf::http::Method {
-                #method
+                ::nerf::http::Method::#method
+            }
+            fn uri(&self) -> ::std::result::Result<::nerf::http::Uri, ::nerf::Error> {
+                #uri
+                ::nerf::__private::parse_uri(uri)
             }
-            fn uri(&self) -> ::nerf::http::Uri {
-                #endpoint.parse().expect("proc-macro attribute `endpoint` is an invalid HTTP URI")
+            fn endpoint(&self) -> ::std::borrow::Cow<'static, str> {
+                ::std::borrow::Cow::Borrowed(#template)
             }
+            #headers
         }
 
-        impl Sealed for #ident {}
-    }
-    .into()
+        impl ::nerf::timeout::Deadline for #ident {
+            fn timeout(&self) -> ::std::option::Option<::std::time::Duration> {
+                #timeout
+            }
+            fn has_side_effects(&self) -> bool {
+                #side_effects
+            }
+        }
+
+        #sealed
+        #cacheable
+        #signer
+        #shim
+        #builder
+    })
 }
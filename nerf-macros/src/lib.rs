@@ -1,13 +1,17 @@
 extern crate proc_macro;
 
+mod builder;
+mod fields;
 mod http;
 mod rate_limited;
 mod request;
+mod retry;
+mod rpc;
+mod subscription;
 mod tag;
 
 use proc_macro::TokenStream;
 use proc_macro2::Ident;
-use quote::quote;
 use syn::{
     parse::{Parse, ParseStream},
     punctuated::Punctuated,
@@ -77,9 +81,12 @@ where
     }
 }
 
-/// Attribute macro to set 'constant' weight for its rate limit.
+/// Attribute macro to set weights for its rate limit, implementing [`nerf::WeightedRateLimit`].
 ///
-/// For complex conditions, please manually implement [`nerf::WeightedRateLimit`].
+/// - `weight` is required, charged to buckets without their own cost.
+/// - Other keys set the costs of the named buckets, e.g. `order_10s = 1`.
+///
+/// Values are expressions, which can refer to the request with `self`.
 ///
 /// # Example
 ///
@@ -89,14 +96,51 @@ where
 /// struct MyRequest {
 ///     params: String,
 /// }
+///
+/// #[rate_limited(weight = if self.symbol.is_some() { 3 } else { 40 })]
+/// struct GetOpenOrders {
+///     symbol: Option<String>,
+/// }
+///
+/// #[rate_limited(weight = 1, order_10s = 1, order_1d = 1)]
+/// struct PlaceOrder {
+///     symbol: String,
+/// }
 /// ```
 #[proc_macro_attribute]
 pub fn rate_limited(attr: TokenStream, item: TokenStream) -> TokenStream {
     rate_limited::rate_limited(attr, item)
 }
 
+/// Attribute macro to declare whether a request is safe to be sent again, implementing
+/// `nerf::retry::Retryable`.
+///
+/// - `idempotent` allows retries. An optional `max_attempts = N` overrides the policy's maximum.
+/// - `never` forbids retries, e.g. for placing orders.
+///
+/// # Example
+///
+/// ```
+/// # use nerf_macros::retry;
+/// #[retry(idempotent, max_attempts = 5)]
+/// struct GetTicker {
+///     symbol: String,
+/// }
+///
+/// #[retry(never)]
+/// struct PlaceOrder {
+///     symbol: String,
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn retry(attr: TokenStream, item: TokenStream) -> TokenStream {
+    retry::retry(attr, item)
+}
+
 /// Attribute macro to implement `Request`.
 ///
+/// - An optional `error = T` sets `Request::ErrorResponse`, which defaults to `nerf::NoErrorResponse`.
+///
 /// # Example
 ///
 /// ```
@@ -116,8 +160,43 @@ pub fn request(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// Attribute macro to implement `Request` and `HttpRequest` with GET method. Requests parameters are encoded
 /// with [`serde_urlencoded`](https://docs.rs/serde_urlencoded).
 ///
-/// - Endpoint is required with string literal.
-/// - Setting `shim = false` will skip `impl TryFrom` for `Request` newtype.
+/// - Endpoint is required with string literal, either an absolute URI or a path like `/v1/orders`
+///   which is resolved against the base URL of the client. See `nerf::base_url`.
+/// - An optional `error = T` sets `Request::ErrorResponse`, which defaults to `nerf::NoErrorResponse`.
+/// - An optional `signer = T` implements `Signer` with `type Signer = T`, e.g. `signer = Private`.
+/// - An optional `shim = common::X` implements `TryFrom<common::X>` with `Shim<common::X>`, which
+///   cannot be done with a blanket implementation. `Signer` and `Shim` are resolved in the scope
+///   of the item, like `Sealed`.
+/// - An optional `timeout = "800ms"` sets `nerf::timeout::Deadline`, with a unit of `ms`, `s` or `m`.
+/// - An optional `side_effects = false` declares that the request does not change the state of the
+///   server, the default of GET requests only. See `nerf::timeout::Deadline`.
+/// - An optional `sealed = path::to::Sealed` replaces the sealing trait, `Sealed` in scope by default.
+/// - An optional `builder` generates `T::builder()`, see below.
+/// - An optional `cacheable` implements `nerf::cache::Cacheable`, for requests whose responses do
+///   not depend on credentials.
+///
+/// # Field attributes
+///
+/// Fields are serialized by the client by default, into the query string of GET requests and into
+/// the body of others. Each field can be routed with an attribute instead:
+///
+/// - `#[path]` formats the field into its `{field}` placeholder of the endpoint, percent-encoded.
+///   Placeholder fields are path parameters without the attribute too.
+/// - `#[query]` or `#[query("name")]` appends the field to the query string. `None` is omitted.
+/// - `#[header("name")]` sends the field as a header. `None` is omitted.
+/// - `#[body]` marks the field to be serialized into the body, which is not allowed for GET.
+///
+/// Path, query and header fields are excluded from serialization with `#[serde(skip)]`, if the
+/// macro is placed above `#[derive(Serialize)]`.
+///
+/// # Builders
+///
+/// `builder` generates `T::builder()` returning a `TBuilder`, with a setter for each field. Fields
+/// other than `Option`s are required, and `build` is not available until all of them are set.
+/// `Option` fields, whose setters take either `T` or `Option<T>`, and fields marked `#[optional]`,
+/// which start from their `Default`, can be set in any order or left out. `builder(validate)`
+/// makes `build` return `Result<T, <T as nerf::Validate>::Error>`, to check invariants like a
+/// price of limit orders.
 ///
 /// # Example
 ///
@@ -127,35 +206,58 @@ pub fn request(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// #[get("https://ifconfig.me", response = IfconfigResponse)]
 /// struct Ifconfig;
 /// struct IfconfigResponse;
+///
+/// #[get("https://api.example.com/v1/orders/{id}", response = Order)]
+/// #[derive(serde::Serialize)]
+/// struct GetOrder {
+///     id: u64,
+///     #[query]
+///     verbose: Option<bool>,
+///     #[header("x-request-id")]
+///     request_id: String,
+/// }
+/// struct Order;
 /// ```
 #[proc_macro_attribute]
 pub fn get(attr: TokenStream, item: TokenStream) -> TokenStream {
-    http::entrypoint(attr, item, quote! { ::nerf::http::Method::GET })
+    http::entrypoint(attr, item, "GET")
 }
 
 /// Attribute macro to implement (`Request` or `JsonRequest`) and `HttpRequest` with POST method.
 ///
-/// - Endpoint is required with string literal.
-/// - Setting `shim = false` will skip `impl TryFrom` for `Request` newtype.
+/// - Endpoint is required with string literal, either an absolute URI or a path, see [`get`].
+/// - An optional `error = T` sets `Request::ErrorResponse`, which defaults to `nerf::NoErrorResponse`.
+/// - Optional `signer = T` and `shim = common::X` implement `Signer` and `TryFrom`, see [`get`].
+/// - An optional `timeout = "800ms"` sets `nerf::timeout::Deadline`, with a unit of `ms`, `s` or `m`.
+/// - An optional `side_effects = false` declares that the request does not change the state of the
+///   server, the default of GET requests only. See `nerf::timeout::Deadline`.
+/// - Fields can be routed with `#[path]`, `#[query]`, `#[header("...")]` and `#[body]`, see [`get`].
+/// - An optional `builder` or `builder(validate)` generates a typed builder, see [`get`].
 ///
 /// # Example
 ///
 /// ```
 /// # use nerf_macros::post;
 /// # trait Sealed {}
-/// #[post("https://ifconfig.me", response = IfconfigResponse)]
+/// #[post("https://ifconfig.me", response = IfconfigResponse, timeout = "800ms")]
 /// struct Ifconfig;
 /// struct IfconfigResponse;
 /// ```
 #[proc_macro_attribute]
 pub fn post(attr: TokenStream, item: TokenStream) -> TokenStream {
-    http::entrypoint(attr, item, quote! { ::nerf::http::Method::POST })
+    http::entrypoint(attr, item, "POST")
 }
 
 /// Attribute macro to implement (`Request` or `JsonRequest`) and `HttpRequest` with PUT method.
 ///
-/// - Endpoint is required with string literal.
-/// - Setting `shim = false` will skip `impl TryFrom` for `Request` newtype.
+/// - Endpoint is required with string literal, either an absolute URI or a path, see [`get`].
+/// - An optional `error = T` sets `Request::ErrorResponse`, which defaults to `nerf::NoErrorResponse`.
+/// - Optional `signer = T` and `shim = common::X` implement `Signer` and `TryFrom`, see [`get`].
+/// - An optional `timeout = "800ms"` sets `nerf::timeout::Deadline`, with a unit of `ms`, `s` or `m`.
+/// - An optional `side_effects = false` declares that the request does not change the state of the
+///   server, the default of GET requests only. See `nerf::timeout::Deadline`.
+/// - Fields can be routed with `#[path]`, `#[query]`, `#[header("...")]` and `#[body]`, see [`get`].
+/// - An optional `builder` or `builder(validate)` generates a typed builder, see [`get`].
 ///
 /// # Example
 ///
@@ -168,13 +270,19 @@ pub fn post(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// ```
 #[proc_macro_attribute]
 pub fn put(attr: TokenStream, item: TokenStream) -> TokenStream {
-    http::entrypoint(attr, item, quote! { ::nerf::http::Method::PUT })
+    http::entrypoint(attr, item, "PUT")
 }
 
 /// Attribute macro to implement (`Request` or `JsonRequest`) and `HttpRequest` with DELETE method.
 ///
-/// - Endpoint is required with string literal.
-/// - Setting `shim = false` will skip `impl TryFrom` for `Request` newtype.
+/// - Endpoint is required with string literal, either an absolute URI or a path, see [`get`].
+/// - An optional `error = T` sets `Request::ErrorResponse`, which defaults to `nerf::NoErrorResponse`.
+/// - Optional `signer = T` and `shim = common::X` implement `Signer` and `TryFrom`, see [`get`].
+/// - An optional `timeout = "800ms"` sets `nerf::timeout::Deadline`, with a unit of `ms`, `s` or `m`.
+/// - An optional `side_effects = false` declares that the request does not change the state of the
+///   server, the default of GET requests only. See `nerf::timeout::Deadline`.
+/// - Fields can be routed with `#[path]`, `#[query]`, `#[header("...")]` and `#[body]`, see [`get`].
+/// - An optional `builder` or `builder(validate)` generates a typed builder, see [`get`].
 ///
 /// # Example
 ///
@@ -187,7 +295,96 @@ pub fn put(attr: TokenStream, item: TokenStream) -> TokenStream {
 /// ```
 #[proc_macro_attribute]
 pub fn delete(attr: TokenStream, item: TokenStream) -> TokenStream {
-    http::entrypoint(attr, item, quote! { ::nerf::http::Method::DELETE })
+    http::entrypoint(attr, item, "DELETE")
+}
+
+/// Derive macro to implement `Request` and `HttpRequest`, like [`get`] and the other HTTP macros
+/// but without the `Sealed` trait in scope.
+///
+/// The `#[http(...)]` attribute of the item takes the keys of [`get`], with the method and the
+/// endpoint as one of `get = "..."`, `post = "..."`, `put = "..."` or `delete = "..."`. An
+/// optional `sealed = path::to::Sealed` implements the sealing trait, which is not implemented
+/// otherwise. The attribute macros accept `sealed` too, which defaults to `Sealed` for them.
+///
+/// Fields are routed with `#[http(path)]`, `#[http(query)]`, `#[http(query("name"))]`,
+/// `#[http(header("name"))]` and `#[http(body)]`, and marked with `#[http(optional)]` for
+/// builders. A derive macro cannot modify the item, so path, query and header fields must be
+/// `#[serde(skip)]` by hand.
+///
+/// # Example
+///
+/// ```
+/// # use nerf_macros::HttpRequest;
+/// mod private {
+///     pub trait Sealed {}
+/// }
+///
+/// #[derive(HttpRequest, serde::Serialize)]
+/// #[http(get = "/v1/orders/{id}", response = Order, sealed = private::Sealed)]
+/// struct GetOrder {
+///     #[serde(skip)]
+///     id: u64,
+///     #[http(header("x-request-id"))]
+///     #[serde(skip)]
+///     request_id: String,
+///     verbose: Option<bool>,
+/// }
+/// struct Order;
+/// ```
+#[proc_macro_derive(HttpRequest, attributes(http))]
+pub fn derive_http_request(item: TokenStream) -> TokenStream {
+    http::derive(item)
+}
+
+/// Attribute macro to implement `Request` and `nerf::jsonrpc::RpcRequest`. The request is serialized
+/// into `params` of the JSON-RPC request object.
+///
+/// - `method = "..."` and `response = T` are required.
+/// - An optional `error = T` sets `Request::ErrorResponse`, which is deserialized from the `error`
+///   object and defaults to `nerf::jsonrpc::ErrorObject`.
+///
+/// `nerf::timeout::Deadline` is implemented with the defaults, which assume side effects.
+///
+/// # Example
+///
+/// ```
+/// # use nerf_macros::rpc;
+/// #[derive(serde::Serialize)]
+/// #[rpc(method = "public/get_order_book", response = OrderBook)]
+/// struct GetOrderBook {
+///     instrument_name: String,
+/// }
+///
+/// #[derive(serde::Deserialize)]
+/// struct OrderBook;
+/// ```
+#[proc_macro_attribute]
+pub fn rpc(attr: TokenStream, item: TokenStream) -> TokenStream {
+    rpc::rpc(attr, item)
+}
+
+/// Attribute macro to implement `nerf::ws::Subscription`.
+///
+/// - Topic is required with string literal. Fields can be formatted into the topic with
+///   `{field_name}`, like endpoints of [`get`].
+/// - `message = T` sets `Subscription::Message`, the type of pushed messages.
+///
+/// # Example
+///
+/// ```
+/// # use nerf_macros::subscription;
+/// #[derive(serde::Serialize)]
+/// #[subscription("{symbol}@trade", message = Trade)]
+/// struct Trades {
+///     symbol: String,
+/// }
+///
+/// #[derive(serde::Deserialize)]
+/// struct Trade;
+/// ```
+#[proc_macro_attribute]
+pub fn subscription(attr: TokenStream, item: TokenStream) -> TokenStream {
+    subscription::subscription(attr, item)
 }
 
 /// Attribute macro to add a 'tag' to a type.
@@ -2,37 +2,74 @@ use proc_macro::TokenStream;
 use quote::quote;
 use syn::{
     parse::{Parse, ParseStream},
-    parse_macro_input, Ident, LitInt, Token,
+    parse_macro_input, Expr, Ident, LitStr, Token,
 };
 
 use crate::NamedItem;
 
 struct RateLimitedAttr {
-    weight: u64,
+    weight: Expr,
+    costs: Vec<(Ident, Expr)>,
 }
 
 impl Parse for RateLimitedAttr {
     fn parse(input: ParseStream) -> syn::Result<Self> {
-        let weight_ident: Ident = input.parse()?;
-        if weight_ident.to_string().as_str() != "weight" {
-            return Err(syn::Error::new(
-                weight_ident.span(),
-                format!("Expected `weight`, got {weight_ident}"),
-            ));
+        let items = input.parse_terminated::<_, Token![,]>(RateLimitedItem::parse)?;
+        let mut weight = None;
+        let mut costs: Vec<(Ident, Expr)> = Vec::new();
+        for RateLimitedItem { key, value } in items {
+            if key == "weight" {
+                if weight.replace(value).is_some() {
+                    return Err(syn::Error::new(key.span(), "Duplicated attribute item"));
+                }
+            } else if costs.iter().any(|(x, _)| *x == key) {
+                return Err(syn::Error::new(key.span(), "Duplicated attribute item"));
+            } else {
+                costs.push((key, value));
+            }
         }
-        let _ = input.parse::<Token![=]>()?;
-        let weight_value: LitInt = input.parse()?;
+        let weight = weight.ok_or_else(|| syn::Error::new(input.span(), "weight is required"))?;
+        Ok(Self { weight, costs })
+    }
+}
+
+/// `weight = expr` or `bucket_name = expr`.
+struct RateLimitedItem {
+    key: Ident,
+    value: Expr,
+}
+
+impl Parse for RateLimitedItem {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let key: Ident = input.parse()?;
+        input
+            .parse::<Token![=]>()
+            .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
         Ok(Self {
-            weight: weight_value.base10_parse()?,
+            key,
+            value: input.parse()?,
         })
     }
 }
 
 pub fn rate_limited(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let RateLimitedAttr { weight } = parse_macro_input!(attr as RateLimitedAttr);
+    let RateLimitedAttr { weight, costs } = parse_macro_input!(attr as RateLimitedAttr);
     let item_ = item.clone();
     let NamedItem { ident } = parse_macro_input!(item_ as NamedItem);
     let item = proc_macro2::TokenStream::from(item);
+    let costs = if costs.is_empty() {
+        quote!()
+    } else {
+        let costs = costs.iter().map(|(key, value)| {
+            let name = LitStr::new(&key.to_string(), key.span());
+            quote!((#name, #value))
+        });
+        quote! {
+            fn costs(&self) -> ::std::vec::Vec<(&'static str, u64)> {
+                ::std::vec![#(#costs),*]
+            }
+        }
+    };
     quote! {
         #item
 
@@ -40,6 +77,7 @@ pub fn rate_limited(attr: TokenStream, item: TokenStream) -> TokenStream {
             fn weight(&self) -> u64 {
                 #weight
             }
+            #costs
         }
     }
     .into()
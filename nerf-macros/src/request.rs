@@ -3,13 +3,14 @@ use proc_macro2::Ident;
 use quote::quote;
 use syn::{
     parse::{Parse, ParseStream},
-    parse_macro_input, Token,
+    parse_macro_input, Token, Type,
 };
 
 use crate::NamedItem;
 
 struct RequestAttr {
     resp_ty: Ident,
+    error_ty: Option<Type>,
 }
 
 impl Parse for RequestAttr {
@@ -23,12 +24,35 @@ impl Parse for RequestAttr {
         }
         let _ = input.parse::<Token![=]>()?;
         let resp_ty = input.parse()?;
-        Ok(Self { resp_ty })
+        if input.is_empty() {
+            return Ok(Self {
+                resp_ty,
+                error_ty: None,
+            });
+        }
+
+        let _ = input.parse::<Token![,]>()?;
+        let error_ident: Ident = input.parse()?;
+        if error_ident.to_string().as_str() != "error" {
+            return Err(syn::Error::new(
+                error_ident.span(),
+                format!("Expected `error`, got {error_ident}"),
+            ));
+        }
+        let _ = input.parse::<Token![=]>()?;
+        let error_ty = input.parse()?;
+        Ok(Self {
+            resp_ty,
+            error_ty: Some(error_ty),
+        })
     }
 }
 
 pub fn request(attr: TokenStream, item: TokenStream) -> TokenStream {
-    let RequestAttr { resp_ty } = parse_macro_input!(attr as RequestAttr);
+    let RequestAttr { resp_ty, error_ty } = parse_macro_input!(attr as RequestAttr);
+    let error_ty = error_ty
+        .map(|x| quote!(#x))
+        .unwrap_or_else(|| quote!(::nerf::NoErrorResponse));
     let item_ = item.clone();
     let NamedItem { ident } = parse_macro_input!(item_ as NamedItem);
     let item = proc_macro2::TokenStream::from(item);
@@ -37,6 +61,7 @@ pub fn request(attr: TokenStream, item: TokenStream) -> TokenStream {
 
         impl ::nerf::Request for #ident {
             type Response = #resp_ty;
+            type ErrorResponse = #error_ty;
         }
     }
     .into()
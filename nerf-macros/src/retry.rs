@@ -0,0 +1,74 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, Ident, LitInt, Token,
+};
+
+use crate::NamedItem;
+
+enum RetryAttr {
+    Never,
+    Idempotent { max_attempts: Option<u32> },
+}
+
+impl Parse for RetryAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let behavior: Ident = input.parse()?;
+        match behavior.to_string().as_str() {
+            "never" => Ok(Self::Never),
+            "idempotent" => {
+                if input.is_empty() {
+                    return Ok(Self::Idempotent { max_attempts: None });
+                }
+                let _ = input.parse::<Token![,]>()?;
+                let max_attempts_ident: Ident = input.parse()?;
+                if max_attempts_ident.to_string().as_str() != "max_attempts" {
+                    return Err(syn::Error::new(
+                        max_attempts_ident.span(),
+                        format!("Expected `max_attempts`, got {max_attempts_ident}"),
+                    ));
+                }
+                let _ = input.parse::<Token![=]>()?;
+                let max_attempts: LitInt = input.parse()?;
+                Ok(Self::Idempotent {
+                    max_attempts: Some(max_attempts.base10_parse()?),
+                })
+            }
+            _ => Err(syn::Error::new(
+                behavior.span(),
+                format!("Expected `idempotent` or `never`, got {behavior}"),
+            )),
+        }
+    }
+}
+
+pub fn retry(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let attr = parse_macro_input!(attr as RetryAttr);
+    let item_ = item.clone();
+    let NamedItem { ident } = parse_macro_input!(item_ as NamedItem);
+    let item = proc_macro2::TokenStream::from(item);
+    let behavior = match attr {
+        RetryAttr::Never => quote! { ::nerf::retry::RetryBehavior::Never },
+        RetryAttr::Idempotent { max_attempts: None } => quote! {
+            ::nerf::retry::RetryBehavior::Idempotent { max_attempts: ::std::option::Option::None }
+        },
+        RetryAttr::Idempotent {
+            max_attempts: Some(max_attempts),
+        } => quote! {
+            ::nerf::retry::RetryBehavior::Idempotent {
+                max_attempts: ::std::option::Option::Some(#max_attempts),
+            }
+        },
+    };
+    quote! {
+        #item
+
+        impl ::nerf::retry::Retryable for #ident {
+            fn retry_behavior(&self) -> ::nerf::retry::RetryBehavior {
+                #behavior
+            }
+        }
+    }
+    .into()
+}
@@ -0,0 +1,89 @@
+use proc_macro::TokenStream;
+use proc_macro2::Ident;
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, parse_quote, DeriveInput, LitStr, Token, Type,
+};
+
+struct RpcAttr {
+    method: LitStr,
+    response: Type,
+    error: Option<Type>,
+}
+
+impl Parse for RpcAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut method = None;
+        let mut response = None;
+        let mut error = None;
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            input
+                .parse::<Token![=]>()
+                .map_err(|e| syn::Error::new(e.span(), "expected `=`"))?;
+            let duplicated = match key.to_string().as_str() {
+                "method" => method.replace(input.parse()?).is_some(),
+                "response" => response.replace(input.parse()?).is_some(),
+                "error" => error.replace(input.parse()?).is_some(),
+                other => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!("unexpected key {other}"),
+                    ))
+                }
+            };
+            if duplicated {
+                return Err(syn::Error::new(key.span(), format!("duplicated key {key}")));
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+
+        Ok(Self {
+            method: method.ok_or_else(|| syn::Error::new(input.span(), "method is required"))?,
+            response: response
+                .ok_or_else(|| syn::Error::new(input.span(), "response is required"))?,
+            error,
+        })
+    }
+}
+
+pub fn rpc(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let RpcAttr {
+        method,
+        response,
+        error,
+    } = parse_macro_input!(attr as RpcAttr);
+    let error = error
+        .map(|x| quote!(#x))
+        .unwrap_or_else(|| quote!(::nerf::jsonrpc::ErrorObject));
+    let item = parse_macro_input!(item as DeriveInput);
+    let ident = &item.ident;
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+    // `Serialize` of generic requests is bounded on their type parameters
+    let mut serializable = item.generics.clone();
+    serializable
+        .make_where_clause()
+        .predicates
+        .push(parse_quote!(Self: ::nerf::serde::Serialize));
+    let (_, _, serializable) = serializable.split_for_impl();
+    quote! {
+        #item
+
+        impl #impl_generics ::nerf::Request for #ident #ty_generics #where_clause {
+            type Response = #response;
+            type ErrorResponse = #error;
+        }
+
+        impl #impl_generics ::nerf::timeout::Deadline for #ident #ty_generics #where_clause {}
+
+        impl #impl_generics ::nerf::jsonrpc::RpcRequest for #ident #ty_generics #serializable {
+            fn method(&self) -> &'static str {
+                #method
+            }
+        }
+    }
+    .into()
+}
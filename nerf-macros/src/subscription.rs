@@ -0,0 +1,67 @@
+use proc_macro::TokenStream;
+use proc_macro2::Ident;
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, LitStr, Token, Type,
+};
+
+use crate::{http::parse_endpoint, NamedItem};
+
+struct SubscriptionAttr {
+    topic: LitStr,
+    message: Type,
+}
+
+impl Parse for SubscriptionAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let topic = input.parse()?;
+        let _ = input.parse::<Token![,]>()?;
+        let message_ident: Ident = input.parse()?;
+        if message_ident.to_string().as_str() != "message" {
+            return Err(syn::Error::new(
+                message_ident.span(),
+                format!("Expected `message`, got {message_ident}"),
+            ));
+        }
+        let _ = input.parse::<Token![=]>()?;
+        let message = input.parse()?;
+        Ok(Self { topic, message })
+    }
+}
+
+pub fn subscription(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let SubscriptionAttr { topic, message } = parse_macro_input!(attr as SubscriptionAttr);
+    let item_ = item.clone();
+    let NamedItem { ident } = parse_macro_input!(item_ as NamedItem);
+    let item = proc_macro2::TokenStream::from(item);
+
+    if topic.value().contains("{}") {
+        return syn::Error::new(topic.span(), "topic must not contain `{}`\nIf you meant a place for format arguments, use `{field_name}` instead")
+            .into_compile_error()
+            .into();
+    }
+
+    let (sub, args) = parse_endpoint(topic.value());
+    let args = args
+        .into_iter()
+        .map(|arg| {
+            let ident = Ident::new(&arg, topic.span());
+            quote!(self.#ident)
+        })
+        .collect::<Vec<_>>();
+    let sub = LitStr::new(&sub, topic.span());
+
+    quote! {
+        #item
+
+        impl ::nerf::ws::Subscription for #ident {
+            type Message = #message;
+
+            fn topic(&self) -> ::std::string::String {
+                format!(#sub, #(#args),*)
+            }
+        }
+    }
+    .into()
+}
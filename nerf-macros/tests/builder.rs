@@ -0,0 +1,78 @@
+use nerf::HttpRequest;
+use nerf_macros::{get, post};
+use serde::Serialize;
+
+#[allow(dead_code)]
+trait Sealed {}
+
+#[derive(Debug, PartialEq, Serialize)]
+enum OrderType {
+    Limit,
+    Market,
+}
+
+#[post("/orders", response = (), builder(validate))]
+#[derive(Debug, Serialize)]
+struct PlaceOrder {
+    symbol: String,
+    order_type: OrderType,
+    price: Option<u64>,
+    client_id: Option<String>,
+    #[optional]
+    reduce_only: bool,
+}
+
+impl nerf::Validate for PlaceOrder {
+    type Error = &'static str;
+
+    fn validate(&self) -> Result<(), Self::Error> {
+        match (&self.order_type, self.price) {
+            (OrderType::Limit, None) => Err("price is required for limit orders"),
+            (OrderType::Market, Some(_)) => Err("price is not allowed for market orders"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[get("/orders/{id}", response = (), builder)]
+#[derive(Serialize)]
+struct GetOrder {
+    id: u64,
+    #[query]
+    verbose: Option<bool>,
+}
+
+#[test]
+fn test_builder() {
+    let req = PlaceOrder::builder()
+        .price(100)
+        .order_type(OrderType::Limit)
+        .symbol("BTCUSDT")
+        .build()
+        .unwrap();
+    assert_eq!(req.symbol, "BTCUSDT");
+    assert_eq!(req.price, Some(100));
+    assert_eq!(req.client_id, None);
+    assert!(!req.reduce_only);
+
+    let req = PlaceOrder::builder()
+        .symbol("BTCUSDT")
+        .order_type(OrderType::Market)
+        .price(None)
+        .client_id(String::from("abc"))
+        .reduce_only(true)
+        .build()
+        .unwrap();
+    assert_eq!(req.client_id.as_deref(), Some("abc"));
+    assert!(req.reduce_only);
+
+    let err = PlaceOrder::builder()
+        .symbol("BTCUSDT")
+        .order_type(OrderType::Limit)
+        .build()
+        .unwrap_err();
+    assert_eq!(err, "price is required for limit orders");
+
+    let req = GetOrder::builder().id(7u8).verbose(true).build();
+    assert_eq!(req.uri().unwrap(), "/orders/7?verbose=true");
+}
@@ -0,0 +1,72 @@
+use nerf::{timeout::Deadline, HttpRequest, Request};
+use serde::Serialize;
+
+mod private {
+    pub trait Sealed {}
+}
+
+#[derive(HttpRequest, Serialize)]
+#[http(get = "/markets/{market}/orders", response = Vec<u64>, sealed = private::Sealed)]
+struct GetOrders {
+    #[serde(skip)]
+    market: String,
+    #[http(query("orderId"))]
+    #[serde(skip)]
+    order_id: u64,
+    #[http(header("X-Request-Id"))]
+    #[serde(skip)]
+    request_id: Option<String>,
+    limit: u32,
+}
+
+#[derive(HttpRequest, Serialize)]
+#[http(delete = "https://example.com/orders/{id}", response = (), timeout = "5s")]
+struct CancelOrder {
+    #[http(path)]
+    #[serde(skip)]
+    id: u64,
+}
+
+#[derive(HttpRequest, Serialize)]
+#[http(post = "/orders/query", response = (), side_effects = false)]
+struct QueryOrders {
+    market: String,
+}
+
+#[test]
+fn test_derive() {
+    fn sealed<T: private::Sealed + Request<Response = Vec<u64>>>() {}
+    sealed::<GetOrders>();
+
+    let req = GetOrders {
+        market: String::from("BTC/USDT"),
+        order_id: 42,
+        request_id: Some(String::from("abc")),
+        limit: 10,
+    };
+    assert_eq!(req.method(), "GET");
+    assert_eq!(req.uri().unwrap(), "/markets/BTC%2FUSDT/orders?orderId=42");
+    assert_eq!(req.endpoint(), "/markets/{market}/orders");
+    assert_eq!(req.headers().unwrap()["x-request-id"], "abc");
+    assert_eq!(
+        nerf::serde_json::to_string(&req).unwrap(),
+        r#"{"limit":10}"#
+    );
+    assert!(!req.has_side_effects());
+
+    let req = CancelOrder { id: 7 };
+    assert_eq!(req.method(), "DELETE");
+    assert_eq!(req.uri().unwrap(), "https://example.com/orders/7");
+    assert_eq!(req.timeout(), Some(std::time::Duration::from_secs(5)));
+    assert!(req.has_side_effects());
+
+    assert!(!QueryOrders {
+        market: String::from("BTC/USDT")
+    }
+    .has_side_effects());
+}
+
+#[test]
+fn ui() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,146 @@
+use nerf::HttpRequest;
+use nerf_macros::{get, post};
+use serde::Serialize;
+
+#[allow(dead_code)]
+trait Sealed {}
+
+trait Signer {
+    type Signer;
+}
+
+struct Private;
+
+trait Shim<T>: Sized {
+    type Error;
+    fn shim(x: T) -> Result<Self, Self::Error>;
+}
+
+/// A request in common, converted into [`PostOrder`].
+struct NewOrder {
+    price: u64,
+}
+
+#[get("https://example.com/markets/{market}/orders", response = ())]
+#[derive(Serialize)]
+struct GetOrders {
+    market: String,
+    #[query]
+    limit: Option<u32>,
+    #[query("orderId")]
+    order_id: u64,
+    #[header("X-Request-Id")]
+    request_id: Option<String>,
+}
+
+#[post("https://example.com/orders/{kind}", response = (), signer = Private, shim = NewOrder)]
+#[derive(Serialize)]
+struct PostOrder {
+    kind: &'static str,
+    #[body]
+    price: u64,
+}
+
+#[get("/markets/{market}/ticker", response = (), cacheable)]
+#[derive(Serialize)]
+struct GetTicker {
+    market: String,
+}
+
+#[get("https://example.com/orders?type=spot", response = ())]
+#[derive(Serialize)]
+struct GetSpotOrders {
+    #[query]
+    market: &'static str,
+    #[header("Idempotency-Key")]
+    key: &'static str,
+    limit: u32,
+}
+
+impl Shim<NewOrder> for PostOrder {
+    type Error = &'static str;
+
+    fn shim(x: NewOrder) -> Result<Self, Self::Error> {
+        if x.price == 0 {
+            return Err("zero price");
+        }
+        Ok(Self {
+            kind: "limit",
+            price: x.price,
+        })
+    }
+}
+
+#[test]
+fn test_field_attributes() {
+    let req = GetOrders {
+        market: String::from("BTC/USDT"),
+        limit: None,
+        order_id: 42,
+        request_id: Some(String::from("abc")),
+    };
+    assert_eq!(
+        req.uri().unwrap(),
+        "https://example.com/markets/BTC%2FUSDT/orders?orderId=42"
+    );
+    assert_eq!(
+        req.endpoint(),
+        "https://example.com/markets/{market}/orders"
+    );
+    assert_eq!(req.headers().unwrap()["x-request-id"], "abc");
+    // Every field is routed elsewhere
+    assert_eq!(nerf::serde_json::to_string(&req).unwrap(), "{}");
+
+    let req = PostOrder {
+        kind: "limit",
+        price: 100,
+    };
+    assert_eq!(req.uri().unwrap(), "https://example.com/orders/limit");
+    assert!(req.headers().unwrap().is_empty());
+    assert_eq!(
+        nerf::serde_json::to_string(&req).unwrap(),
+        r#"{"price":100}"#
+    );
+}
+
+#[test]
+fn test_signer_and_shim() {
+    fn signer<T: Signer<Signer = Private>>() {}
+    signer::<PostOrder>();
+
+    let req = PostOrder::try_from(NewOrder { price: 100 }).unwrap();
+    assert_eq!(req.uri().unwrap(), "https://example.com/orders/limit");
+    assert!(PostOrder::try_from(NewOrder { price: 0 }).is_err());
+}
+
+#[test]
+fn test_relative_endpoint() {
+    let req = GetTicker {
+        market: String::from("BTC-USDT"),
+    };
+    assert_eq!(req.uri().unwrap(), "/markets/BTC-USDT/ticker");
+    assert_eq!(req.endpoint(), "/markets/{market}/ticker");
+
+    fn cacheable<T: nerf::cache::Cacheable>() {}
+    cacheable::<GetTicker>();
+}
+
+#[test]
+fn test_existing_query() {
+    let req = GetSpotOrders {
+        market: "BTC-USDT",
+        key: "abc",
+        limit: 10,
+    };
+    assert_eq!(
+        req.uri().unwrap(),
+        "https://example.com/orders?type=spot&market=BTC-USDT"
+    );
+    let params = nerf::serde_json::to_value(&req).unwrap();
+    assert_eq!(params, nerf::serde_json::json!({ "limit": 10 }));
+    assert_eq!(
+        req.uri_with_query("limit=10").unwrap(),
+        "https://example.com/orders?type=spot&market=BTC-USDT&limit=10"
+    );
+    assert_eq!(req.headers().unwrap()["idempotency-key"], "abc");
+}
@@ -0,0 +1,26 @@
+use nerf::WeightedRateLimit;
+use nerf_macros::rate_limited;
+
+#[rate_limited(weight = if self.symbol.is_some() { 3 } else { 40 })]
+struct GetOpenOrders {
+    symbol: Option<String>,
+}
+
+#[rate_limited(weight = 1, order_10s = 1, order_1d = self.quantity / 100)]
+struct PlaceOrder {
+    quantity: u64,
+}
+
+#[test]
+fn test_rate_limited() {
+    let req = GetOpenOrders {
+        symbol: Some(String::from("BTCUSDT")),
+    };
+    assert_eq!(req.weight(), 3);
+    assert!(req.costs().is_empty());
+    assert_eq!(GetOpenOrders { symbol: None }.weight(), 40);
+
+    let req = PlaceOrder { quantity: 500 };
+    assert_eq!(req.weight(), 1);
+    assert_eq!(req.costs(), [("order_10s", 1), ("order_1d", 5)]);
+}
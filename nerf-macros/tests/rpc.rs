@@ -0,0 +1,36 @@
+use nerf::{jsonrpc::RpcRequest, timeout::Deadline};
+use nerf_macros::rpc;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize)]
+#[rpc(method = "public/get_instruments", response = Vec<Instrument>)]
+struct GetInstruments<'a> {
+    currency: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Instrument;
+
+#[derive(Serialize)]
+#[rpc(method = "private/edit", response = T)]
+struct Edit<T>
+where
+    T: Clone,
+{
+    order: T,
+}
+
+fn response_of<T: nerf::Request>(_: &T) -> Option<T::Response> {
+    None
+}
+
+#[test]
+fn test_generic_rpc() {
+    let req = GetInstruments { currency: "BTC" };
+    assert_eq!(req.method(), "public/get_instruments");
+    assert!(req.has_side_effects());
+
+    let req = Edit { order: 1u64 };
+    assert_eq!(req.method(), "private/edit");
+    let _: Option<u64> = response_of(&req);
+}
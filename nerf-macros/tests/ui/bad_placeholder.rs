@@ -0,0 +1,18 @@
+use nerf_macros::HttpRequest;
+
+#[derive(HttpRequest, serde::Serialize)]
+#[http(get = "/orders/{order_id}", response = ())]
+struct GetOrder {
+    #[serde(skip)]
+    id: u64,
+}
+
+#[derive(HttpRequest, serde::Serialize)]
+#[http(get = "/orders/{id}", response = ())]
+struct GetTrades {
+    #[http(query)]
+    #[serde(skip)]
+    id: u64,
+}
+
+fn main() {}
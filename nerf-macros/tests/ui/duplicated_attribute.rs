@@ -0,0 +1,16 @@
+use nerf_macros::HttpRequest;
+
+#[derive(HttpRequest)]
+#[http(get = "/orders", post = "/orders", response = ())]
+struct GetOrders;
+
+#[derive(HttpRequest, serde::Serialize)]
+#[http(get = "/orders", response = ())]
+struct GetOrder {
+    #[http(query)]
+    #[http(header("x-order-id"))]
+    #[serde(skip)]
+    id: u64,
+}
+
+fn main() {}
@@ -0,0 +1,7 @@
+use nerf_macros::HttpRequest;
+
+#[derive(HttpRequest)]
+#[http("/orders", response = ())]
+struct GetOrders;
+
+fn main() {}
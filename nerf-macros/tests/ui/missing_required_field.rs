@@ -0,0 +1,13 @@
+use nerf_macros::HttpRequest;
+
+#[derive(HttpRequest, serde::Serialize)]
+#[http(post = "/orders", response = (), builder)]
+struct PlaceOrder {
+    symbol: String,
+    quantity: u64,
+    price: Option<u64>,
+}
+
+fn main() {
+    PlaceOrder::builder().symbol("BTCUSDT").price(100u64).build();
+}
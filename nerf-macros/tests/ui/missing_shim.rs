@@ -0,0 +1,14 @@
+use nerf_macros::HttpRequest;
+
+trait Shim<T>: Sized {
+    type Error;
+    fn shim(x: T) -> Result<Self, Self::Error>;
+}
+
+struct GetAllOrders;
+
+#[derive(HttpRequest)]
+#[http(get = "/orders", response = (), shim = GetAllOrders)]
+struct GetOrders;
+
+fn main() {}
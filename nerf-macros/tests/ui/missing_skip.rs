@@ -0,0 +1,16 @@
+use nerf_macros::HttpRequest;
+
+#[derive(HttpRequest, serde::Serialize)]
+#[http(get = "/orders/{id}", response = ())]
+struct GetOrder {
+    id: u64,
+}
+
+#[derive(HttpRequest, serde::Serialize)]
+#[http(get = "/orders", response = ())]
+struct GetOrders {
+    #[http(query)]
+    limit: u32,
+}
+
+fn main() {}
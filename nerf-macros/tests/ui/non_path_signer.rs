@@ -0,0 +1,7 @@
+use nerf_macros::HttpRequest;
+
+#[derive(HttpRequest)]
+#[http(get = "/orders", response = (), signer = &'static str)]
+struct GetOrders;
+
+fn main() {}
@@ -0,0 +1,10 @@
+use nerf_macros::HttpRequest;
+
+#[derive(HttpRequest, serde::Serialize)]
+#[http(get = "/orders", response = ())]
+struct GetOrders {
+    #[http(params)]
+    limit: u32,
+}
+
+fn main() {}
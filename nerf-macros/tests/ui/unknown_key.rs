@@ -0,0 +1,7 @@
+use nerf_macros::HttpRequest;
+
+#[derive(HttpRequest)]
+#[http(get = "/orders", response = (), retries = 3)]
+struct GetOrders;
+
+fn main() {}
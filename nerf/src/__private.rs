@@ -0,0 +1,70 @@
+//! Helpers of the code generated by `nerf_macros`. Not a public API.
+
+use std::fmt::{self, Display, Write};
+
+use http::{header::HeaderName, HeaderMap, HeaderValue, Uri};
+use serde::Serialize;
+
+use crate::Error;
+
+/// Formats a path parameter, percent-encoding every byte but unreserved characters.
+pub struct PathParam<'a, T: ?Sized>(pub &'a T);
+
+impl<T: Display + ?Sized> Display for PathParam<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0.to_string().bytes() {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                f.write_char(b as char)?;
+            } else {
+                write!(f, "%{b:02X}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Appends a query parameter to `uri`, or nothing if `value` is `None`.
+pub fn append_query<T: Serialize + ?Sized>(
+    uri: &mut String,
+    name: &str,
+    value: &T,
+) -> Result<(), Error> {
+    let pair = serde_urlencoded::to_string([(name, value)]).map_err(Error::SerializeUrlencoded)?;
+    if !pair.is_empty() {
+        uri.push(if uri.contains('?') { '&' } else { '?' });
+        uri.push_str(&pair);
+    }
+    Ok(())
+}
+
+pub fn parse_uri(uri: String) -> Result<Uri, Error> {
+    Uri::try_from(uri).map_err(|e| Error::ConstructRequest(e.into()))
+}
+
+/// Inserts a header. `name` is validated by the macros.
+pub fn insert_header<T: Display + ?Sized>(
+    headers: &mut HeaderMap,
+    name: &'static str,
+    value: &T,
+) -> Result<(), Error> {
+    let value =
+        HeaderValue::try_from(value.to_string()).map_err(|e| Error::ConstructRequest(e.into()))?;
+    headers.insert(HeaderName::from_static(name), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_parameters() {
+        assert_eq!(PathParam("BTC/USDT 1").to_string(), "BTC%2FUSDT%201");
+
+        let mut uri = String::from("https://example.com/orders");
+        append_query(&mut uri, "symbol", "BTC USDT").unwrap();
+        append_query(&mut uri, "limit", &None::<u32>).unwrap();
+        append_query(&mut uri, "limit", &Some(10)).unwrap();
+        assert_eq!(uri, "https://example.com/orders?symbol=BTC+USDT&limit=10");
+    }
+}
@@ -0,0 +1,86 @@
+//! Base URLs to point clients at another environment, e.g. a testnet or a local mock server.
+//!
+//! Endpoints of the HTTP macros may be relative paths like `/api/v3/depth`, which are resolved
+//! against the [`BaseUrl`] of the client when the request is built.
+
+use std::{fmt::Display, str::FromStr};
+
+use http::Uri;
+use thiserror::Error;
+
+use crate::Error;
+
+/// The URL is not an absolute URL without a query, e.g. `https://testnet.binance.vision`.
+#[derive(Error, Debug)]
+#[error("invalid base URL {0:?}, expected an absolute URL without a query")]
+pub struct InvalidBaseUrl(String);
+
+/// The scheme, the host and an optional path prefix of an API.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BaseUrl(String);
+
+impl BaseUrl {
+    /// Parses a static base URL.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `x` is not a valid base URL, like [`Uri::from_static`].
+    pub fn from_static(x: &'static str) -> Self {
+        x.parse().expect("static base URL is not valid")
+    }
+
+    /// Resolves an endpoint, appending its path and query to the base URL. Absolute endpoints
+    /// have their scheme and host replaced.
+    pub fn join(&self, uri: &Uri) -> Result<Uri, Error> {
+        let path = uri.path_and_query().map_or("/", |x| x.as_str());
+        let path = if path.starts_with('/') {
+            path.to_string()
+        } else {
+            format!("/{path}")
+        };
+        Uri::try_from(format!("{}{path}", self.0)).map_err(|e| Error::ConstructRequest(e.into()))
+    }
+}
+
+impl FromStr for BaseUrl {
+    type Err = InvalidBaseUrl;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let uri: Uri = s.parse().map_err(|_| InvalidBaseUrl(s.to_string()))?;
+        if uri.scheme().is_none() || uri.authority().is_none() || uri.query().is_some() {
+            return Err(InvalidBaseUrl(s.to_string()));
+        }
+        Ok(Self(s.trim_end_matches('/').to_string()))
+    }
+}
+
+impl Display for BaseUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_endpoints() {
+        let base = BaseUrl::from_static("http://localhost:8080/binance/");
+        assert_eq!(base.to_string(), "http://localhost:8080/binance");
+
+        let relative = Uri::from_static("/api/v3/depth?symbol=BTCUSDT");
+        assert_eq!(
+            base.join(&relative).unwrap(),
+            "http://localhost:8080/binance/api/v3/depth?symbol=BTCUSDT"
+        );
+        let absolute = Uri::from_static("https://api.binance.com/api/v3/time");
+        assert_eq!(
+            base.join(&absolute).unwrap(),
+            "http://localhost:8080/binance/api/v3/time"
+        );
+
+        assert!("/api".parse::<BaseUrl>().is_err());
+        assert!("https://example.com?x=1".parse::<BaseUrl>().is_err());
+    }
+}
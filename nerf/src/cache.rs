@@ -0,0 +1,374 @@
+//! Caching responses of public requests with [`CacheLayer`].
+//!
+//! Requests are cached only if their types opted in with [`CacheLayer::with_ttl`], keyed on their
+//! [`HttpRequest::uri`], headers and serialized body. Place the layer outside of
+//! [`RateLimit`](crate::rate_limit::RateLimit), so that cached responses do not charge weights.
+//!
+//! Only [`Cacheable`] requests can opt in, as the key does not include the credentials added by
+//! signers.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use pin_project::pin_project;
+use serde::Serialize;
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+use crate::HttpRequest;
+
+pub(crate) type Key = (TypeId, String);
+
+/// Requests whose responses do not depend on credentials, e.g. public market data.
+///
+/// Implemented by the HTTP attribute macros with `cacheable`, e.g. [`nerf_macros::get`].
+pub trait Cacheable {}
+
+/// Returns the key of a request, or `None` if it cannot be encoded, to be sent as is and fail.
+pub(crate) fn request_key<Req>(x: &Req) -> Option<Key>
+where
+    Req: HttpRequest + Serialize + 'static,
+{
+    let body = serde_json::to_string(x).ok()?;
+    let mut headers = x
+        .headers()
+        .ok()?
+        .iter()
+        .map(|(name, value)| format!("{name}: {value:?}"))
+        .collect::<Vec<_>>();
+    headers.sort();
+    Some((
+        TypeId::of::<Req>(),
+        format!("{} {:?} {}", x.uri().ok()?, headers, body),
+    ))
+}
+
+struct Entry {
+    expires: Instant,
+    response: Box<dyn Any + Send>,
+}
+
+type Entries = Arc<Mutex<HashMap<Key, Entry>>>;
+
+/// A [`Layer`] caching responses of opted-in request types for their TTLs.
+///
+/// Services of the same layer share their cache.
+#[derive(Clone, Default)]
+pub struct CacheLayer {
+    ttls: Arc<HashMap<TypeId, Duration>>,
+    entries: Entries,
+}
+
+impl CacheLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caches responses of `Req` for `ttl`.
+    pub fn with_ttl<Req: Cacheable + 'static>(mut self, ttl: Duration) -> Self {
+        Arc::make_mut(&mut self.ttls).insert(TypeId::of::<Req>(), ttl);
+        self
+    }
+
+    /// Removes every cached response.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+impl std::fmt::Debug for CacheLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CacheLayer")
+            .field("ttls", &self.ttls)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for CacheLayer {
+    type Service = Cache<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Cache {
+            inner,
+            ttls: Arc::clone(&self.ttls),
+            entries: Arc::clone(&self.entries),
+        }
+    }
+}
+
+/// A [`Service`] returned by [`CacheLayer`].
+#[derive(Clone)]
+pub struct Cache<S> {
+    inner: S,
+    ttls: Arc<HashMap<TypeId, Duration>>,
+    entries: Entries,
+}
+
+impl<S: std::fmt::Debug> std::fmt::Debug for Cache<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cache")
+            .field("inner", &self.inner)
+            .field("ttls", &self.ttls)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, Req> Service<Req> for Cache<S>
+where
+    S: Service<Req>,
+    S::Response: Clone + Send + 'static,
+    Req: HttpRequest + Serialize + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = ResponseFuture<S::Future, S::Response>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let cached = self
+            .ttls
+            .get(&TypeId::of::<Req>())
+            .and_then(|ttl| Some((*ttl, request_key(&req)?)));
+        let (ttl, key) = match cached {
+            Some(x) => x,
+            None => {
+                return ResponseFuture {
+                    state: State::Miss {
+                        inner: self.inner.call(req),
+                        store: None,
+                    },
+                }
+            }
+        };
+
+        let now = Instant::now();
+        {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.get(&key) {
+                Some(entry) if entry.expires > now => {
+                    let response = entry
+                        .response
+                        .downcast_ref::<S::Response>()
+                        .expect("keyed with the request type")
+                        .clone();
+                    tracing::trace!(?ttl, "cache hit");
+                    return ResponseFuture {
+                        state: State::Hit {
+                            response: Some(response),
+                        },
+                    };
+                }
+                Some(_) => {
+                    entries.remove(&key);
+                }
+                None => (),
+            }
+        }
+
+        ResponseFuture {
+            state: State::Miss {
+                inner: self.inner.call(req),
+                store: Some(Store {
+                    entries: Arc::clone(&self.entries),
+                    key,
+                    ttl,
+                }),
+            },
+        }
+    }
+}
+
+/// Where to store the response of a cache miss.
+struct Store {
+    entries: Entries,
+    key: Key,
+    ttl: Duration,
+}
+
+/// A [`Future`] returned by [`Cache`].
+#[pin_project]
+pub struct ResponseFuture<F, T> {
+    #[pin]
+    state: State<F, T>,
+}
+
+#[pin_project(project = StateProj)]
+enum State<F, T> {
+    Hit {
+        response: Option<T>,
+    },
+    Miss {
+        #[pin]
+        inner: F,
+        store: Option<Store>,
+    },
+}
+
+impl<F, T, E> Future for ResponseFuture<F, T>
+where
+    F: Future<Output = Result<T, E>>,
+    T: Clone + Send + 'static,
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().state.project() {
+            StateProj::Hit { response } => {
+                Poll::Ready(Ok(response.take().expect("polled after completion")))
+            }
+            StateProj::Miss { inner, store } => {
+                let result = ready!(inner.poll(cx));
+                if let (Ok(response), Some(store)) = (&result, store.take()) {
+                    let now = Instant::now();
+                    let mut entries = store.entries.lock().unwrap();
+                    entries.retain(|_, x| x.expires > now);
+                    entries.insert(
+                        store.key,
+                        Entry {
+                            expires: now + store.ttl,
+                            response: Box::new(response.clone()),
+                        },
+                    );
+                }
+                Poll::Ready(result)
+            }
+        }
+    }
+}
+
+impl<F, T> std::fmt::Debug for ResponseFuture<F, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hit = matches!(self.state, State::Hit { .. });
+        f.debug_struct("ResponseFuture")
+            .field("hit", &hit)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use futures::future::{ready, Ready};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{NoErrorResponse, Request};
+
+    #[derive(Serialize)]
+    struct GetTicker {
+        symbol: &'static str,
+        #[serde(skip)]
+        window: Option<&'static str>,
+    }
+
+    impl Request for GetTicker {
+        type Response = u64;
+        type ErrorResponse = NoErrorResponse;
+    }
+
+    impl HttpRequest for GetTicker {
+        fn uri(&self) -> Result<http::Uri, crate::Error> {
+            Ok(http::Uri::from_static("https://example.com/ticker"))
+        }
+
+        fn method(&self) -> http::Method {
+            http::Method::GET
+        }
+
+        fn headers(&self) -> Result<http::HeaderMap, crate::Error> {
+            let mut headers = http::HeaderMap::new();
+            if let Some(x) = self.window {
+                headers.insert("x-window", http::HeaderValue::from_static(x));
+            }
+            Ok(headers)
+        }
+    }
+
+    impl Cacheable for GetTicker {}
+
+    #[derive(Serialize)]
+    struct GetBalance;
+
+    impl Request for GetBalance {
+        type Response = u64;
+        type ErrorResponse = NoErrorResponse;
+    }
+
+    impl HttpRequest for GetBalance {
+        fn uri(&self) -> Result<http::Uri, crate::Error> {
+            Ok(http::Uri::from_static("https://example.com/balance"))
+        }
+
+        fn method(&self) -> http::Method {
+            http::Method::GET
+        }
+    }
+
+    /// Responds with the number of calls so far.
+    #[derive(Clone, Default)]
+    struct Counter(Arc<AtomicU64>);
+
+    impl<Req> Service<Req> for Counter {
+        type Response = u64;
+
+        type Error = Infallible;
+
+        type Future = Ready<Result<u64, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: Req) -> Self::Future {
+            ready(Ok(self.0.fetch_add(1, Ordering::SeqCst) + 1))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn caches_opted_in_requests() {
+        let svc = CacheLayer::new()
+            .with_ttl::<GetTicker>(Duration::from_secs(1))
+            .layer(Counter::default());
+        let btc = || GetTicker {
+            symbol: "BTC",
+            window: None,
+        };
+
+        assert_eq!(svc.clone().oneshot(btc()).await.unwrap(), 1);
+        assert_eq!(svc.clone().oneshot(btc()).await.unwrap(), 1);
+        // Keyed on the serialized request
+        let eth = GetTicker {
+            symbol: "ETH",
+            window: None,
+        };
+        assert_eq!(svc.clone().oneshot(eth).await.unwrap(), 2);
+        // And on the headers
+        let windowed = GetTicker {
+            symbol: "BTC",
+            window: Some("1h"),
+        };
+        assert_eq!(svc.clone().oneshot(windowed).await.unwrap(), 3);
+        // Not opted in
+        assert_eq!(svc.clone().oneshot(GetBalance).await.unwrap(), 4);
+        assert_eq!(svc.clone().oneshot(GetBalance).await.unwrap(), 5);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(svc.oneshot(btc()).await.unwrap(), 6);
+    }
+}
@@ -0,0 +1,411 @@
+//! Single-flight coalescing of identical in-flight requests with [`CoalesceLayer`].
+//!
+//! Identical requests, i.e. of the same type, [`HttpRequest::uri`], headers and serialized body, share
+//! the response of the first one while it is in flight, so that only one of them is sent. Unlike
+//! [`CacheLayer`](crate::cache::CacheLayer), a response is not reused once it completes.
+//!
+//! Requests are coalesced only if their types opted in with [`CoalesceLayer::coalesce`]. Like
+//! [`tower::buffer::Buffer`], this layer can be cloned to share it between tasks.
+
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::{
+    future::{MapErr, Shared, WeakShared},
+    FutureExt, TryFutureExt,
+};
+use pin_project::pin_project;
+use serde::Serialize;
+use thiserror::Error;
+use tower::{Layer, Service};
+
+use crate::{
+    cache::{request_key, Cacheable, Key},
+    Classify, ErrorClass, HttpRequest,
+};
+
+type Entries = Arc<Mutex<HashMap<Key, Box<dyn InFlight>>>>;
+
+/// A [`WeakShared`] of an in-flight request, whose type depends on the request.
+trait InFlight: Send {
+    fn as_any(&self) -> &dyn Any;
+
+    /// Returns `true` if any waiter of the request is left.
+    fn is_alive(&self) -> bool;
+}
+
+impl<Fut> InFlight for WeakShared<Fut>
+where
+    Fut: Future + 'static,
+    Fut::Output: Clone,
+    Self: Send,
+{
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn is_alive(&self) -> bool {
+        self.upgrade().is_some()
+    }
+}
+
+type ToShared<F, E> = MapErr<F, fn(E) -> Arc<E>>;
+
+/// Errors of [`Coalesce`].
+#[derive(Error, Debug)]
+pub enum Error<E> {
+    /// The request was not coalesced.
+    #[error("{0}")]
+    Inner(E),
+    /// The request was coalesced, so its error is shared between identical requests.
+    #[error("{0}")]
+    Coalesced(Arc<E>),
+}
+
+impl<E: Classify> Classify for Error<E> {
+    fn class(&self) -> ErrorClass {
+        match self {
+            Self::Inner(e) => e.class(),
+            Self::Coalesced(e) => e.class(),
+        }
+    }
+}
+
+/// A [`Layer`] coalescing identical in-flight requests of opted-in request types.
+///
+/// Services of the same layer share their in-flight requests.
+#[derive(Clone, Default)]
+pub struct CoalesceLayer {
+    types: Arc<HashSet<TypeId>>,
+    entries: Entries,
+}
+
+impl CoalesceLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Coalesces identical in-flight requests of `Req`, which must not depend on credentials like
+    /// [`CacheLayer`](crate::cache::CacheLayer).
+    pub fn coalesce<Req: Cacheable + 'static>(mut self) -> Self {
+        Arc::make_mut(&mut self.types).insert(TypeId::of::<Req>());
+        self
+    }
+}
+
+impl std::fmt::Debug for CoalesceLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CoalesceLayer")
+            .field("types", &self.types)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Layer<S> for CoalesceLayer {
+    type Service = Coalesce<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Coalesce {
+            inner,
+            types: Arc::clone(&self.types),
+            entries: Arc::clone(&self.entries),
+        }
+    }
+}
+
+/// A [`Service`] returned by [`CoalesceLayer`].
+#[derive(Clone)]
+pub struct Coalesce<S> {
+    inner: S,
+    types: Arc<HashSet<TypeId>>,
+    entries: Entries,
+}
+
+impl<S: std::fmt::Debug> std::fmt::Debug for Coalesce<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Coalesce")
+            .field("inner", &self.inner)
+            .field("types", &self.types)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S, Req> Service<Req> for Coalesce<S>
+where
+    S: Service<Req>,
+    S::Future: Send + 'static,
+    S::Response: Clone + Send + Sync + 'static,
+    S::Error: Send + Sync + 'static,
+    Req: HttpRequest + Serialize + 'static,
+{
+    type Response = S::Response;
+
+    type Error = Error<S::Error>;
+
+    type Future = ResponseFuture<S::Future, S::Response, S::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Error::Inner)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let key = if self.types.contains(&TypeId::of::<Req>()) {
+            request_key(&req)
+        } else {
+            None
+        };
+        let key = match key {
+            Some(x) => x,
+            None => {
+                return ResponseFuture {
+                    state: State::Inner {
+                        inner: self.inner.call(req),
+                    },
+                }
+            }
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        let in_flight = entries.get(&key).and_then(|x| {
+            x.as_any()
+                .downcast_ref::<WeakShared<ToShared<S::Future, S::Error>>>()
+                .expect("keyed with the request type")
+                .upgrade()
+        });
+        let shared = match in_flight {
+            Some(shared) => {
+                tracing::trace!("coalesced with an in-flight request");
+                shared
+            }
+            None => {
+                let to_shared: fn(S::Error) -> Arc<S::Error> = Arc::new;
+                let shared = self.inner.call(req).map_err(to_shared).shared();
+                let weak = shared.downgrade().expect("not polled yet");
+                // Requests whose waiters were all dropped before completion are left behind
+                entries.retain(|_, x| x.is_alive());
+                entries.insert(key.clone(), Box::new(weak));
+                shared
+            }
+        };
+
+        ResponseFuture {
+            state: State::Coalesced {
+                weak: shared.downgrade().expect("not completed yet"),
+                shared,
+                entries: Arc::clone(&self.entries),
+                key,
+            },
+        }
+    }
+}
+
+/// A [`Future`] returned by [`Coalesce`].
+#[pin_project]
+pub struct ResponseFuture<F, T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    #[pin]
+    state: State<F, T, E>,
+}
+
+#[pin_project(project = StateProj)]
+enum State<F, T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    Inner {
+        #[pin]
+        inner: F,
+    },
+    Coalesced {
+        shared: Shared<ToShared<F, E>>,
+        /// Identifies the request after `shared` completes, which cannot be compared then.
+        weak: WeakShared<ToShared<F, E>>,
+        entries: Entries,
+        key: Key,
+    },
+}
+
+impl<F, T, E> Future for ResponseFuture<F, T, E>
+where
+    F: Future<Output = Result<T, E>> + 'static,
+    T: Clone + 'static,
+    E: 'static,
+{
+    type Output = Result<T, Error<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().state.project() {
+            StateProj::Inner { inner } => inner.poll(cx).map_err(Error::Inner),
+            StateProj::Coalesced {
+                shared,
+                weak,
+                entries,
+                key,
+            } => {
+                let result = match shared.poll_unpin(cx) {
+                    Poll::Ready(x) => x,
+                    Poll::Pending => return Poll::Pending,
+                };
+                // The first waiter to complete removes the request, unless an identical request
+                // has replaced it since
+                let ours = weak.upgrade();
+                let mut entries = entries.lock().unwrap();
+                let replaced = entries.get(key).and_then(|x| {
+                    x.as_any()
+                        .downcast_ref::<WeakShared<ToShared<F, E>>>()
+                        .expect("keyed with the request type")
+                        .upgrade()
+                        .filter(|x| match &ours {
+                            Some(ours) => !x.ptr_eq(ours),
+                            None => true,
+                        })
+                });
+                if replaced.is_none() {
+                    entries.remove(key);
+                }
+                Poll::Ready(result.map_err(Error::Coalesced))
+            }
+        }
+    }
+}
+
+impl<F, T, E> std::fmt::Debug for ResponseFuture<F, T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let coalesced = matches!(self.state, State::Coalesced { .. });
+        f.debug_struct("ResponseFuture")
+            .field("coalesced", &coalesced)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    };
+
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{NoErrorResponse, Request};
+
+    #[derive(Serialize)]
+    struct GetOrderbook {
+        symbol: &'static str,
+    }
+
+    impl Request for GetOrderbook {
+        type Response = u64;
+        type ErrorResponse = NoErrorResponse;
+    }
+
+    impl Cacheable for GetOrderbook {}
+
+    impl HttpRequest for GetOrderbook {
+        fn uri(&self) -> Result<http::Uri, crate::Error> {
+            Ok(http::Uri::from_static("https://example.com/orderbook"))
+        }
+
+        fn method(&self) -> http::Method {
+            http::Method::GET
+        }
+    }
+
+    #[derive(Debug)]
+    struct Failed;
+
+    /// Responds with the number of calls so far after 10 milliseconds, failing on `FAIL`.
+    #[derive(Clone, Default)]
+    struct Counter(Arc<AtomicU64>);
+
+    impl Service<GetOrderbook> for Counter {
+        type Response = u64;
+
+        type Error = Failed;
+
+        type Future = Pin<Box<dyn Future<Output = Result<u64, Failed>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: GetOrderbook) -> Self::Future {
+            let count = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                if req.symbol == "FAIL" {
+                    Err(Failed)
+                } else {
+                    Ok(count)
+                }
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn coalesces_in_flight_requests() {
+        let counter = Counter::default();
+        let calls = Arc::clone(&counter.0);
+        let svc = CoalesceLayer::new()
+            .coalesce::<GetOrderbook>()
+            .layer(counter);
+        let get = |symbol| svc.clone().oneshot(GetOrderbook { symbol });
+
+        let (a, b, c) = tokio::join!(get("BTC"), get("BTC"), get("ETH"));
+        assert_eq!(a.unwrap(), b.unwrap());
+        c.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Completed requests are not reused
+        get("BTC").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let (a, b) = tokio::join!(get("FAIL"), get("FAIL"));
+        assert!(matches!(a, Err(Error::Coalesced(_))));
+        assert!(matches!(b, Err(Error::Coalesced(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_requests_sent_after_completion() {
+        let counter = Counter::default();
+        let calls = Arc::clone(&counter.0);
+        let mut svc = CoalesceLayer::new()
+            .coalesce::<GetOrderbook>()
+            .layer(counter);
+        let btc = || GetOrderbook { symbol: "BTC" };
+
+        let a = svc.ready().await.unwrap().call(btc());
+        let late = svc.ready().await.unwrap().call(btc());
+        assert_eq!(a.await.unwrap(), 1);
+        let b = svc.ready().await.unwrap().call(btc());
+        // The late waiter of the first request does not remove the second one
+        assert_eq!(late.await.unwrap(), 1);
+        let c = svc.ready().await.unwrap().call(btc());
+        assert_eq!(tokio::join!(b, c).0.unwrap(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Dropped before completion
+        drop(svc.ready().await.unwrap().call(btc()));
+        assert_eq!(svc.entries.lock().unwrap().len(), 1);
+        let eth = svc
+            .ready()
+            .await
+            .unwrap()
+            .call(GetOrderbook { symbol: "ETH" });
+        assert_eq!(svc.entries.lock().unwrap().len(), 1);
+        eth.await.unwrap();
+    }
+}
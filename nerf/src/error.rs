@@ -1,3 +1,7 @@
+use std::fmt::Display;
+
+use http::StatusCode;
+use serde::{de, Deserialize, Deserializer};
 use thiserror::Error;
 
 /// Common errors across the crate.
@@ -6,6 +10,130 @@ use thiserror::Error;
 pub enum Error {
     #[error("Cannot serialize request into JSON bytes: {0}")]
     SerializeRequest(serde_json::Error),
+    #[error("Cannot serialize request into URL-encoded parameters: {0}")]
+    SerializeUrlencoded(serde_urlencoded::ser::Error),
+    #[error("Cannot construct HTTP request: {0}")]
+    ConstructRequest(http::Error),
     #[error("Cannot deserialize response into JSON bytes: {0}")]
     DeserializeResponse(serde_json::Error),
 }
+
+impl Classify for Error {
+    fn class(&self) -> ErrorClass {
+        match self {
+            Error::SerializeRequest(_)
+            | Error::SerializeUrlencoded(_)
+            | Error::ConstructRequest(_) => ErrorClass::InvalidRequest,
+            Error::DeserializeResponse(_) => ErrorClass::Other,
+        }
+    }
+}
+
+/// Coarse classification of errors, to let generic middlewares(e.g. retries or alerts) act on
+/// errors without matching exchange-specific codes or messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorClass {
+    /// A temporary failure such as a connection error or a `5xx` response.
+    Retryable,
+    /// The rate limit is exceeded. Requests should be paused for a while.
+    RateLimited,
+    /// The credential, the signature or the timestamp is rejected.
+    Authentication,
+    /// The request is rejected by its content, and sending it again fails again.
+    InvalidRequest,
+    /// The request may or may not have taken effect, e.g. a timeout after an order is sent.
+    /// The order state should be queried before placing it again.
+    OrderStateUnknown,
+    /// Any other errors.
+    Other,
+}
+
+impl ErrorClass {
+    /// Classifies an error response by its HTTP status code only.
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::IM_A_TEAPOT => Self::RateLimited,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Authentication,
+            StatusCode::GATEWAY_TIMEOUT => Self::OrderStateUnknown,
+            x if x.is_server_error() => Self::Retryable,
+            x if x.is_client_error() => Self::InvalidRequest,
+            _ => Self::Other,
+        }
+    }
+
+    /// Returns `true` if sending an idempotent request again may succeed.
+    pub fn is_transient(self) -> bool {
+        matches!(
+            self,
+            Self::Retryable | Self::RateLimited | Self::OrderStateUnknown
+        )
+    }
+}
+
+/// Errors with an [`ErrorClass`].
+pub trait Classify {
+    fn class(&self) -> ErrorClass;
+}
+
+/// [`Request::ErrorResponse`] of requests without a typed error body.
+///
+/// As it never deserializes, clients fall back to the raw response body.
+///
+/// [`Request::ErrorResponse`]: crate::Request::ErrorResponse
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoErrorResponse {}
+
+impl<'de> Deserialize<'de> for NoErrorResponse {
+    fn deserialize<D>(_deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Err(de::Error::custom("no error response is expected"))
+    }
+}
+
+impl Display for NoErrorResponse {
+    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {}
+    }
+}
+
+impl std::error::Error for NoErrorResponse {}
+
+impl Classify for NoErrorResponse {
+    fn class(&self) -> ErrorClass {
+        match *self {}
+    }
+}
+
+impl Classify for std::convert::Infallible {
+    fn class(&self) -> ErrorClass {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_status_codes() {
+        let table = [
+            (StatusCode::TOO_MANY_REQUESTS, ErrorClass::RateLimited),
+            (StatusCode::IM_A_TEAPOT, ErrorClass::RateLimited),
+            (StatusCode::UNAUTHORIZED, ErrorClass::Authentication),
+            (StatusCode::FORBIDDEN, ErrorClass::Authentication),
+            (StatusCode::GATEWAY_TIMEOUT, ErrorClass::OrderStateUnknown),
+            (StatusCode::INTERNAL_SERVER_ERROR, ErrorClass::Retryable),
+            (StatusCode::SERVICE_UNAVAILABLE, ErrorClass::Retryable),
+            (StatusCode::BAD_REQUEST, ErrorClass::InvalidRequest),
+            (StatusCode::NOT_FOUND, ErrorClass::InvalidRequest),
+            (StatusCode::OK, ErrorClass::Other),
+            (StatusCode::MOVED_PERMANENTLY, ErrorClass::Other),
+        ];
+        for (status, class) in table {
+            assert_eq!(ErrorClass::from_status(status), class, "{status}");
+        }
+    }
+}
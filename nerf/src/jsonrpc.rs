@@ -0,0 +1,478 @@
+//! JSON-RPC 2.0 over HTTP.
+//!
+//! [`RpcRequest`]s, usually defined with [`nerf_macros::rpc`], are sent to a single endpoint by
+//! [`RpcClient`]. Several requests can be sent at once as a [`Batch`], whose response is a tuple
+//! of the responses of each request.
+
+use std::{collections::HashMap, fmt::Display};
+
+use http::{header::CONTENT_TYPE, HeaderValue, Uri};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::{rest::DecodeFuture, Classify, Client, ErrorClass, Request};
+
+/// Errors of JSON-RPC calls. `E` is the [`Request::ErrorResponse`] of the request, which is
+/// deserialized from the `error` object of the response.
+#[derive(Error, Debug)]
+pub enum Error<E = ErrorObject> {
+    #[error(transparent)]
+    Request(#[from] crate::Error),
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error("JSON-RPC call returned error: {body}")]
+    Rpc { class: ErrorClass, body: E },
+    /// The response is not a JSON-RPC response.
+    #[error("request to API server returned error, status: {status}, payload: {payload}")]
+    RequestFailed {
+        status: http::StatusCode,
+        payload: String,
+    },
+    #[error("cannot deserialize response: {0}, payload: {1}")]
+    DeserializeResponse(serde_json::Error, String),
+    #[error("response id does not match, expected: {expected}, payload: {payload}")]
+    MismatchedId { expected: u64, payload: String },
+    #[error("no response for request id {0}")]
+    MissingResponse(u64),
+}
+
+impl<E> Classify for Error<E> {
+    fn class(&self) -> ErrorClass {
+        match self {
+            Self::Request(e) => e.class(),
+            Self::Hyper(e) if e.is_connect() => ErrorClass::Retryable,
+            Self::Hyper(_) => ErrorClass::OrderStateUnknown,
+            Self::Rpc { class, .. } => *class,
+            Self::RequestFailed { status, .. } => ErrorClass::from_status(*status),
+            Self::DeserializeResponse(..)
+            | Self::MismatchedId { .. }
+            | Self::MissingResponse(_) => ErrorClass::Other,
+        }
+    }
+}
+
+/// The `error` object of JSON-RPC responses.
+///
+/// This is the default [`Request::ErrorResponse`] of [`nerf_macros::rpc`]. Use a type with a
+/// typed `data` to inspect it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorObject<D = Value> {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<D>,
+}
+
+impl<D> Display for ErrorObject<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl<D> Classify for ErrorObject<D> {
+    fn class(&self) -> ErrorClass {
+        match self.code {
+            // Parse error, invalid request, method not found and invalid params
+            -32700 | -32600 | -32601 | -32602 => ErrorClass::InvalidRequest,
+            _ => ErrorClass::Other,
+        }
+    }
+}
+
+/// JSON-RPC requests, whose `params` are the serialized request.
+///
+/// Usually implemented with [`nerf_macros::rpc`].
+pub trait RpcRequest: Request + Serialize {
+    fn method(&self) -> &'static str;
+}
+
+/// Calls sent by [`RpcClient`]: an [`RpcRequest`] or a [`Batch`] of them.
+pub trait Call: Request {
+    /// The number of requests in the call.
+    const LEN: u64;
+
+    /// Serializes the call, numbering requests from `first_id`.
+    fn to_payload(&self, first_id: u64) -> Result<Value, serde_json::Error>;
+
+    /// Decodes the response of the call serialized with `first_id`.
+    fn from_payload(
+        first_id: u64,
+        payload: Value,
+    ) -> Result<Self::Response, Error<Self::ErrorResponse>>;
+}
+
+/// Serializes the request object of `x`.
+fn request_object<T: RpcRequest>(id: u64, x: &T) -> Result<Value, serde_json::Error> {
+    let mut object = json!({ "jsonrpc": "2.0", "id": id, "method": x.method() });
+    // Requests without parameters omit `params`
+    let params = serde_json::to_value(x)?;
+    if !params.is_null() {
+        object["params"] = params;
+    }
+    Ok(object)
+}
+
+/// Decodes `result` or `error` of a response object.
+fn decode<T, E>(mut x: Value) -> Result<T, Error<E>>
+where
+    T: DeserializeOwned,
+    E: DeserializeOwned,
+{
+    if let Some(error) = x.get_mut("error").map(Value::take) {
+        if !error.is_null() {
+            let class = ErrorObject::<Value>::deserialize(&error)
+                .map_err(|e| Error::DeserializeResponse(e, error.to_string()))?
+                .class();
+            let body = E::deserialize(&error)
+                .map_err(|e| Error::DeserializeResponse(e, error.to_string()))?;
+            return Err(Error::Rpc { class, body });
+        }
+    }
+    let result = x.get_mut("result").map(Value::take).unwrap_or_default();
+    T::deserialize(&result).map_err(|e| Error::DeserializeResponse(e, result.to_string()))
+}
+
+impl<T> Call for T
+where
+    T: RpcRequest,
+    T::Response: DeserializeOwned,
+    T::ErrorResponse: DeserializeOwned,
+{
+    const LEN: u64 = 1;
+
+    fn to_payload(&self, first_id: u64) -> Result<Value, serde_json::Error> {
+        request_object(first_id, self)
+    }
+
+    fn from_payload(
+        first_id: u64,
+        payload: Value,
+    ) -> Result<Self::Response, Error<Self::ErrorResponse>> {
+        // Servers respond with a null id if the request cannot be parsed
+        match payload.get("id").and_then(Value::as_u64) {
+            Some(id) if id != first_id => Err(Error::MismatchedId {
+                expected: first_id,
+                payload: payload.to_string(),
+            }),
+            _ => decode(payload),
+        }
+    }
+}
+
+/// A batch of [`RpcRequest`]s in a tuple, e.g. `Batch((GetTicker, GetOrderbook))`.
+///
+/// The response is the tuple of the results of each request. The batch itself fails only if the
+/// whole batch is rejected.
+#[derive(Clone, Debug)]
+pub struct Batch<T>(pub T);
+
+// A timeout of a batch leaves the outcome of every request in it unknown
+impl<T> crate::timeout::Deadline for Batch<T> {}
+
+/// Indexes the response objects of a batch by their ids.
+fn batch_responses(first_id: u64, payload: Value) -> Result<HashMap<u64, Value>, Error> {
+    let items = match payload {
+        Value::Array(x) => x,
+        // The whole batch is rejected with a single response object
+        x => {
+            decode::<Value, ErrorObject>(x)?;
+            return Err(Error::MissingResponse(first_id));
+        }
+    };
+    Ok(items
+        .into_iter()
+        .filter_map(|x| Some((x.get("id")?.as_u64()?, x)))
+        .collect())
+}
+
+macro_rules! impl_batch {
+    ($($T:ident $i:tt),+) => {
+        impl<$($T: RpcRequest),+> Request for Batch<($($T,)+)> {
+            type Response = ($(Result<$T::Response, Error<$T::ErrorResponse>>,)+);
+            type ErrorResponse = ErrorObject;
+        }
+
+        impl<$($T),+> Call for Batch<($($T,)+)>
+        where
+            $(
+                $T: RpcRequest,
+                $T::Response: DeserializeOwned,
+                $T::ErrorResponse: DeserializeOwned,
+            )+
+        {
+            const LEN: u64 = [$($i),+].len() as u64;
+
+            fn to_payload(&self, first_id: u64) -> Result<Value, serde_json::Error> {
+                Ok(Value::Array(vec![$(request_object(first_id + $i, &(self.0).$i)?),+]))
+            }
+
+            fn from_payload(
+                first_id: u64,
+                payload: Value,
+            ) -> Result<Self::Response, Error<Self::ErrorResponse>> {
+                let mut responses = batch_responses(first_id, payload)?;
+                Ok(($(
+                    responses
+                        .remove(&(first_id + $i))
+                        .ok_or(Error::MissingResponse(first_id + $i))
+                        .and_then(decode),
+                )+))
+            }
+        }
+    };
+}
+
+impl_batch!(A 0);
+impl_batch!(A 0, B 1);
+impl_batch!(A 0, B 1, C 2);
+impl_batch!(A 0, B 1, C 2, D 3);
+impl_batch!(A 0, B 1, C 2, D 3, E 4);
+impl_batch!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_batch!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_batch!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+/// A [`Client`] posting [`Call`]s to a JSON-RPC endpoint.
+///
+/// Request ids are numbered by each client, so clones of the client may use the same ids.
+#[derive(Clone, Debug)]
+pub struct RpcClient<S> {
+    endpoint: Uri,
+    next_id: u64,
+    service: S,
+}
+
+impl<S> RpcClient<S> {
+    pub fn new(endpoint: Uri, service: S) -> Self {
+        Self {
+            endpoint,
+            next_id: 1,
+            service,
+        }
+    }
+}
+
+impl<Req, S> Client<Req> for RpcClient<S>
+where
+    Req: Call,
+    Req::Response: Send + 'static,
+    Req::ErrorResponse: Send + 'static,
+{
+    type Service = S;
+
+    type Error = Error<Req::ErrorResponse>;
+
+    /// The id of the first request.
+    type Context = u64;
+
+    type TryFromResponseFuture = DecodeFuture<Req::Response, Self::Error>;
+
+    fn service(&mut self) -> &mut Self::Service {
+        &mut self.service
+    }
+
+    fn context(&mut self, _x: &Req) -> Self::Context {
+        self.next_id
+    }
+
+    fn try_into_request(&mut self, x: Req) -> Result<hyper::Request<hyper::Body>, Self::Error> {
+        let payload = x
+            .to_payload(self.next_id)
+            .map_err(crate::Error::SerializeRequest)?;
+        self.next_id += Req::LEN;
+        let body = serde_json::to_vec(&payload).map_err(crate::Error::SerializeRequest)?;
+        let req = hyper::Request::post(self.endpoint.clone())
+            .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+            .body(hyper::Body::from(body))
+            .map_err(crate::Error::ConstructRequest)?;
+        Ok(req)
+    }
+
+    fn try_from_response(
+        cx: Self::Context,
+        x: hyper::Response<hyper::Body>,
+    ) -> Self::TryFromResponseFuture {
+        Box::pin(async move {
+            let status = x.status();
+            let buf = hyper::body::to_bytes(x.into_body()).await?;
+            // Error objects may come with error statuses
+            match serde_json::from_slice(&buf) {
+                Ok(payload) => Req::from_payload(cx, payload),
+                Err(_) if !status.is_success() => Err(Error::RequestFailed {
+                    status,
+                    payload: String::from_utf8_lossy(&buf).to_string(),
+                }),
+                Err(e) => Err(Error::DeserializeResponse(
+                    e,
+                    String::from_utf8_lossy(&buf).to_string(),
+                )),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use tower::{Service, ServiceExt};
+
+    use super::*;
+    use crate::IntoService;
+
+    #[derive(Serialize)]
+    struct GetPrice {
+        symbol: &'static str,
+    }
+
+    impl Request for GetPrice {
+        type Response = u32;
+        type ErrorResponse = ErrorObject<PriceError>;
+    }
+
+    impl RpcRequest for GetPrice {
+        fn method(&self) -> &'static str {
+            "public/get_price"
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct PriceError {
+        symbol: String,
+    }
+
+    #[derive(Serialize)]
+    struct GetTime;
+
+    impl Request for GetTime {
+        type Response = String;
+        type ErrorResponse = ErrorObject;
+    }
+
+    impl RpcRequest for GetTime {
+        fn method(&self) -> &'static str {
+            "public/get_time"
+        }
+    }
+
+    /// Answers requests in reverse order. Prices are known for `btc` only.
+    struct Server;
+
+    impl Server {
+        fn respond(x: &Value) -> Value {
+            let id = x["id"].clone();
+            match (
+                x["method"].as_str().unwrap(),
+                x["params"]["symbol"].as_str(),
+            ) {
+                ("public/get_price", Some("btc")) => {
+                    json!({ "jsonrpc": "2.0", "id": id, "result": 100 })
+                }
+                ("public/get_price", Some(symbol)) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": 10001, "message": "unknown symbol", "data": { "symbol": symbol } },
+                }),
+                ("public/get_time", None) => {
+                    json!({ "jsonrpc": "2.0", "id": id, "result": "2022-08-01T00:00:00Z" })
+                }
+                _ => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": { "code": -32601, "message": "method not found" },
+                }),
+            }
+        }
+    }
+
+    impl Service<hyper::Request<hyper::Body>> for Server {
+        type Response = hyper::Response<hyper::Body>;
+
+        type Error = hyper::Error;
+
+        type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
+            Box::pin(async move {
+                assert_eq!(req.method(), http::Method::POST);
+                let buf = hyper::body::to_bytes(req.into_body()).await?;
+                let resp = match serde_json::from_slice(&buf).unwrap() {
+                    Value::Array(x) => Value::Array(x.iter().rev().map(Self::respond).collect()),
+                    x => Self::respond(&x),
+                };
+                Ok(hyper::Response::new(resp.to_string().into()))
+            })
+        }
+    }
+
+    fn client() -> RpcClient<Server> {
+        RpcClient::new(Uri::from_static("http://example.com/api"), Server)
+    }
+
+    #[test]
+    fn serializes_request_objects() {
+        let payload = Batch((GetPrice { symbol: "btc" }, GetTime))
+            .to_payload(3)
+            .unwrap();
+        assert_eq!(
+            payload,
+            json!([
+                { "jsonrpc": "2.0", "id": 3, "method": "public/get_price", "params": { "symbol": "btc" } },
+                { "jsonrpc": "2.0", "id": 4, "method": "public/get_time" },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn calls_and_maps_errors() {
+        let mut svc = client().into_service();
+        let price = ServiceExt::<GetPrice>::ready(&mut svc)
+            .await
+            .unwrap()
+            .call(GetPrice { symbol: "btc" });
+        assert_eq!(price.await.unwrap(), 100);
+
+        let err = ServiceExt::<GetPrice>::ready(&mut svc)
+            .await
+            .unwrap()
+            .call(GetPrice { symbol: "eth" })
+            .await
+            .unwrap_err();
+        match err {
+            Error::Rpc { class, body } => {
+                assert_eq!(class, ErrorClass::Other);
+                assert_eq!(body.code, 10001);
+                assert_eq!(
+                    body.data,
+                    Some(PriceError {
+                        symbol: "eth".to_string()
+                    })
+                );
+            }
+            e => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn decodes_batches_into_tuples() {
+        let svc = client().into_service();
+        let (btc, time, eth) = svc
+            .oneshot(Batch((
+                GetPrice { symbol: "btc" },
+                GetTime,
+                GetPrice { symbol: "eth" },
+            )))
+            .await
+            .unwrap();
+        assert_eq!(btc.unwrap(), 100);
+        assert_eq!(time.unwrap(), "2022-08-01T00:00:00Z");
+        assert!(matches!(eth, Err(Error::Rpc { body, .. }) if body.message == "unknown symbol"));
+    }
+}
@@ -1,27 +1,69 @@
 #![warn(clippy::print_stderr, clippy::print_stdout)]
 //! nerf is a toolkit to create client-side SDK for (mainly) HTTP endpoint APIs.
 
+#[doc(hidden)]
+pub mod __private;
+pub mod base_url;
+pub mod cache;
+pub mod coalesce;
 mod error;
+pub mod jsonrpc;
 mod macro_reexport;
+#[cfg(any(test, feature = "observe"))]
+pub mod observe;
+pub mod paginate;
+pub mod rate_limit;
 mod ready_call;
+pub mod rest;
+pub mod retry;
+pub mod sign;
+pub mod streaming;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timeout;
+#[cfg(any(test, feature = "ws"))]
+pub mod ws;
 
+use std::borrow::Cow;
 use std::future::Future;
 use std::pin::Pin;
 
 pub use macro_reexport::*;
-pub use ready_call::ReadyCall;
+pub use ready_call::{CallAll, ReadyCall};
 
 pub use bytes::Bytes;
-pub use error::Error;
+pub use error::{Classify, Error, ErrorClass, NoErrorResponse};
 pub use http;
 pub use nerf_macros::rate_limited;
+pub use nerf_macros::retry;
+pub use nerf_macros::rpc;
+#[cfg(feature = "ws")]
+pub use nerf_macros::subscription;
 pub use pin_project::pin_project;
 pub use serde;
 pub use serde_json;
 
 /// Rate limit with weights.
+///
+/// Weights are charged by [`rate_limit::RateLimit`] services.
 pub trait WeightedRateLimit {
+    /// Returns the weight charged to buckets without their own cost in
+    /// [`costs`](Self::costs).
     fn weight(&self) -> u64;
+
+    /// Returns the costs of named buckets, e.g. `[("order_10s", 1)]` for an order which also
+    /// counts towards an order count limit.
+    fn costs(&self) -> Vec<(&'static str, u64)> {
+        Vec::new()
+    }
+}
+
+/// Invariants of a request which its type cannot express, e.g. a price is required for limit
+/// orders. Checked by builders generated with `builder(validate)` of the HTTP macros.
+pub trait Validate {
+    type Error;
+
+    fn validate(&self) -> Result<(), Self::Error>;
 }
 
 /// Abstraction of `request` → [hyper::Client] → (server) → [hyper::Client] → [Response] flow.
@@ -29,22 +71,59 @@ pub trait WeightedRateLimit {
 /// [Response]: Request::Response
 pub trait Request {
     /// 'Expected' response type. Error case should not be included here.
-    /// TODO: introduce how to handle errors with `to
     type Response;
+    /// Body of error responses, e.g. `{"code": -1121, "msg": "Invalid symbol."}`.
+    ///
+    /// Use [`NoErrorResponse`] if the endpoint does not have a typed error body.
+    type ErrorResponse;
 }
 
 /// HTTP request metadata.
 /// Usually autogenerated with attribute macros, e.g. [`nerf_macros::get`].
 pub trait HttpRequest {
-    fn uri(&self) -> http::Uri;
+    fn uri(&self) -> Result<http::Uri, Error>;
     fn method(&self) -> http::Method;
+
+    /// Headers of the request, e.g. from `#[header("...")]` fields of the HTTP attribute macros.
+    fn headers(&self) -> Result<http::HeaderMap, Error> {
+        Ok(http::HeaderMap::new())
+    }
+
+    /// Returns [`uri`](Self::uri) with `params`(e.g. `symbol=BTCUSDT&limit=10`) merged into its
+    /// query string, which may already exist from the endpoint or `#[query]` fields.
+    fn uri_with_query(&self, params: &str) -> Result<http::Uri, Error> {
+        let uri = self.uri()?;
+        if params.is_empty() {
+            return Ok(uri);
+        }
+        let uri = match uri.query() {
+            Some(query) if !query.is_empty() => format!("{uri}&{params}"),
+            Some(_) => format!("{uri}{params}"),
+            None => format!("{uri}?{params}"),
+        };
+        http::Uri::try_from(uri).map_err(|e| Error::ConstructRequest(e.into()))
+    }
+
+    /// The endpoint without path parameters formatted in, e.g. `https://api.upbit.com/v1/order`,
+    /// to label requests of the endpoint. Defaults to `unknown`, as the formatted path would be a
+    /// distinct label for every path parameter.
+    fn endpoint(&self) -> Cow<'static, str> {
+        Cow::Borrowed("unknown")
+    }
 }
 
 /// Clients that accept [Request]s.
+///
+/// See [`rest::RestClient`] for a generic implementation for REST APIs.
 pub trait Client<Req: Request> {
     /// The service wrapped by the client.
     type Service;
     type Error: Send + 'static;
+    /// Owned state handed from the client to [try_from_response], e.g. a clock offset, a
+    /// credential or a part of the original request.
+    ///
+    /// [try_from_response]: Client::try_from_response
+    type Context: Send + 'static;
     /// A [Future] returned by [try_from_response].
     ///
     /// [try_from_response]: Client::try_from_response
@@ -55,12 +134,26 @@ pub trait Client<Req: Request> {
     /// [Service]: tower::Service
     fn service(&mut self) -> &mut Self::Service;
 
+    /// Captures the [Context] for the response of the request. Called before [try_into_request].
+    ///
+    /// [Context]: Client::Context
+    /// [try_into_request]: Client::try_into_request
+    fn context(&mut self, x: &Req) -> Self::Context;
+
     fn try_into_request(&mut self, x: Req) -> Result<hyper::Request<hyper::Body>, Self::Error>;
 
-    // FIXME: this should receive `&mut self` as `try_into_request` does, but the borrowck becomes unhappy
-    // because  `tower::Service::Future` cannot hold a lifetime. Once tower 1.0 releases and tower::Service
-    // GAT-ifies, we can consider adding `&mut self` to this method.
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture;
+    /// Decodes the response with the [Context] captured by [context].
+    ///
+    /// This does not receive `&mut self` because the returned future must outlive the borrow of
+    /// the client(`tower::Service::Future` cannot hold a lifetime), so any state needed for decoding
+    /// should be moved into the [Context].
+    ///
+    /// [Context]: Client::Context
+    /// [context]: Client::context
+    fn try_from_response(
+        cx: Self::Context,
+        x: hyper::Response<hyper::Body>,
+    ) -> Self::TryFromResponseFuture;
 }
 
 #[derive(Clone, Debug)]
@@ -109,12 +202,13 @@ where
     }
 
     fn call(&mut self, req: Req) -> Self::Future {
+        let cx = self.client.context(&req);
         let req = match self.client.try_into_request(req) {
             Ok(x) => x,
             Err(e) => return Box::pin(async move { Err(e) }),
         };
         let fut = self.client.service().call(req);
-        Box::pin(async move { T::try_from_response(fut.await?).await })
+        Box::pin(async move { T::try_from_response(cx, fut.await?).await })
     }
 }
 
@@ -122,3 +216,101 @@ where
 pub trait RequestService<R: Request>: tower::Service<R, Response = R::Response> {}
 
 impl<S: tower::Service<R, Response = R::Response> + ?Sized, R: Request> RequestService<R> for S {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        task::{Context, Poll},
+    };
+
+    use futures::future::{ready, Ready};
+    use tower::{Service, ServiceExt};
+
+    use super::*;
+
+    struct Ping;
+
+    impl Request for Ping {
+        type Response = (u64, String);
+        type ErrorResponse = NoErrorResponse;
+    }
+
+    /// Tags each request with an increasing request ID, carried to the response as its context.
+    struct IdClient<S> {
+        service: S,
+        next_id: u64,
+    }
+
+    impl<S> Client<Ping> for IdClient<S> {
+        type Service = S;
+
+        type Error = Infallible;
+
+        type Context = u64;
+
+        type TryFromResponseFuture =
+            Pin<Box<dyn Future<Output = Result<(u64, String), Infallible>> + Send>>;
+
+        fn service(&mut self) -> &mut Self::Service {
+            &mut self.service
+        }
+
+        fn context(&mut self, _x: &Ping) -> Self::Context {
+            self.next_id += 1;
+            self.next_id
+        }
+
+        fn try_into_request(
+            &mut self,
+            _x: Ping,
+        ) -> Result<hyper::Request<hyper::Body>, Infallible> {
+            Ok(hyper::Request::new(hyper::Body::empty()))
+        }
+
+        fn try_from_response(
+            cx: Self::Context,
+            x: hyper::Response<hyper::Body>,
+        ) -> Self::TryFromResponseFuture {
+            Box::pin(async move {
+                let body = hyper::body::to_bytes(x.into_body()).await.unwrap();
+                Ok((cx, String::from_utf8(body.to_vec()).unwrap()))
+            })
+        }
+    }
+
+    struct Pong;
+
+    impl Service<hyper::Request<hyper::Body>> for Pong {
+        type Response = hyper::Response<hyper::Body>;
+
+        type Error = Infallible;
+
+        type Future = Ready<Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: hyper::Request<hyper::Body>) -> Self::Future {
+            ready(Ok(hyper::Response::new("pong".into())))
+        }
+    }
+
+    #[tokio::test]
+    async fn passes_context_to_response() {
+        let mut svc = IdClient {
+            service: Pong,
+            next_id: 0,
+        }
+        .into_service();
+        assert_eq!(
+            svc.ready().await.unwrap().call(Ping).await.unwrap(),
+            (1, "pong".to_string())
+        );
+        assert_eq!(
+            svc.ready().await.unwrap().call(Ping).await.unwrap(),
+            (2, "pong".to_string())
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Tracing spans and metrics of requests with [`ObserveLayer`].
+//!
+//! [`ObserveLayer`] wraps a client service(e.g. [`ClientService`](crate::ClientService)) and opens
+//! a `request` span for every request, labeled by a [`Describe`] implementor. The HTTP status is
+//! recorded into the span by [`StatusLayer`] on the transport.
+//!
+//! The following metrics are reported to the [`metrics`] facade, labeled with `exchange`, `method`,
+//! `endpoint`, `signer` and `class`(the [`ErrorClass`] or `ok`):
+//!
+//! - `nerf_requests_total`: a counter of completed requests.
+//! - `nerf_request_duration_seconds`: a histogram of the latency of requests.
+//!
+//! This module requires the `observe` feature.
+
+use std::{
+    borrow::Cow,
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use http::Method;
+use metrics::{histogram, increment_counter, Label};
+use pin_project::pin_project;
+use tokio::time::Instant;
+use tower::{Layer, Service};
+use tracing::{field, info_span, instrument::Instrumented, Instrument, Span};
+
+use crate::{Classify, ErrorClass, HttpRequest};
+
+/// Labels of a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Labels {
+    /// The exchange or the API, e.g. `binance`.
+    pub exchange: &'static str,
+    pub method: Method,
+    /// The endpoint template, e.g. [`HttpRequest::endpoint`].
+    pub endpoint: Cow<'static, str>,
+    /// The kind of the signer, e.g. `public` or `private`.
+    pub signer: &'static str,
+    /// The rate limit weight, if known.
+    pub weight: Option<u64>,
+    /// The costs of named rate limit buckets, e.g. `[("order_10s", 1)]`.
+    pub costs: Vec<(&'static str, u64)>,
+}
+
+/// Labels requests of type `Req`.
+pub trait Describe<Req> {
+    fn describe(&self, x: &Req) -> Labels;
+}
+
+/// Labels [`HttpRequest`]s with their method and endpoint only, under an `unknown` exchange.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Endpoints;
+
+impl<Req: HttpRequest> Describe<Req> for Endpoints {
+    fn describe(&self, x: &Req) -> Labels {
+        Labels {
+            exchange: "unknown",
+            method: x.method(),
+            endpoint: x.endpoint(),
+            signer: "unknown",
+            weight: None,
+            costs: Vec::new(),
+        }
+    }
+}
+
+/// A [`Layer`] tracing and measuring requests labeled with `D`.
+#[derive(Clone, Debug, Default)]
+pub struct ObserveLayer<D> {
+    describe: D,
+}
+
+impl<D> ObserveLayer<D> {
+    pub fn new(describe: D) -> Self {
+        Self { describe }
+    }
+}
+
+impl<S, D: Clone> Layer<S> for ObserveLayer<D> {
+    type Service = Observed<S, D>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Observed {
+            inner,
+            describe: self.describe.clone(),
+        }
+    }
+}
+
+/// A [`Service`] returned by [`ObserveLayer`].
+#[derive(Clone, Debug)]
+pub struct Observed<S, D> {
+    inner: S,
+    describe: D,
+}
+
+impl<S, D, Req> Service<Req> for Observed<S, D>
+where
+    S: Service<Req>,
+    S::Error: Classify,
+    D: Describe<Req>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let labels = self.describe.describe(&req);
+        let span = info_span!(
+            "request",
+            exchange = labels.exchange,
+            method = %labels.method,
+            endpoint = %labels.endpoint,
+            signer = labels.signer,
+            weight = labels.weight,
+            costs = ?labels.costs,
+            status = field::Empty,
+            class = field::Empty,
+            latency_ms = field::Empty,
+        );
+        let inner = span.in_scope(|| self.inner.call(req));
+        ResponseFuture {
+            inner: inner.instrument(span),
+            labels: Some(labels),
+            start: Instant::now(),
+        }
+    }
+}
+
+/// A [`Future`] returned by [`Observed`].
+#[pin_project]
+#[derive(Debug)]
+pub struct ResponseFuture<F> {
+    #[pin]
+    inner: Instrumented<F>,
+    labels: Option<Labels>,
+    start: Instant,
+}
+
+impl<F, T, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+    E: Classify,
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        let result = ready!(this.inner.as_mut().poll(cx));
+        let latency = this.start.elapsed();
+        let class = match &result {
+            Ok(_) => "ok",
+            Err(e) => class_label(e.class()),
+        };
+
+        let span = this.inner.span();
+        span.record("class", class);
+        span.record("latency_ms", latency.as_millis() as u64);
+        span.in_scope(|| match &result {
+            Ok(_) => tracing::debug!(?latency, "request completed"),
+            Err(_) => tracing::debug!(?latency, class, "request failed"),
+        });
+
+        let labels = this.labels.take().expect("polled after completion");
+        let labels = [
+            Label::new("exchange", labels.exchange),
+            Label::new("method", labels.method.to_string()),
+            Label::new("endpoint", labels.endpoint),
+            Label::new("signer", labels.signer),
+            Label::new("class", class),
+        ];
+        increment_counter!("nerf_requests_total", labels.iter());
+        histogram!("nerf_request_duration_seconds", latency, labels.iter());
+
+        Poll::Ready(result)
+    }
+}
+
+fn class_label(x: ErrorClass) -> &'static str {
+    match x {
+        ErrorClass::Retryable => "retryable",
+        ErrorClass::RateLimited => "rate_limited",
+        ErrorClass::Authentication => "authentication",
+        ErrorClass::InvalidRequest => "invalid_request",
+        ErrorClass::OrderStateUnknown => "order_state_unknown",
+        ErrorClass::Other => "other",
+    }
+}
+
+/// A [`Layer`] of the transport(e.g. [`hyper::Client`]) recording HTTP statuses into the
+/// current span, i.e. the span of [`ObserveLayer`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StatusLayer;
+
+impl<S> Layer<S> for StatusLayer {
+    type Service = RecordStatus<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RecordStatus { inner }
+    }
+}
+
+/// A [`Service`] returned by [`StatusLayer`].
+#[derive(Clone, Debug)]
+pub struct RecordStatus<S> {
+    inner: S,
+}
+
+impl<S, B, ResBody> Service<http::Request<B>> for RecordStatus<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<ResBody>>,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = RecordStatusFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<B>) -> Self::Future {
+        RecordStatusFuture {
+            inner: self.inner.call(req),
+            span: Span::current(),
+        }
+    }
+}
+
+/// A [`Future`] returned by [`RecordStatus`].
+#[pin_project]
+#[derive(Debug)]
+pub struct RecordStatusFuture<F> {
+    #[pin]
+    inner: F,
+    span: Span,
+}
+
+impl<F, ResBody, E> Future for RecordStatusFuture<F>
+where
+    F: Future<Output = Result<http::Response<ResBody>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        if let Ok(x) = &result {
+            this.span.record("status", x.status().as_u16());
+        }
+        Poll::Ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    use futures::future::ready;
+    use tower::ServiceExt;
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        subscriber::set_default,
+        Subscriber,
+    };
+    use tracing_subscriber::{layer::Context as LayerContext, prelude::*, registry};
+
+    use super::*;
+    use crate::{NoErrorResponse, Request};
+
+    struct GetThing;
+
+    impl Request for GetThing {
+        type Response = ();
+        type ErrorResponse = NoErrorResponse;
+    }
+
+    impl HttpRequest for GetThing {
+        fn uri(&self) -> Result<http::Uri, crate::Error> {
+            Ok(http::Uri::from_static(
+                "https://example.com/things/1?verbose=true",
+            ))
+        }
+
+        fn method(&self) -> Method {
+            Method::GET
+        }
+
+        fn endpoint(&self) -> Cow<'static, str> {
+            Cow::Borrowed("https://example.com/things/{id}")
+        }
+    }
+
+    /// A request without its endpoint template.
+    struct GetOther;
+
+    impl HttpRequest for GetOther {
+        fn uri(&self) -> Result<http::Uri, crate::Error> {
+            Ok(http::Uri::from_static("https://example.com/others/1"))
+        }
+
+        fn method(&self) -> Method {
+            Method::GET
+        }
+    }
+
+    /// Collects fields of spans.
+    #[derive(Clone, Default)]
+    struct Fields(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            let value = format!("{value:?}");
+            self.0
+                .lock()
+                .unwrap()
+                .push((field.name().to_string(), value));
+        }
+    }
+
+    impl<S: Subscriber> tracing_subscriber::Layer<S> for Fields {
+        fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _cx: LayerContext<'_, S>) {
+            attrs.record(&mut self.clone());
+        }
+
+        fn on_record(&self, _span: &Id, values: &Record<'_>, _cx: LayerContext<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    /// Calls the transport, which responds with `404 Not Found`.
+    struct Client<T>(T);
+
+    impl<T> Service<GetThing> for Client<T>
+    where
+        T: Service<http::Request<()>, Response = http::Response<()>, Error = Infallible>,
+        T::Future: Send + 'static,
+    {
+        type Response = ();
+
+        type Error = NoErrorResponse;
+
+        type Future = Pin<Box<dyn Future<Output = Result<(), NoErrorResponse>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: GetThing) -> Self::Future {
+            let fut = self.0.call(http::Request::new(()));
+            Box::pin(async move {
+                let resp = fut.await.unwrap();
+                assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn describes_endpoints() {
+        let labels = Endpoints.describe(&GetThing);
+        assert_eq!(labels.method, Method::GET);
+        assert_eq!(labels.endpoint, "https://example.com/things/{id}");
+
+        // Formatted paths are not labels
+        assert_eq!(Endpoints.describe(&GetOther).endpoint, "unknown");
+    }
+
+    #[tokio::test]
+    async fn records_fields_into_span() {
+        let fields = Fields::default();
+        let transport = StatusLayer.layer(tower::service_fn(|_req: http::Request<()>| {
+            let mut resp = http::Response::new(());
+            *resp.status_mut() = http::StatusCode::NOT_FOUND;
+            ready(Ok::<_, Infallible>(resp))
+        }));
+        let svc = ObserveLayer::new(Endpoints).layer(Client(transport));
+        let _guard = set_default(registry().with(fields.clone()));
+        svc.oneshot(GetThing).await.unwrap();
+
+        let fields = fields.0.lock().unwrap();
+        let get = |name: &str| {
+            fields
+                .iter()
+                .find(|(x, _)| x == name)
+                .map(|(_, x)| x.clone())
+        };
+        assert_eq!(get("exchange").as_deref(), Some("\"unknown\""));
+        assert_eq!(get("method").as_deref(), Some("GET"));
+        assert_eq!(
+            get("endpoint").as_deref(),
+            Some("https://example.com/things/{id}")
+        );
+        assert_eq!(get("status").as_deref(), Some("404"));
+        assert_eq!(get("class").as_deref(), Some("\"ok\""));
+        assert!(get("latency_ms").is_some());
+    }
+}
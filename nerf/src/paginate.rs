@@ -0,0 +1,178 @@
+//! Streaming items of paged endpoints with [`Paginate::paginate`].
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures::Stream;
+use pin_project::pin_project;
+use tower::Service;
+
+use crate::Request;
+
+/// Requests on paged endpoints, e.g. with `page` and `limit` parameters or a cursor.
+pub trait Paginated: Request + Sized {
+    type Item;
+
+    /// Returns the request of the page after `response`, or `None` if `response` is the last page.
+    fn next_page(&self, response: &Self::Response) -> Option<Self>;
+
+    /// Extracts the items of a page.
+    fn items(response: Self::Response) -> Vec<Self::Item>;
+}
+
+/// A trait providing [`Paginate::paginate`] to [`Service`]s.
+pub trait Paginate<Req>: Service<Req, Response = Req::Response> + Sized
+where
+    Req: Paginated + Clone,
+{
+    /// Returns a [`Stream`] of items of every page from `first`.
+    ///
+    /// Pages are requested one by one when the service is ready, so the rate limits of the service
+    /// are respected. The stream ends after an error.
+    fn paginate(self, first: Req) -> Pages<Self, Req> {
+        Pages {
+            service: self,
+            next: Some(first),
+            current: None,
+            items: VecDeque::new(),
+            future: None,
+        }
+    }
+}
+
+impl<T, Req> Paginate<Req> for T
+where
+    T: Service<Req, Response = Req::Response>,
+    Req: Paginated + Clone,
+{
+}
+
+/// A [`Stream`] returned by [`Paginate::paginate`].
+#[pin_project]
+pub struct Pages<S, Req>
+where
+    S: Service<Req>,
+    Req: Paginated,
+{
+    service: S,
+    next: Option<Req>,
+    current: Option<Req>,
+    items: VecDeque<Req::Item>,
+    #[pin]
+    future: Option<S::Future>,
+}
+
+impl<S, Req> Stream for Pages<S, Req>
+where
+    S: Service<Req, Response = Req::Response>,
+    Req: Paginated + Clone,
+{
+    type Item = Result<Req::Item, S::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if let Some(item) = this.items.pop_front() {
+                return Poll::Ready(Some(Ok(item)));
+            }
+
+            if let Some(future) = this.future.as_mut().as_pin_mut() {
+                let result = ready!(future.poll(cx));
+                this.future.set(None);
+                let current = this.current.take().expect("request of the page is kept");
+                match result {
+                    Ok(response) => {
+                        *this.next = current.next_page(&response);
+                        this.items.extend(Req::items(response));
+                        continue;
+                    }
+                    Err(e) => return Poll::Ready(Some(Err(e))),
+                }
+            }
+
+            if this.next.is_none() {
+                return Poll::Ready(None);
+            }
+            if let Err(e) = ready!(this.service.poll_ready(cx)) {
+                *this.next = None;
+                return Poll::Ready(Some(Err(e)));
+            }
+            let req = this.next.take().expect("checked above");
+            this.future.set(Some(this.service.call(req.clone())));
+            *this.current = Some(req);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{ready, Ready},
+    };
+
+    use futures::StreamExt;
+
+    use super::*;
+
+    /// Requests `limit` numbers from `page * limit`.
+    #[derive(Clone)]
+    struct GetNumbers {
+        page: u32,
+        limit: u32,
+    }
+
+    impl Request for GetNumbers {
+        type Response = Vec<u32>;
+        type ErrorResponse = crate::NoErrorResponse;
+    }
+
+    impl Paginated for GetNumbers {
+        type Item = u32;
+
+        fn next_page(&self, response: &Self::Response) -> Option<Self> {
+            (response.len() == self.limit as usize).then(|| Self {
+                page: self.page + 1,
+                ..self.clone()
+            })
+        }
+
+        fn items(response: Self::Response) -> Vec<Self::Item> {
+            response
+        }
+    }
+
+    /// Serves numbers below 7.
+    struct Numbers;
+
+    impl Service<GetNumbers> for Numbers {
+        type Response = Vec<u32>;
+
+        type Error = Infallible;
+
+        type Future = Ready<Result<Vec<u32>, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: GetNumbers) -> Self::Future {
+            let start = req.page * req.limit;
+            ready(Ok((start..(start + req.limit).min(7)).collect()))
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_every_page() {
+        let items = Numbers
+            .paginate(GetNumbers { page: 0, limit: 3 })
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(items, (0..7).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,772 @@
+//! Weight-aware rate limiting with [`WeightedRateLimit`] requests.
+//!
+//! A [`RateLimiter`] tracks its budget locally, but servers often report their own view of it in
+//! response headers. Wrap the transport(e.g. [`hyper::Client`]) with
+//! [`RateLimiter::feedback_layer`] to feed those reports back into the limiter.
+
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use http::{header::RETRY_AFTER, HeaderMap, StatusCode};
+use pin_project::pin_project;
+use tokio::time::{Instant, Sleep};
+use tower::{Layer, Service};
+use tracing::{debug, trace};
+
+use crate::WeightedRateLimit;
+
+/// A fixed-window budget, e.g. 1200 weights per minute.
+///
+/// A request is charged its cost of the bucket from [`WeightedRateLimit::costs`], or its
+/// [`WeightedRateLimit::weight`] if the bucket is not listed.
+#[derive(Clone, Debug)]
+pub struct Bucket {
+    name: &'static str,
+    capacity: u64,
+    period: Duration,
+    listed_only: bool,
+}
+
+impl Bucket {
+    /// Creates a new [`Bucket`] which allows `capacity` weights for every `period`.
+    pub fn new(name: &'static str, capacity: u64, period: Duration) -> Self {
+        assert!(capacity > 0, "bucket capacity must be positive");
+        assert!(!period.is_zero(), "bucket period must be positive");
+        Self {
+            name,
+            capacity,
+            period,
+            listed_only: false,
+        }
+    }
+
+    /// Charges only requests which list the bucket in [`WeightedRateLimit::costs`], e.g. for
+    /// order count limits which other requests do not count towards.
+    ///
+    /// An exhausted bucket delays only the requests which list it, inside of their futures, as
+    /// [`RateLimit`] applies backpressure before it knows the next request.
+    pub fn listed_only(mut self) -> Self {
+        self.listed_only = true;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+#[derive(Debug)]
+struct BucketState {
+    bucket: Bucket,
+    used: u64,
+    window_end: Instant,
+}
+
+impl BucketState {
+    fn refresh(&mut self, now: Instant) {
+        if now >= self.window_end {
+            self.used = 0;
+            self.window_end = now + self.bucket.period;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    buckets: Vec<BucketState>,
+    cool_down_until: Option<Instant>,
+}
+
+impl State {
+    /// Returns the instant when every bucket but [`Bucket::listed_only`] ones accepts a request
+    /// again, or `None` if they already do.
+    fn blocked_until(&mut self, now: Instant) -> Option<Instant> {
+        let cool_down_until = self.cool_down_until.filter(|&x| x > now);
+        self.buckets
+            .iter_mut()
+            .filter(|x| !x.bucket.listed_only)
+            .filter_map(|x| {
+                x.refresh(now);
+                (x.used >= x.bucket.capacity).then_some(x.window_end)
+            })
+            .chain(cool_down_until)
+            .max()
+    }
+
+    /// Charges every bucket but [`Bucket::listed_only`] ones.
+    fn charge(&mut self, now: Instant, weight: u64, costs: &[(&'static str, u64)]) {
+        for x in self.buckets.iter_mut().filter(|x| !x.bucket.listed_only) {
+            let cost = match costs.iter().find(|(name, _)| *name == x.bucket.name) {
+                Some(&(_, cost)) => cost,
+                None => weight,
+            };
+            x.refresh(now);
+            x.used = x.used.saturating_add(cost);
+        }
+    }
+
+    /// Charges the [`Bucket::listed_only`] buckets listed in `costs`, or returns the instant when
+    /// all of them accept the request again.
+    fn charge_listed(&mut self, now: Instant, costs: &[(&'static str, u64)]) -> Option<Instant> {
+        let listed = |x: &BucketState| {
+            costs
+                .iter()
+                .find(|(name, _)| x.bucket.listed_only && *name == x.bucket.name)
+                .map(|&(_, cost)| cost)
+        };
+        let blocked_until = self
+            .buckets
+            .iter_mut()
+            .filter(|x| listed(x).is_some())
+            .filter_map(|x| {
+                x.refresh(now);
+                (x.used >= x.bucket.capacity).then_some(x.window_end)
+            })
+            .max();
+        if blocked_until.is_none() {
+            for x in &mut self.buckets {
+                if let Some(cost) = listed(x) {
+                    x.used = x.used.saturating_add(cost);
+                }
+            }
+        }
+        blocked_until
+    }
+
+    fn report(&mut self, now: Instant, usage: Usage) {
+        let (name, used) = match usage {
+            Usage::Used { bucket, used } => (bucket, used),
+            Usage::Remaining { bucket, remaining } => match self.find(bucket) {
+                Some(x) => (bucket, x.bucket.capacity.saturating_sub(remaining)),
+                None => return,
+            },
+            Usage::CoolDown(duration) => {
+                self.cool_down_until = self.cool_down_until.max(Some(now + duration));
+                return;
+            }
+        };
+
+        // The server-side window is not aligned with ours, so never lower the local count.
+        if let Some(x) = self.find(name) {
+            x.refresh(now);
+            x.used = x.used.max(used);
+        }
+    }
+
+    fn find(&mut self, name: &str) -> Option<&mut BucketState> {
+        self.buckets.iter_mut().find(|x| x.bucket.name == name)
+    }
+}
+
+/// A report of the server-side rate limit status, parsed from a response by [`ParseUsage`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Usage {
+    /// The server has counted `used` weights in the current window of the bucket.
+    Used { bucket: &'static str, used: u64 },
+    /// The server allows `remaining` more weights in the current window of the bucket.
+    Remaining {
+        bucket: &'static str,
+        remaining: u64,
+    },
+    /// The server asked to stop sending any requests for the duration.
+    CoolDown(Duration),
+}
+
+/// Parses [`Usage`]s from a response.
+///
+/// Reports for bucket names which the [`RateLimiter`] does not have are ignored.
+pub trait ParseUsage {
+    fn parse_usage(&self, status: StatusCode, headers: &HeaderMap) -> Vec<Usage>;
+}
+
+/// Reports a [`Usage::CoolDown`] on `429 Too Many Requests` and `418 I'm a teapot` responses.
+///
+/// The duration is read from the `Retry-After` header in seconds, falling back to the default
+/// duration if the header is missing or malformed.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryAfter {
+    default: Duration,
+}
+
+impl RetryAfter {
+    pub const fn new(default: Duration) -> Self {
+        Self { default }
+    }
+}
+
+impl ParseUsage for RetryAfter {
+    fn parse_usage(&self, status: StatusCode, headers: &HeaderMap) -> Vec<Usage> {
+        if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::IM_A_TEAPOT {
+            return Vec::new();
+        }
+
+        let duration = headers
+            .get(RETRY_AFTER)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.trim().parse().ok())
+            .map_or(self.default, Duration::from_secs);
+        vec![Usage::CoolDown(duration)]
+    }
+}
+
+/// A shared set of [`Bucket`]s.
+///
+/// Cloning a [`RateLimiter`] (or services created from it) shares the budget, so a single limiter
+/// should be created per rate limit scope(e.g. per IP or per account) and reused across clients.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    state: Arc<Mutex<State>>,
+}
+
+impl RateLimiter {
+    /// Creates a new [`RateLimiter`] with the buckets.
+    pub fn new(buckets: impl IntoIterator<Item = Bucket>) -> Self {
+        let now = Instant::now();
+        let buckets = buckets
+            .into_iter()
+            .map(|bucket| BucketState {
+                window_end: now + bucket.period,
+                bucket,
+                used: 0,
+            })
+            .collect();
+        Self {
+            state: Arc::new(Mutex::new(State {
+                buckets,
+                cool_down_until: None,
+            })),
+        }
+    }
+
+    /// Returns a [`Layer`] which applies this limiter to the wrapped service.
+    pub fn layer(&self) -> RateLimitLayer {
+        RateLimitLayer {
+            limiter: self.clone(),
+        }
+    }
+
+    /// Returns a [`Layer`] which reports the [`Usage`]s parsed from the responses of the wrapped
+    /// transport service to this limiter.
+    pub fn feedback_layer<P>(&self, parser: P) -> UsageFeedbackLayer<P> {
+        UsageFeedbackLayer::new(self.clone(), parser)
+    }
+
+    /// Applies a [`Usage`] reported by the server.
+    pub fn report(&self, usage: Usage) {
+        debug!(?usage, "rate limit usage reported");
+        self.lock().report(Instant::now(), usage);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("rate limiter state is poisoned")
+    }
+}
+
+/// A [`Layer`] producing [`RateLimit`] services.
+#[derive(Clone, Debug)]
+pub struct RateLimitLayer {
+    limiter: RateLimiter,
+}
+
+impl RateLimitLayer {
+    pub fn new(limiter: RateLimiter) -> Self {
+        Self { limiter }
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit::new(inner, self.limiter.clone())
+    }
+}
+
+/// Applies backpressure until every bucket of the [`RateLimiter`] has room, then charges each
+/// request its [`WeightedRateLimit::weight`], or its [`WeightedRateLimit::costs`] of named buckets.
+///
+/// The weight is charged on [`Service::call`], so a bucket may overdraw by at most one request's
+/// weight. Configure bucket capacities with that headroom in mind.
+///
+/// Requests listing an exhausted [`Bucket::listed_only`] bucket are not sent until it has room,
+/// which the returned future waits for, so that other requests are not delayed.
+pub struct RateLimit<S> {
+    inner: S,
+    limiter: RateLimiter,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> RateLimit<S> {
+    pub fn new(inner: S, limiter: RateLimiter) -> Self {
+        Self {
+            inner,
+            limiter,
+            sleep: None,
+        }
+    }
+
+    /// Returns the shared [`RateLimiter`].
+    pub fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Clone> Clone for RateLimit<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            limiter: self.limiter.clone(),
+            sleep: None,
+        }
+    }
+}
+
+impl<S: Debug> Debug for RateLimit<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("inner", &self.inner)
+            .field("limiter", &self.limiter)
+            .finish()
+    }
+}
+
+impl<S, Req> Service<Req> for RateLimit<S>
+where
+    S: Service<Req> + Clone,
+    Req: WeightedRateLimit,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = ResponseFuture<S, Req>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            if let Some(sleep) = &mut self.sleep {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.sleep = None;
+            }
+
+            let blocked_until = self.limiter.lock().blocked_until(Instant::now());
+            match blocked_until {
+                Some(until) => {
+                    trace!(wait = ?until.saturating_duration_since(Instant::now()), "rate limit exceeded; sleeping");
+                    self.sleep = Some(Box::pin(tokio::time::sleep_until(until)));
+                }
+                None => break,
+            }
+        }
+
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let now = Instant::now();
+        let costs = req.costs();
+        let blocked_until = {
+            let mut state = self.limiter.lock();
+            state.charge(now, req.weight(), &costs);
+            state.charge_listed(now, &costs)
+        };
+        match blocked_until {
+            None => ResponseFuture::Called {
+                inner: self.inner.call(req),
+            },
+            Some(until) => {
+                trace!(wait = ?until.saturating_duration_since(now), "rate limit exceeded; sleeping");
+                // The ready service is taken along, and the clone takes its place
+                let clone = self.inner.clone();
+                ResponseFuture::Waiting {
+                    sleep: tokio::time::sleep_until(until),
+                    inner: Some((std::mem::replace(&mut self.inner, clone), req)),
+                    limiter: self.limiter.clone(),
+                    costs,
+                }
+            }
+        }
+    }
+}
+
+/// A [`Future`] returned by [`RateLimit`].
+#[pin_project(project = ResponseFutureProj)]
+pub enum ResponseFuture<S: Service<Req>, Req> {
+    Called {
+        #[pin]
+        inner: S::Future,
+    },
+    /// Waiting for [`Bucket::listed_only`] buckets to have room.
+    Waiting {
+        #[pin]
+        sleep: Sleep,
+        inner: Option<(S, Req)>,
+        limiter: RateLimiter,
+        costs: Vec<(&'static str, u64)>,
+    },
+}
+
+impl<S: Service<Req>, Req> Future for ResponseFuture<S, Req> {
+    type Output = Result<S::Response, S::Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let inner = match self.as_mut().project() {
+                ResponseFutureProj::Called { inner } => return inner.poll(cx),
+                ResponseFutureProj::Waiting {
+                    mut sleep,
+                    inner,
+                    limiter,
+                    costs,
+                } => {
+                    ready!(sleep.as_mut().poll(cx));
+                    let now = Instant::now();
+                    if let Some(until) = limiter.lock().charge_listed(now, costs) {
+                        sleep.reset(until);
+                        continue;
+                    }
+                    let (mut svc, req) = inner.take().expect("polled after completion");
+                    svc.call(req)
+                }
+            };
+            self.set(ResponseFuture::Called { inner });
+        }
+    }
+}
+
+impl<S: Service<Req>, Req> Debug for ResponseFuture<S, Req> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let waiting = matches!(self, Self::Waiting { .. });
+        f.debug_struct("ResponseFuture")
+            .field("waiting", &waiting)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [`Layer`] producing [`UsageFeedback`] services.
+#[derive(Clone, Debug)]
+pub struct UsageFeedbackLayer<P> {
+    limiter: RateLimiter,
+    parser: P,
+}
+
+impl<P> UsageFeedbackLayer<P> {
+    pub fn new(limiter: RateLimiter, parser: P) -> Self {
+        Self { limiter, parser }
+    }
+}
+
+impl<S, P: Clone> Layer<S> for UsageFeedbackLayer<P> {
+    type Service = UsageFeedback<S, P>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        UsageFeedback::new(inner, self.limiter.clone(), self.parser.clone())
+    }
+}
+
+/// Wraps a transport service which returns [`http::Response`]s and reports the [`Usage`]s parsed
+/// from every response to the [`RateLimiter`].
+#[derive(Clone, Debug)]
+pub struct UsageFeedback<S, P> {
+    inner: S,
+    limiter: RateLimiter,
+    parser: P,
+}
+
+impl<S, P> UsageFeedback<S, P> {
+    pub fn new(inner: S, limiter: RateLimiter, parser: P) -> Self {
+        Self {
+            inner,
+            limiter,
+            parser,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S, P, Req, B> Service<Req> for UsageFeedback<S, P>
+where
+    S: Service<Req, Response = http::Response<B>>,
+    P: ParseUsage + Clone,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future = UsageFeedbackFuture<S::Future, P>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        UsageFeedbackFuture {
+            inner: self.inner.call(req),
+            limiter: self.limiter.clone(),
+            parser: self.parser.clone(),
+        }
+    }
+}
+
+#[pin_project]
+pub struct UsageFeedbackFuture<F, P> {
+    #[pin]
+    inner: F,
+    limiter: RateLimiter,
+    parser: P,
+}
+
+impl<F, P, B, E> Future for UsageFeedbackFuture<F, P>
+where
+    F: Future<Output = Result<http::Response<B>, E>>,
+    P: ParseUsage,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let resp = ready!(this.inner.poll(cx))?;
+        for usage in this.parser.parse_usage(resp.status(), resp.headers()) {
+            this.limiter.report(usage);
+        }
+        Poll::Ready(Ok(resp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures::future::{ready, Ready};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    struct Weighted(u64);
+
+    impl WeightedRateLimit for Weighted {
+        fn weight(&self) -> u64 {
+            self.0
+        }
+    }
+
+    /// An order which costs 1 weight and counts towards the order count.
+    struct Order;
+
+    impl WeightedRateLimit for Order {
+        fn weight(&self) -> u64 {
+            1
+        }
+
+        fn costs(&self) -> Vec<(&'static str, u64)> {
+            vec![("order", 1)]
+        }
+    }
+
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Weighted> for Echo {
+        type Response = u64;
+
+        type Error = Infallible;
+
+        type Future = Ready<Result<u64, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Weighted) -> Self::Future {
+            ready(Ok(req.0))
+        }
+    }
+
+    /// Accepts every order.
+    #[derive(Clone)]
+    struct Exchange;
+
+    impl Service<Order> for Exchange {
+        type Response = ();
+
+        type Error = Infallible;
+
+        type Future = Ready<Result<(), Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: Order) -> Self::Future {
+            ready(Ok(()))
+        }
+    }
+
+    /// Returns the request itself as the response.
+    struct Reflect;
+
+    impl Service<http::Response<()>> for Reflect {
+        type Response = http::Response<()>;
+
+        type Error = Infallible;
+
+        type Future = Ready<Result<http::Response<()>, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: http::Response<()>) -> Self::Future {
+            ready(Ok(req))
+        }
+    }
+
+    #[derive(Clone)]
+    struct UsedWeight;
+
+    impl ParseUsage for UsedWeight {
+        fn parse_usage(&self, _status: StatusCode, headers: &HeaderMap) -> Vec<Usage> {
+            headers
+                .get("x-used-weight")
+                .and_then(|x| x.to_str().ok()?.parse().ok())
+                .map(|used| Usage::Used {
+                    bucket: "weight",
+                    used,
+                })
+                .into_iter()
+                .collect()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_next_window() {
+        let limiter = RateLimiter::new([Bucket::new("weight", 10, Duration::from_secs(1))]);
+        let mut svc = limiter.layer().layer(Echo);
+        let start = Instant::now();
+
+        svc.ready().await.unwrap().call(Weighted(6)).await.unwrap();
+        svc.ready().await.unwrap().call(Weighted(6)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        svc.ready().await.unwrap().call(Weighted(1)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clones_share_buckets() {
+        let limiter = RateLimiter::new([
+            Bucket::new("second", 5, Duration::from_secs(1)),
+            Bucket::new("minute", 8, Duration::from_secs(60)),
+        ]);
+        let mut a = limiter.layer().layer(Echo);
+        let mut b = a.clone();
+        let start = Instant::now();
+
+        a.ready().await.unwrap().call(Weighted(5)).await.unwrap();
+        b.ready().await.unwrap().call(Weighted(3)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+
+        a.ready().await.unwrap().call(Weighted(1)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(60));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn charges_named_buckets() {
+        let limiter = RateLimiter::new([
+            Bucket::new("weight", 100, Duration::from_secs(60)),
+            Bucket::new("order", 2, Duration::from_secs(10)).listed_only(),
+        ]);
+        let mut svc = limiter.layer().layer(Echo);
+        let mut orders = limiter.layer().layer(Exchange);
+        let start = Instant::now();
+
+        // Not listed, so the order count is not charged
+        svc.ready().await.unwrap().call(Weighted(5)).await.unwrap();
+        orders.ready().await.unwrap().call(Order).await.unwrap();
+        orders.ready().await.unwrap().call(Order).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        // The order count delays orders only
+        let order = orders.ready().await.unwrap().call(Order);
+        svc.ready().await.unwrap().call(Weighted(5)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        order.await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn follows_reported_usage() {
+        let limiter = RateLimiter::new([Bucket::new("weight", 10, Duration::from_secs(1))]);
+        let mut transport = limiter.feedback_layer(UsedWeight).layer(Reflect);
+        let mut svc = limiter.layer().layer(Echo);
+        let start = Instant::now();
+
+        let resp = http::Response::builder()
+            .header("x-used-weight", "10")
+            .body(())
+            .unwrap();
+        transport.ready().await.unwrap().call(resp).await.unwrap();
+
+        svc.ready().await.unwrap().call(Weighted(1)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cools_down_after_too_many_requests() {
+        let limiter = RateLimiter::new([Bucket::new("weight", 10, Duration::from_secs(1))]);
+        let mut transport = limiter
+            .feedback_layer(RetryAfter::new(Duration::from_secs(1)))
+            .layer(Reflect);
+        let mut svc = limiter.layer().layer(Echo);
+        let start = Instant::now();
+
+        let resp = http::Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .header(RETRY_AFTER, "3")
+            .body(())
+            .unwrap();
+        transport.ready().await.unwrap().call(resp).await.unwrap();
+
+        svc.ready().await.unwrap().call(Weighted(1)).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+    }
+}
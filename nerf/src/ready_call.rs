@@ -1,3 +1,12 @@
+use std::{
+    future::Future,
+    iter::{Enumerate, Peekable},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures::{stream::FuturesUnordered, Stream};
+use pin_project::pin_project;
 use tower::{util::Oneshot, Service, ServiceExt};
 
 /// A trait providing [`ReadyCall::ready_call`] to [`Service`]s.
@@ -8,6 +17,177 @@ pub trait ReadyCall<Request>: Service<Request> + Sized {
     fn ready_call(&mut self, req: Request) -> Oneshot<&mut Self, Request> {
         self.oneshot(req)
     }
+
+    /// Calls the service with every request of `requests`, at most `limit` requests at once.
+    ///
+    /// Returns a [`Stream`] of the index of each request and its result, in the order of
+    /// completion. The stream owns the service, so it is `Send` if the service and the requests
+    /// are. A request is taken from `requests` only after the service is ready, so backpressure of
+    /// the service, e.g. a rate limiter, is respected.
+    ///
+    /// If the service fails to be ready, the error is yielded with the index of the next request
+    /// and no more requests are sent.
+    ///
+    /// Use `ReadyCall::call_all(svc, ..)` if [`ServiceExt`] is in scope, as it has an unbounded
+    /// `call_all` too.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    fn call_all<I>(self, requests: I, limit: usize) -> CallAll<Self, I::IntoIter, Request>
+    where
+        I: IntoIterator<Item = Request>,
+    {
+        assert!(limit > 0, "concurrency limit must be positive");
+        CallAll {
+            service: self,
+            requests: requests.into_iter().enumerate().peekable(),
+            in_flight: FuturesUnordered::new(),
+            limit,
+            exhausted: false,
+        }
+    }
 }
 
 impl<T, Request> ReadyCall<Request> for T where T: Service<Request> {}
+
+/// A [`Stream`] returned by [`ReadyCall::call_all`].
+#[pin_project]
+pub struct CallAll<S, I, Request>
+where
+    S: Service<Request>,
+    I: Iterator<Item = Request>,
+{
+    service: S,
+    requests: Peekable<Enumerate<I>>,
+    in_flight: FuturesUnordered<Indexed<S::Future>>,
+    limit: usize,
+    exhausted: bool,
+}
+
+impl<S, I, Request> Stream for CallAll<S, I, Request>
+where
+    S: Service<Request>,
+    I: Iterator<Item = Request>,
+{
+    type Item = (usize, Result<S::Response, S::Error>);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        while !*this.exhausted && this.in_flight.len() < *this.limit {
+            // The service is not polled without a request, not to reserve its capacity
+            if this.requests.peek().is_none() {
+                *this.exhausted = true;
+                break;
+            }
+            match this.service.poll_ready(cx) {
+                Poll::Ready(Ok(())) => {
+                    let (index, req) = this.requests.next().expect("peeked above");
+                    this.in_flight.push(Indexed {
+                        index,
+                        future: this.service.call(req),
+                    });
+                }
+                Poll::Ready(Err(e)) => {
+                    *this.exhausted = true;
+                    let (index, _) = this.requests.next().expect("peeked above");
+                    return Poll::Ready(Some((index, Err(e))));
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        match ready!(Pin::new(&mut *this.in_flight).poll_next(cx)) {
+            Some(x) => Poll::Ready(Some(x)),
+            None if *this.exhausted => Poll::Ready(None),
+            // Waiting for the service to be ready
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// A [`Future`] resolving to its output with the index of the request.
+#[pin_project]
+struct Indexed<F> {
+    index: usize,
+    #[pin]
+    future: F,
+}
+
+impl<F: Future> Future for Indexed<F> {
+    type Output = (usize, F::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = ready!(this.future.poll(cx));
+        Poll::Ready((*this.index, output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use futures::StreamExt;
+
+    use super::*;
+
+    /// Doubles numbers after `10 - x` milliseconds, tracking the number of calls in flight.
+    #[derive(Default)]
+    struct Double {
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl Service<u64> for Double {
+        type Response = u64;
+
+        type Error = Infallible;
+
+        type Future = Pin<Box<dyn Future<Output = Result<u64, Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, x: u64) -> Self::Future {
+            let in_flight = Arc::clone(&self.in_flight);
+            let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(current, Ordering::SeqCst);
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(10 - x)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(x * 2)
+            })
+        }
+    }
+
+    fn assert_send<T: Send>(x: T) -> T {
+        x
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn calls_concurrently_within_limit() {
+        let svc = Double::default();
+        let max_in_flight = Arc::clone(&svc.max_in_flight);
+        let mut results = assert_send(ReadyCall::call_all(svc, 0..10, 3))
+            .map(|(i, x)| (i, x.unwrap()))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+        // Faster calls complete first
+        assert_eq!(results[0], (2, 4));
+        results.sort();
+        assert_eq!(
+            results,
+            (0..10).map(|x| (x as usize, x * 2)).collect::<Vec<_>>()
+        );
+    }
+}
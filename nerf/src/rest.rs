@@ -0,0 +1,443 @@
+//! A generic [`Client`] for REST APIs.
+//!
+//! Most REST APIs differ only in a few places: how a request is encoded, how it is signed, and how
+//! the response body is wrapped. [`RestClient`] takes each of them as a separate part, so a new API
+//! needs a [`Codec`], an [`Envelope`] and a [`Signer`] instead of a whole [`Client`]
+//! implementation.
+
+use std::{convert::Infallible, fmt::Debug, future::Future, marker::PhantomData, pin::Pin};
+
+use bytes::Bytes;
+use http::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Method, Uri,
+};
+use serde::Serialize;
+
+use crate::{base_url::BaseUrl, Client, Error, HttpRequest, Request};
+
+/// Encodes requests into HTTP requests.
+pub trait Codec<Req> {
+    type Error;
+
+    fn encode(&mut self, x: &Req) -> Result<http::Request<Bytes>, Self::Error>;
+}
+
+/// Signs encoded requests, e.g. appends a signature header.
+///
+/// This also decides which requests a [`RestClient`] accepts, e.g. a signer without credentials
+/// may be implemented only for public endpoints.
+pub trait Signer<Req> {
+    type Error;
+
+    fn sign(&mut self, x: &mut http::Request<Bytes>) -> Result<(), Self::Error>;
+}
+
+/// Unwraps responses, e.g. extracts `data` from `{"code": "0", "data": [...]}`, or deserializes the
+/// error response.
+pub trait Envelope<Req: Request> {
+    type Error;
+
+    fn decode(x: http::Response<Bytes>) -> Result<Req::Response, Self::Error>;
+
+    /// Decodes the response before its body is received.
+    ///
+    /// Buffers the whole body and calls [`Envelope::decode`] by default. Envelopes of streaming
+    /// responses(e.g. [`Streamed`](crate::streaming::Streamed)) override this to decode the body
+    /// incrementally.
+    fn decode_body(x: hyper::Response<hyper::Body>) -> DecodeFuture<Req::Response, Self::Error>
+    where
+        Self::Error: From<hyper::Error> + Send + 'static,
+    {
+        Box::pin(async move {
+            let (parts, body) = x.into_parts();
+            let buf = hyper::body::to_bytes(body).await?;
+            Self::decode(http::Response::from_parts(parts, buf))
+        })
+    }
+}
+
+/// A [`Future`] returned by [`Envelope::decode_body`].
+pub type DecodeFuture<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send + 'static>>;
+
+/// A [`Signer`] for APIs without authentication, which accepts every request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Unsigned;
+
+impl<Req> Signer<Req> for Unsigned {
+    type Error = Infallible;
+
+    fn sign(&mut self, _x: &mut http::Request<Bytes>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Encodes requests with [`serde_urlencoded`], into the query string for `GET` requests or into
+/// the form body for others.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Form;
+
+impl<Req> Codec<Req> for Form
+where
+    Req: HttpRequest + Serialize,
+{
+    type Error = Error;
+
+    fn encode(&mut self, x: &Req) -> Result<http::Request<Bytes>, Self::Error> {
+        let params = serde_urlencoded::to_string(x).map_err(Error::SerializeUrlencoded)?;
+        if x.method() == Method::GET {
+            build(x, x.uri_with_query(&params)?, None, Bytes::new())
+        } else {
+            build(
+                x,
+                x.uri()?,
+                Some("application/x-www-form-urlencoded"),
+                params.into(),
+            )
+        }
+    }
+}
+
+/// Encodes requests into the query string with [`serde_urlencoded`] for `GET` requests, or into
+/// the JSON body for others.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl<Req> Codec<Req> for Json
+where
+    Req: HttpRequest + Serialize,
+{
+    type Error = Error;
+
+    fn encode(&mut self, x: &Req) -> Result<http::Request<Bytes>, Self::Error> {
+        if x.method() == Method::GET {
+            let params = serde_urlencoded::to_string(x).map_err(Error::SerializeUrlencoded)?;
+            build(x, x.uri_with_query(&params)?, None, Bytes::new())
+        } else {
+            let body = serde_json::to_vec(x).map_err(Error::SerializeRequest)?;
+            build(x, x.uri()?, Some("application/json"), body.into())
+        }
+    }
+}
+
+fn build<Req: HttpRequest>(
+    x: &Req,
+    uri: Uri,
+    content_type: Option<&'static str>,
+    body: Bytes,
+) -> Result<http::Request<Bytes>, Error> {
+    let mut builder = http::Request::builder()
+        .method(x.method())
+        .uri(uri)
+        .header(ACCEPT, "application/json");
+    if let Some(content_type) = content_type {
+        builder = builder.header(CONTENT_TYPE, content_type);
+    }
+    let mut req = builder.body(body).map_err(Error::ConstructRequest)?;
+    req.headers_mut().extend(x.headers()?);
+    Ok(req)
+}
+
+/// A [`Client`] composed of a [`Codec`] `C`, an [`Envelope`] `E` and a [`Signer`] `Sg`.
+///
+/// Requests are encoded with `C`, signed with `Sg` and sent to the service `S`. The responses are
+/// decoded with `E`, so errors of every part must be convertible into `E::Error`.
+pub struct RestClient<C, E, Sg, S> {
+    codec: C,
+    signer: Sg,
+    service: S,
+    base_url: Option<BaseUrl>,
+    _envelope: PhantomData<fn() -> E>,
+}
+
+impl<C, E, Sg, S> RestClient<C, E, Sg, S> {
+    /// Creates a new [`RestClient`] with the default codec and signer.
+    pub fn new(service: S) -> Self
+    where
+        C: Default,
+        Sg: Default,
+    {
+        Self::from_parts(C::default(), Sg::default(), service)
+    }
+
+    pub fn from_parts(codec: C, signer: Sg, service: S) -> Self {
+        Self {
+            codec,
+            signer,
+            service,
+            base_url: None,
+            _envelope: PhantomData,
+        }
+    }
+
+    /// Replaces the signer, e.g. to add credentials to a client for public endpoints.
+    pub fn with_signer<T>(self, signer: T) -> RestClient<C, E, T, S> {
+        RestClient {
+            codec: self.codec,
+            signer,
+            service: self.service,
+            base_url: self.base_url,
+            _envelope: PhantomData,
+        }
+    }
+
+    /// Sends requests to `base_url`, which is required for relative endpoints. Absolute endpoints
+    /// are sent to their own hosts without it.
+    pub fn with_base_url(mut self, base_url: BaseUrl) -> Self {
+        self.base_url = Some(base_url);
+        self
+    }
+}
+
+impl<C: Clone, E, Sg: Clone, S: Clone> Clone for RestClient<C, E, Sg, S> {
+    fn clone(&self) -> Self {
+        Self {
+            codec: self.codec.clone(),
+            signer: self.signer.clone(),
+            service: self.service.clone(),
+            base_url: self.base_url.clone(),
+            _envelope: PhantomData,
+        }
+    }
+}
+
+impl<C: Debug, E, Sg: Debug, S: Debug> Debug for RestClient<C, E, Sg, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RestClient")
+            .field("codec", &self.codec)
+            .field("envelope", &std::any::type_name::<E>())
+            .field("signer", &self.signer)
+            .field("service", &self.service)
+            .field("base_url", &self.base_url)
+            .finish()
+    }
+}
+
+impl<Req, C, E, Sg, S> Client<Req> for RestClient<C, E, Sg, S>
+where
+    Req: Request,
+    C: Codec<Req>,
+    C::Error: From<Error>,
+    E: Envelope<Req>,
+    E::Error: From<C::Error> + From<Sg::Error> + From<hyper::Error> + Send + 'static,
+    Sg: Signer<Req>,
+{
+    type Service = S;
+
+    type Error = E::Error;
+
+    type Context = ();
+
+    type TryFromResponseFuture = DecodeFuture<Req::Response, Self::Error>;
+
+    fn service(&mut self) -> &mut Self::Service {
+        &mut self.service
+    }
+
+    fn context(&mut self, _x: &Req) -> Self::Context {}
+
+    fn try_into_request(&mut self, x: Req) -> Result<hyper::Request<hyper::Body>, Self::Error> {
+        let mut req = self.codec.encode(&x)?;
+        if let Some(base_url) = &self.base_url {
+            *req.uri_mut() = base_url.join(req.uri()).map_err(C::Error::from)?;
+        }
+        self.signer.sign(&mut req)?;
+        Ok(req.map(hyper::Body::from))
+    }
+
+    fn try_from_response(
+        _cx: Self::Context,
+        x: hyper::Response<hyper::Body>,
+    ) -> Self::TryFromResponseFuture {
+        E::decode_body(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::{ready, Ready};
+    use http::StatusCode;
+    use serde::Deserialize;
+    use tower::{Service, ServiceExt};
+
+    use super::*;
+    use crate::IntoService;
+
+    #[derive(Serialize)]
+    struct GetThings {
+        name: &'static str,
+        limit: u32,
+    }
+
+    impl Request for GetThings {
+        type Response = Vec<u32>;
+        type ErrorResponse = crate::NoErrorResponse;
+    }
+
+    impl HttpRequest for GetThings {
+        fn uri(&self) -> Result<Uri, crate::Error> {
+            Ok(Uri::from_static("http://example.com/things"))
+        }
+
+        fn method(&self) -> Method {
+            Method::GET
+        }
+    }
+
+    #[derive(Serialize)]
+    struct PostThing {
+        name: &'static str,
+    }
+
+    impl HttpRequest for PostThing {
+        fn uri(&self) -> Result<Uri, crate::Error> {
+            Ok(Uri::from_static("http://example.com/things?dry_run=true"))
+        }
+
+        fn method(&self) -> Method {
+            Method::POST
+        }
+    }
+
+    #[test]
+    fn encodes_requests() {
+        let req = Form
+            .encode(&GetThings {
+                name: "a b",
+                limit: 3,
+            })
+            .unwrap();
+        assert_eq!(req.method(), Method::GET);
+        assert_eq!(req.uri(), "http://example.com/things?name=a+b&limit=3");
+        assert!(req.body().is_empty());
+
+        let req = Form.encode(&PostThing { name: "a" }).unwrap();
+        assert_eq!(req.method(), Method::POST);
+        assert_eq!(req.uri(), "http://example.com/things?dry_run=true");
+        assert_eq!(
+            req.headers()[CONTENT_TYPE],
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(req.body(), "name=a");
+
+        let req = Json.encode(&PostThing { name: "a" }).unwrap();
+        assert_eq!(req.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(req.body(), r#"{"name":"a"}"#);
+    }
+
+    #[derive(Debug)]
+    enum TestError {
+        Nerf,
+        Hyper,
+        Failed(StatusCode),
+    }
+
+    impl From<Error> for TestError {
+        fn from(_: Error) -> Self {
+            Self::Nerf
+        }
+    }
+
+    impl From<hyper::Error> for TestError {
+        fn from(_: hyper::Error) -> Self {
+            Self::Hyper
+        }
+    }
+
+    impl From<Infallible> for TestError {
+        fn from(x: Infallible) -> Self {
+            match x {}
+        }
+    }
+
+    /// Unwraps `{"data": ...}`.
+    struct Data;
+
+    impl<Req> Envelope<Req> for Data
+    where
+        Req: Request,
+        Req::Response: serde::de::DeserializeOwned,
+    {
+        type Error = TestError;
+
+        fn decode(x: http::Response<Bytes>) -> Result<Req::Response, Self::Error> {
+            #[derive(Deserialize)]
+            struct Response<T> {
+                data: T,
+            }
+
+            if !x.status().is_success() {
+                return Err(TestError::Failed(x.status()));
+            }
+            let resp: Response<Req::Response> =
+                serde_json::from_slice(x.body()).map_err(Error::DeserializeResponse)?;
+            Ok(resp.data)
+        }
+    }
+
+    #[derive(Default)]
+    struct Key;
+
+    impl<Req> Signer<Req> for Key {
+        type Error = Infallible;
+
+        fn sign(&mut self, x: &mut http::Request<Bytes>) -> Result<(), Self::Error> {
+            x.headers_mut().insert("x-key", "key".parse().unwrap());
+            Ok(())
+        }
+    }
+
+    /// Responds with the length of the query string if the request is signed.
+    struct Server;
+
+    impl Service<hyper::Request<hyper::Body>> for Server {
+        type Response = hyper::Response<hyper::Body>;
+
+        type Error = hyper::Error;
+
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(
+            &mut self,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
+            let resp = if req.headers().contains_key("x-key") {
+                let len = req.uri().query().unwrap_or_default().len();
+                hyper::Response::new(format!(r#"{{"data":[{len}]}}"#).into())
+            } else {
+                let mut resp = hyper::Response::new(hyper::Body::empty());
+                *resp.status_mut() = StatusCode::UNAUTHORIZED;
+                resp
+            };
+            ready(Ok(resp))
+        }
+    }
+
+    #[tokio::test]
+    async fn composes_parts() {
+        let req = || GetThings {
+            name: "a",
+            limit: 3,
+        };
+
+        let client = RestClient::<Form, Data, Key, _>::new(Server).into_service();
+        assert_eq!(client.oneshot(req()).await.unwrap(), vec![14]);
+
+        let client = RestClient::<Form, Data, Key, _>::new(Server)
+            .with_signer(Unsigned)
+            .into_service();
+        assert!(matches!(
+            client.oneshot(req()).await,
+            Err(TestError::Failed(StatusCode::UNAUTHORIZED))
+        ));
+
+        let mut client = RestClient::<Form, Data, Key, _>::new(Server)
+            .with_base_url(BaseUrl::from_static("http://localhost:8080"));
+        let req = Client::<GetThings>::try_into_request(&mut client, req()).unwrap();
+        assert_eq!(req.uri(), "http://localhost:8080/things?name=a&limit=3");
+    }
+}
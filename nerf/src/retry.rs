@@ -0,0 +1,230 @@
+//! Retrying [`Retryable`] requests with exponential backoff.
+//!
+//! [`RetryPolicy`] implements [`tower::retry::Policy`], so a client service can be wrapped with
+//! [`tower::retry::RetryLayer`]. Only requests declared as idempotent(e.g. with
+//! `#[retry(idempotent)]`) are sent again, and only when the error is [`TransientError`].
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use pin_project::pin_project;
+use rand::Rng;
+use tokio::time::Sleep;
+use tower::retry::Policy;
+use tracing::debug;
+
+use crate::Classify;
+
+/// Declares whether a request is safe to be sent again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryBehavior {
+    /// The request may have side effects(e.g. placing an order), so it is never sent again.
+    Never,
+    /// The request has no side effects and can be replayed.
+    ///
+    /// `max_attempts` overrides [`RetryPolicy`]'s maximum number of attempts if present.
+    Idempotent { max_attempts: Option<u32> },
+}
+
+/// Requests with a [`RetryBehavior`].
+///
+/// Usually implemented with [`nerf_macros::retry`].
+pub trait Retryable {
+    fn retry_behavior(&self) -> RetryBehavior;
+}
+
+/// Errors which may succeed if the request is sent again, e.g. transport errors or
+/// `5xx`/`429` responses.
+///
+/// Implemented for every [`Classify`] error by its [`ErrorClass`](crate::ErrorClass).
+pub trait TransientError {
+    fn is_transient(&self) -> bool;
+}
+
+impl<E: Classify> TransientError for E {
+    fn is_transient(&self) -> bool {
+        self.class().is_transient()
+    }
+}
+
+/// A [`Policy`] retrying idempotent requests on transient errors with exponential backoff and
+/// jitter.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    attempts: u32,
+}
+
+impl RetryPolicy {
+    /// Creates a new [`RetryPolicy`] which sends a request at most `max_attempts` times including
+    /// the first attempt.
+    ///
+    /// Backoff starts from 100 milliseconds and is capped at 10 seconds.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            attempts: 1,
+        }
+    }
+
+    /// Sets the initial backoff and its upper bound.
+    pub fn with_backoff(self, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            base_delay,
+            max_delay,
+            ..self
+        }
+    }
+
+    /// Returns the backoff before the next attempt, randomized between the half and the whole of
+    /// the exponential delay.
+    fn backoff(&self) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(self.attempts - 1))
+            .min(self.max_delay);
+        rand::thread_rng().gen_range(delay / 2..=delay)
+    }
+}
+
+impl<Req, Res, E> Policy<Req, Res, E> for RetryPolicy
+where
+    Req: Retryable + Clone,
+    E: TransientError,
+{
+    type Future = Backoff;
+
+    fn retry(&self, req: &Req, result: Result<&Res, &E>) -> Option<Self::Future> {
+        let max_attempts = match req.retry_behavior() {
+            RetryBehavior::Never => return None,
+            RetryBehavior::Idempotent { max_attempts } => max_attempts.unwrap_or(self.max_attempts),
+        };
+
+        match result {
+            Err(e) if e.is_transient() && self.attempts < max_attempts => {
+                let backoff = self.backoff();
+                debug!(attempts = self.attempts, ?backoff, "retrying request");
+                Some(Backoff {
+                    sleep: tokio::time::sleep(backoff),
+                    policy: Some(Self {
+                        attempts: self.attempts + 1,
+                        ..self.clone()
+                    }),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn clone_request(&self, req: &Req) -> Option<Req> {
+        match req.retry_behavior() {
+            RetryBehavior::Never => None,
+            RetryBehavior::Idempotent { .. } => Some(req.clone()),
+        }
+    }
+}
+
+/// A [`Future`] resolving to the next [`RetryPolicy`] after the backoff.
+#[pin_project]
+pub struct Backoff {
+    #[pin]
+    sleep: Sleep,
+    policy: Option<RetryPolicy>,
+}
+
+impl Future for Backoff {
+    type Output = RetryPolicy;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        match this.sleep.poll(cx) {
+            Poll::Ready(()) => Poll::Ready(this.policy.take().expect("polled after completion")),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    use futures::future::{ready, Ready};
+    use tower::{retry::RetryLayer, Layer, Service, ServiceExt};
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct Req(RetryBehavior);
+
+    impl Retryable for Req {
+        fn retry_behavior(&self) -> RetryBehavior {
+            self.0
+        }
+    }
+
+    #[derive(Debug)]
+    struct Transient;
+
+    impl TransientError for Transient {
+        fn is_transient(&self) -> bool {
+            true
+        }
+    }
+
+    /// Always fails, counting the calls.
+    #[derive(Clone, Default)]
+    struct Failing(Arc<AtomicU32>);
+
+    impl Service<Req> for Failing {
+        type Response = ();
+
+        type Error = Transient;
+
+        type Future = Ready<Result<(), Transient>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: Req) -> Self::Future {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            ready(Err(Transient))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_idempotent_requests() {
+        let inner = Failing::default();
+        let svc = RetryLayer::new(RetryPolicy::new(3)).layer(inner.clone());
+
+        let req = Req(RetryBehavior::Idempotent { max_attempts: None });
+        svc.clone().oneshot(req).await.unwrap_err();
+        assert_eq!(inner.0.swap(0, Ordering::SeqCst), 3);
+
+        let req = Req(RetryBehavior::Idempotent {
+            max_attempts: Some(5),
+        });
+        svc.oneshot(req).await.unwrap_err();
+        assert_eq!(inner.0.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn never_retries_non_idempotent_requests() {
+        let inner = Failing::default();
+        let svc = RetryLayer::new(RetryPolicy::new(3)).layer(inner.clone());
+
+        svc.oneshot(Req(RetryBehavior::Never)).await.unwrap_err();
+        assert_eq!(inner.0.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,177 @@
+//! Signing requests on the transport with [`SignLayer`].
+//!
+//! Clients mark requests to be signed with the [`SignatureRequired`] extension instead of signing
+//! them by themselves. The transport(e.g. [`hyper::Client`]) is wrapped with [`SignLayer`], so the
+//! signature is made right before the request is sent, after waiting in a
+//! [`tower::buffer::Buffer`] or a rate limiter, and every attempt is signed again.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use tower::{BoxError, Layer, Service};
+
+/// Signs requests marked with [`SignatureRequired`].
+pub trait Sign {
+    type Error;
+
+    fn sign(&self, x: &mut http::Request<Bytes>) -> Result<(), Self::Error>;
+}
+
+/// An extension of requests to be signed by [`SignLayer`].
+///
+/// Requests without this extension are sent as they are.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SignatureRequired;
+
+/// A [`Layer`] signing requests with `Sg`.
+#[derive(Debug)]
+pub struct SignLayer<Sg> {
+    signer: Arc<Sg>,
+}
+
+impl<Sg> SignLayer<Sg> {
+    pub fn new(signer: Sg) -> Self {
+        Self {
+            signer: Arc::new(signer),
+        }
+    }
+}
+
+impl<Sg> Clone for SignLayer<Sg> {
+    fn clone(&self) -> Self {
+        Self {
+            signer: Arc::clone(&self.signer),
+        }
+    }
+}
+
+impl<S, Sg> Layer<S> for SignLayer<Sg> {
+    type Service = Signed<S, Sg>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Signed {
+            inner,
+            signer: Arc::clone(&self.signer),
+        }
+    }
+}
+
+/// A transport [`Service`] signing requests marked with [`SignatureRequired`].
+///
+/// Bodies of those requests are buffered to be signed.
+#[derive(Debug)]
+pub struct Signed<S, Sg> {
+    inner: S,
+    signer: Arc<Sg>,
+}
+
+impl<S: Clone, Sg> Clone for Signed<S, Sg> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            signer: Arc::clone(&self.signer),
+        }
+    }
+}
+
+impl<S, Sg> Service<hyper::Request<hyper::Body>> for Signed<S, Sg>
+where
+    S: Service<hyper::Request<hyper::Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+    Sg: Sign + Send + Sync + 'static,
+    Sg::Error: Into<BoxError>,
+{
+    type Response = S::Response;
+
+    type Error = BoxError;
+
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
+        if req.extensions().get::<SignatureRequired>().is_none() {
+            let fut = self.inner.call(req);
+            return Box::pin(async move { fut.await.map_err(Into::into) });
+        }
+
+        // The ready service is taken to be called after the body is buffered
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let signer = Arc::clone(&self.signer);
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let mut req = http::Request::from_parts(parts, body);
+            signer.sign(&mut req).map_err(Into::into)?;
+            inner
+                .call(req.map(hyper::Body::from))
+                .await
+                .map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures::future::{ready, Ready};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    struct Header(&'static str);
+
+    impl Sign for Header {
+        type Error = Infallible;
+
+        fn sign(&self, x: &mut http::Request<Bytes>) -> Result<(), Self::Error> {
+            let signature = format!("{}:{}", self.0, String::from_utf8_lossy(x.body()));
+            x.headers_mut()
+                .insert("x-signature", signature.parse().unwrap());
+            Ok(())
+        }
+    }
+
+    /// Returns the signature header of the request.
+    #[derive(Clone)]
+    struct Reflect;
+
+    impl Service<hyper::Request<hyper::Body>> for Reflect {
+        type Response = Option<String>;
+
+        type Error = Infallible;
+
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
+            let signature = req.headers().get("x-signature");
+            ready(Ok(signature.map(|x| x.to_str().unwrap().to_string())))
+        }
+    }
+
+    #[tokio::test]
+    async fn signs_marked_requests_only() {
+        let svc = SignLayer::new(Header("key")).layer(Reflect);
+
+        let req = hyper::Request::new(hyper::Body::from("body"));
+        assert_eq!(svc.clone().oneshot(req).await.unwrap(), None);
+
+        let mut req = hyper::Request::new(hyper::Body::from("body"));
+        req.extensions_mut().insert(SignatureRequired);
+        assert_eq!(svc.oneshot(req).await.unwrap().as_deref(), Some("key:body"));
+    }
+}
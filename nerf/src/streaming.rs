@@ -0,0 +1,408 @@
+//! Streaming responses, e.g. Server-Sent Events or newline-delimited JSON.
+//!
+//! Requests with a [`FromBody`] response(e.g. [`NdJson`] or [`EventStream`]) are sent with the
+//! [`Streamed`] envelope of [`RestClient`](crate::rest::RestClient). The response resolves as soon
+//! as its headers are received, and the body is decoded incrementally while the stream is polled,
+//! so a slow consumer does not buffer the whole body.
+
+use std::{
+    convert::Infallible,
+    marker::PhantomData,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, Bytes, BytesMut};
+use futures::Stream;
+use hyper::body::HttpBody;
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::{
+    rest::{DecodeFuture, Envelope},
+    Classify, ErrorClass, Request,
+};
+
+/// Errors of streaming requests and their items.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Request(#[from] crate::Error),
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error("request to API server returned error, status: {status}, payload: {payload}")]
+    RequestFailed {
+        status: http::StatusCode,
+        payload: String,
+    },
+    #[error("cannot deserialize stream item: {0}, payload: {1}")]
+    DeserializeItem(serde_json::Error, String),
+}
+
+impl From<Infallible> for Error {
+    fn from(x: Infallible) -> Self {
+        match x {}
+    }
+}
+
+impl Classify for Error {
+    fn class(&self) -> ErrorClass {
+        match self {
+            Self::Request(e) => e.class(),
+            Self::Hyper(e) if e.is_connect() => ErrorClass::Retryable,
+            Self::Hyper(_) => ErrorClass::OrderStateUnknown,
+            Self::RequestFailed { status, .. } => ErrorClass::from_status(*status),
+            Self::DeserializeItem(..) => ErrorClass::Other,
+        }
+    }
+}
+
+/// Responses decoded from a streaming body.
+pub trait FromBody {
+    fn from_body(body: hyper::Body) -> Self;
+}
+
+/// An [`Envelope`] returning [`FromBody`] responses without buffering the body.
+///
+/// Error responses are buffered into [`Error::RequestFailed`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Streamed;
+
+impl<Req> Envelope<Req> for Streamed
+where
+    Req: Request,
+    Req::Response: FromBody,
+{
+    type Error = Error;
+
+    fn decode(x: http::Response<Bytes>) -> Result<Req::Response, Self::Error> {
+        if x.status().is_success() {
+            Ok(Req::Response::from_body(x.into_body().into()))
+        } else {
+            Err(Error::RequestFailed {
+                status: x.status(),
+                payload: String::from_utf8_lossy(x.body()).to_string(),
+            })
+        }
+    }
+
+    fn decode_body(x: hyper::Response<hyper::Body>) -> DecodeFuture<Req::Response, Self::Error> {
+        Box::pin(async move {
+            if x.status().is_success() {
+                return Ok(Req::Response::from_body(x.into_body()));
+            }
+            let status = x.status();
+            let buf = hyper::body::to_bytes(x.into_body()).await?;
+            Err(Error::RequestFailed {
+                status,
+                payload: String::from_utf8_lossy(&buf).to_string(),
+            })
+        })
+    }
+}
+
+/// Splits a body into lines, without line terminators(`\n` or `\r\n`).
+#[derive(Debug)]
+struct Lines {
+    body: hyper::Body,
+    buf: BytesMut,
+    done: bool,
+}
+
+impl Lines {
+    fn new(body: hyper::Body) -> Self {
+        Self {
+            body,
+            buf: BytesMut::new(),
+            done: false,
+        }
+    }
+
+    fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<String, Error>>> {
+        loop {
+            if let Some(pos) = self.buf.iter().position(|&b| b == b'\n') {
+                let line = self.buf.split_to(pos + 1);
+                let line = line.strip_suffix(b"\n").unwrap_or(&line);
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                return Poll::Ready(Some(Ok(String::from_utf8_lossy(line).to_string())));
+            }
+            if self.done {
+                if self.buf.is_empty() {
+                    return Poll::Ready(None);
+                }
+                // The last line without a terminator
+                let line = self.buf.split();
+                return Poll::Ready(Some(Ok(String::from_utf8_lossy(&line).to_string())));
+            }
+            match ready!(Pin::new(&mut self.body).poll_data(cx)) {
+                Some(Ok(mut chunk)) => {
+                    while chunk.has_remaining() {
+                        let bytes = chunk.chunk();
+                        let len = bytes.len();
+                        self.buf.extend_from_slice(bytes);
+                        chunk.advance(len);
+                    }
+                }
+                Some(Err(e)) => {
+                    self.done = true;
+                    self.buf.clear();
+                    return Poll::Ready(Some(Err(e.into())));
+                }
+                None => self.done = true,
+            }
+        }
+    }
+}
+
+/// A [`Stream`] of newline-delimited JSON(`application/x-ndjson`) items.
+///
+/// Empty lines are skipped.
+#[derive(Debug)]
+pub struct NdJson<T> {
+    lines: Lines,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> FromBody for NdJson<T> {
+    fn from_body(body: hyper::Body) -> Self {
+        Self {
+            lines: Lines::new(body),
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Stream for NdJson<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let line = match ready!(this.lines.poll_line(cx)) {
+                Some(Ok(x)) if x.trim().is_empty() => continue,
+                Some(Ok(x)) => x,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            };
+            return Poll::Ready(Some(
+                serde_json::from_str(&line).map_err(|e| Error::DeserializeItem(e, line)),
+            ));
+        }
+    }
+}
+
+/// An event of [`EventStream`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event<T> {
+    /// The `event` field, which is `message` if omitted.
+    pub event: Option<String>,
+    /// The last `id` field received.
+    pub id: Option<String>,
+    pub data: T,
+}
+
+/// A [`Stream`] of Server-Sent Events(`text/event-stream`) whose `data` is a JSON `T`.
+///
+/// Events without `data` are skipped. Use [`serde_json::Value`] as `T` to inspect data of
+/// different shapes.
+#[derive(Debug)]
+pub struct EventStream<T> {
+    lines: Lines,
+    event: Option<String>,
+    id: Option<String>,
+    data: Option<String>,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> FromBody for EventStream<T> {
+    fn from_body(body: hyper::Body) -> Self {
+        Self {
+            lines: Lines::new(body),
+            event: None,
+            id: None,
+            data: None,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Stream for EventStream<T> {
+    type Item = Result<Event<T>, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let line = match ready!(this.lines.poll_line(cx)) {
+                Some(Ok(x)) => x,
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                // An incomplete event at the end is discarded
+                None => return Poll::Ready(None),
+            };
+
+            if line.is_empty() {
+                let event = this.event.take();
+                if let Some(data) = this.data.take() {
+                    return Poll::Ready(Some(
+                        serde_json::from_str(&data)
+                            .map(|data| Event {
+                                event,
+                                id: this.id.clone(),
+                                data,
+                            })
+                            .map_err(|e| Error::DeserializeItem(e, data)),
+                    ));
+                }
+                continue;
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line.as_str(), ""),
+            };
+            match field {
+                // A comment
+                "" => {}
+                "event" => this.event = Some(value.to_string()),
+                "data" => match &mut this.data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => this.data = Some(value.to_string()),
+                },
+                "id" if !value.contains('\0') => this.id = Some(value.to_string()),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use serde::Deserialize;
+    use tower::{Service, ServiceExt};
+
+    use super::*;
+    use crate::{
+        rest::{Json, RestClient, Unsigned},
+        HttpRequest, IntoService,
+    };
+
+    #[derive(Debug, PartialEq, Eq, Deserialize)]
+    struct Tick {
+        price: u32,
+    }
+
+    /// Returns a body which sends `chunks` one by one.
+    fn body(chunks: &'static [&'static str]) -> hyper::Body {
+        let (mut tx, body) = hyper::Body::channel();
+        tokio::spawn(async move {
+            for &chunk in chunks {
+                tx.send_data(chunk.into()).await.unwrap();
+            }
+        });
+        body
+    }
+
+    #[tokio::test]
+    async fn decodes_ndjson_across_chunks() {
+        let items = NdJson::<Tick>::from_body(body(&[
+            "{\"price\":",
+            "1}\n{\"price\":2}\r\n\n{\"pri",
+            "ce\":3}",
+        ]))
+        .map(Result::unwrap)
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(
+            items,
+            [Tick { price: 1 }, Tick { price: 2 }, Tick { price: 3 }]
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_server_sent_events() {
+        let events = EventStream::<serde_json::Value>::from_body(body(&[
+            ": keep-alive\n\nevent: tick\nid: 1\ndata: {\"price\":\n",
+            "data: 1}\n\ndata:2\n\n",
+            "data: 3\n",
+        ]))
+        .map(Result::unwrap)
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(
+            events,
+            [
+                Event {
+                    event: Some("tick".to_string()),
+                    id: Some("1".to_string()),
+                    data: serde_json::json!({ "price": 1 }),
+                },
+                Event {
+                    event: None,
+                    id: Some("1".to_string()),
+                    data: serde_json::json!(2),
+                },
+            ]
+        );
+    }
+
+    #[derive(serde::Serialize)]
+    struct GetTicks;
+
+    impl Request for GetTicks {
+        type Response = NdJson<Tick>;
+        type ErrorResponse = crate::NoErrorResponse;
+    }
+
+    impl HttpRequest for GetTicks {
+        fn uri(&self) -> Result<http::Uri, crate::Error> {
+            Ok(http::Uri::from_static("http://example.com/ticks"))
+        }
+
+        fn method(&self) -> http::Method {
+            http::Method::GET
+        }
+    }
+
+    /// Streams ticks until the receiver is dropped.
+    struct Server;
+
+    impl Service<hyper::Request<hyper::Body>> for Server {
+        type Response = hyper::Response<hyper::Body>;
+
+        type Error = hyper::Error;
+
+        type Future = std::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: hyper::Request<hyper::Body>) -> Self::Future {
+            let (mut tx, body) = hyper::Body::channel();
+            tokio::spawn(async move {
+                for price in 0.. {
+                    let line = format!("{{\"price\":{price}}}\n");
+                    if tx.send_data(line.into()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            std::future::ready(Ok(hyper::Response::new(body)))
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_through_client() {
+        let client = RestClient::<Json, Streamed, Unsigned, _>::new(Server).into_service();
+        let ticks = client.oneshot(GetTicks).await.unwrap();
+        let prices = ticks
+            .take(3)
+            .map(|x| x.unwrap().price)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(prices, [0, 1, 2]);
+    }
+}
@@ -0,0 +1,530 @@
+//! Record/replay transports to test clients without network access.
+//!
+//! [`Recorder`] wraps a real transport(e.g. [`hyper::Client`]) and records request/response pairs
+//! into a [`Cassette`], and [`Replay`] serves the responses later. Cassettes can also be scripted
+//! with [`Interaction`]s.
+//!
+//! Requests are matched on the method, the path, the normalized query string and the normalized
+//! body: parameters of the query string and form or JSON bodies are sorted, and the
+//! [redacted](Redact) ones(e.g. `signature` and `timestamp`) are stripped. Request headers are not
+//! recorded at all, so credentials never reach the cassette. Bodies are stored in base64, not to
+//! corrupt binary payloads.
+
+use std::{
+    future::Future,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use base64::prelude::*;
+use bytes::Bytes;
+use http::{HeaderName, Method, StatusCode, Uri};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+use tower::{BoxError, Service};
+
+/// Request/response pairs to be replayed.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads a cassette saved with [`Cassette::save`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let buf = std::fs::read(path)?;
+        serde_json::from_slice(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Saves the cassette as a JSON file.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let buf = serde_json::to_vec_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, buf)
+    }
+
+    pub fn push(&mut self, x: Interaction) {
+        self.interactions.push(x);
+    }
+
+    /// Appends an interaction, to script a cassette without recording.
+    pub fn with(mut self, x: Interaction) -> Self {
+        self.push(x);
+        self
+    }
+
+    pub fn interactions(&self) -> &[Interaction] {
+        &self.interactions
+    }
+}
+
+/// An expected request and its response.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Interaction {
+    method: String,
+    path: String,
+    /// Matches any query string if `None`.
+    #[serde(default)]
+    query: Option<String>,
+    /// Matches any body if `None`.
+    #[serde(default)]
+    request_body: Option<Base64>,
+    status: u16,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    #[serde(default)]
+    body: Base64,
+}
+
+impl Interaction {
+    /// Expects a request on `path`(e.g. `/api/v3/depth`) with any query string and any body,
+    /// which is responded with `200 OK` and an empty body.
+    pub fn new(method: Method, path: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: None,
+            request_body: None,
+            status: StatusCode::OK.as_u16(),
+            headers: Vec::new(),
+            body: Base64::default(),
+        }
+    }
+
+    /// Expects the query string, e.g. `symbol=BTCUSDT&limit=5`. The order of parameters does not
+    /// matter, and an empty string expects no query string.
+    pub fn query(self, query: &str) -> Self {
+        Self {
+            query: Some(query.to_string()),
+            ..self
+        }
+    }
+
+    /// Expects the request body, e.g. `{"symbol":"BTCUSDT"}`. The order of form parameters or JSON
+    /// keys does not matter, and an empty body expects no body.
+    pub fn request_body(self, body: impl Into<Bytes>) -> Self {
+        Self {
+            request_body: Some(Base64(body.into())),
+            ..self
+        }
+    }
+
+    pub fn status(self, status: StatusCode) -> Self {
+        Self {
+            status: status.as_u16(),
+            ..self
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(self, body: impl Into<Bytes>) -> Self {
+        Self {
+            body: Base64(body.into()),
+            ..self
+        }
+    }
+
+    fn matches(&self, redact: &Redact, method: &Method, uri: &Uri, body: &[u8]) -> bool {
+        let query = match &self.query {
+            Some(query) => redact.normalize(query) == redact.normalize(uri.query().unwrap_or("")),
+            None => true,
+        };
+        let body = match &self.request_body {
+            Some(expected) => redact.normalize_body(&expected.0) == redact.normalize_body(body),
+            None => true,
+        };
+        self.method == method.as_str() && self.path == uri.path() && query && body
+    }
+
+    fn response(&self) -> Result<hyper::Response<hyper::Body>, BoxError> {
+        let mut builder = hyper::Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        Ok(builder.body(self.body.0.clone().into())?)
+    }
+}
+
+/// Bytes serialized as a base64 string.
+#[derive(Clone, Debug, Default)]
+struct Base64(Bytes);
+
+impl Serialize for Base64 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64_STANDARD.encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Base64 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let buf = BASE64_STANDARD.decode(s).map_err(de::Error::custom)?;
+        Ok(Self(buf.into()))
+    }
+}
+
+/// Query parameters and response headers stripped from cassettes.
+///
+/// The default strips `signature`, `timestamp`, `recvWindow` and `nonce` parameters and
+/// `set-cookie` headers.
+#[derive(Clone, Debug)]
+pub struct Redact {
+    params: Vec<String>,
+    headers: Vec<HeaderName>,
+}
+
+impl Default for Redact {
+    fn default() -> Self {
+        Self {
+            params: ["signature", "timestamp", "recvWindow", "nonce"]
+                .map(String::from)
+                .to_vec(),
+            headers: vec![http::header::SET_COOKIE],
+        }
+    }
+}
+
+impl Redact {
+    pub fn param(mut self, name: &str) -> Self {
+        self.params.push(name.to_string());
+        self
+    }
+
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.headers.push(name);
+        self
+    }
+
+    /// Sorts the keys of JSON bodies, or the parameters of form bodies, removing redacted ones.
+    /// Other bodies are compared as they are.
+    fn normalize_body(&self, body: &[u8]) -> Bytes {
+        if let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(body) {
+            if let Some(object) = value.as_object_mut() {
+                object.retain(|name, _| !self.params.contains(name));
+            }
+            // Keys of `serde_json::Map` are sorted
+            return serde_json::to_vec(&value)
+                .map_or_else(|_| Bytes::copy_from_slice(body), Bytes::from);
+        }
+        match std::str::from_utf8(body) {
+            Ok(form) => Bytes::from(self.normalize(form)),
+            Err(_) => Bytes::copy_from_slice(body),
+        }
+    }
+
+    /// Sorts the parameters, removing redacted ones.
+    fn normalize(&self, query: &str) -> String {
+        let mut params = match serde_urlencoded::from_str::<Vec<(String, String)>>(query) {
+            Ok(x) => x,
+            Err(_) => return query.to_string(),
+        };
+        params.retain(|(name, _)| !self.params.contains(name));
+        params.sort();
+        serde_urlencoded::to_string(params).unwrap_or_else(|_| query.to_string())
+    }
+}
+
+/// A transport recording request/response pairs into a [`Cassette`].
+#[derive(Clone, Debug)]
+pub struct Recorder<S> {
+    inner: S,
+    redact: Arc<Redact>,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl<S> Recorder<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            redact: Default::default(),
+            cassette: Default::default(),
+        }
+    }
+
+    pub fn with_redact(self, redact: Redact) -> Self {
+        Self {
+            redact: Arc::new(redact),
+            ..self
+        }
+    }
+
+    /// Returns the interactions recorded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.cassette().save(path)
+    }
+}
+
+impl<S> Service<hyper::Request<hyper::Body>> for Recorder<S>
+where
+    S: Service<hyper::Request<hyper::Body>, Response = hyper::Response<hyper::Body>>
+        + Clone
+        + Send
+        + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = hyper::Response<hyper::Body>;
+
+    type Error = BoxError;
+
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
+        // The body is read before the request is sent, so take the service which is ready
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let redact = Arc::clone(&self.redact);
+        let cassette = Arc::clone(&self.cassette);
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let interaction = Interaction::new(parts.method.clone(), parts.uri.path())
+                .query(&redact.normalize(parts.uri.query().unwrap_or("")))
+                .request_body(redact.normalize_body(&body));
+
+            let req = hyper::Request::from_parts(parts, body.into());
+            let (parts, body) = inner.call(req).await.map_err(Into::into)?.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+
+            let mut interaction = interaction.status(parts.status).body(body.clone());
+            for (name, value) in &parts.headers {
+                if let (false, Ok(value)) = (redact.headers.contains(name), value.to_str()) {
+                    interaction = interaction.header(name.as_str(), value);
+                }
+            }
+            cassette.lock().unwrap().push(interaction);
+
+            Ok(hyper::Response::from_parts(parts, body.into()))
+        })
+    }
+}
+
+/// No interaction of the [`Cassette`] matches the request.
+#[derive(Debug, Error)]
+#[error("no interaction matches the request {method} {uri}")]
+pub struct Unmatched {
+    pub method: Method,
+    pub uri: Uri,
+}
+
+/// A transport serving responses from a [`Cassette`].
+///
+/// Each interaction is replayed once, in the order of the cassette among matching ones. Requests
+/// without a matching interaction fail with [`Unmatched`].
+#[derive(Clone, Debug)]
+pub struct Replay {
+    redact: Arc<Redact>,
+    pending: Arc<Mutex<Vec<Interaction>>>,
+}
+
+impl Replay {
+    pub fn new(cassette: Cassette) -> Self {
+        Self {
+            redact: Default::default(),
+            pending: Arc::new(Mutex::new(cassette.interactions)),
+        }
+    }
+
+    pub fn with_redact(self, redact: Redact) -> Self {
+        Self {
+            redact: Arc::new(redact),
+            ..self
+        }
+    }
+
+    /// Returns the interactions which have not been replayed yet.
+    pub fn pending(&self) -> Vec<Interaction> {
+        self.pending.lock().unwrap().clone()
+    }
+}
+
+impl Service<hyper::Request<hyper::Body>> for Replay {
+    type Response = hyper::Response<hyper::Body>;
+
+    type Error = BoxError;
+
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: hyper::Request<hyper::Body>) -> Self::Future {
+        let redact = Arc::clone(&self.redact);
+        let pending = Arc::clone(&self.pending);
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = hyper::body::to_bytes(body).await?;
+            let mut pending = pending.lock().unwrap();
+            match pending
+                .iter()
+                .position(|x| x.matches(&redact, &parts.method, &parts.uri, &body))
+            {
+                Some(i) => pending.remove(i).response(),
+                None => Err(Box::new(Unmatched {
+                    method: parts.method,
+                    uri: parts.uri,
+                }) as BoxError),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn get(uri: &str) -> hyper::Request<hyper::Body> {
+        hyper::Request::get(uri).body(hyper::Body::empty()).unwrap()
+    }
+
+    async fn body(x: hyper::Response<hyper::Body>) -> String {
+        let buf = hyper::body::to_bytes(x.into_body()).await.unwrap();
+        String::from_utf8(buf.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_recorded_interactions() {
+        let server = tower::service_fn(|_req: hyper::Request<hyper::Body>| async {
+            hyper::Response::builder()
+                .header("set-cookie", "session=secret")
+                .header("x-used-weight", "1")
+                .body(hyper::Body::from(r#"{"price":"1.0"}"#))
+        });
+        let recorder = Recorder::new(server);
+        let resp = recorder
+            .clone()
+            .oneshot(get(
+                "http://example.com/api?symbol=BTC&timestamp=1&signature=a",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(body(resp).await, r#"{"price":"1.0"}"#);
+
+        let path = std::env::temp_dir().join(format!("nerf-cassette-{}.json", std::process::id()));
+        recorder.save(&path).unwrap();
+        let cassette = Cassette::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            cassette.interactions()[0].query.as_deref(),
+            Some("symbol=BTC")
+        );
+
+        let replay = Replay::new(cassette);
+        let resp = replay
+            .clone()
+            .oneshot(get(
+                "http://example.com/api?signature=b&timestamp=2&symbol=BTC",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["x-used-weight"], "1");
+        assert!(!resp.headers().contains_key("set-cookie"));
+        assert_eq!(body(resp).await, r#"{"price":"1.0"}"#);
+
+        let err = replay
+            .oneshot(get("http://example.com/api?symbol=BTC"))
+            .await
+            .unwrap_err();
+        assert!(err.is::<Unmatched>());
+    }
+
+    #[tokio::test]
+    async fn replays_scripted_interactions_in_order() {
+        let cassette = Cassette::new()
+            .with(Interaction::new(Method::GET, "/api").status(StatusCode::SERVICE_UNAVAILABLE))
+            .with(
+                Interaction::new(Method::GET, "/api")
+                    .query("b=2&a=1")
+                    .body("ok"),
+            )
+            .with(Interaction::new(Method::POST, "/api"));
+        let replay = Replay::new(cassette);
+
+        let resp = replay.clone().oneshot(get("/api?a=1&b=2")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let err = replay.clone().oneshot(get("/api?a=3")).await.unwrap_err();
+        assert!(err.is::<Unmatched>());
+        let resp = replay.clone().oneshot(get("/api?a=1&b=2")).await.unwrap();
+        assert_eq!(body(resp).await, "ok");
+
+        assert_eq!(replay.pending().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn matches_queries_and_bodies_exactly() {
+        // Responds with the request body, or non-UTF-8 bytes if there is none
+        let server = tower::service_fn(|req: hyper::Request<hyper::Body>| async {
+            let body = hyper::body::to_bytes(req.into_body()).await?;
+            let body = if body.is_empty() {
+                Bytes::from_static(&[0xff, 0xfe])
+            } else {
+                body
+            };
+            Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from(body)))
+        });
+        let recorder = Recorder::new(server);
+        let post = |body: &'static str| {
+            hyper::Request::post("/orders")
+                .body(hyper::Body::from(body))
+                .unwrap()
+        };
+        for req in [
+            get("/api"),
+            post(r#"{"symbol":"BTC","timestamp":1}"#),
+            post("symbol=ETH&timestamp=1"),
+        ] {
+            recorder.clone().oneshot(req).await.unwrap();
+        }
+
+        let cassette = recorder.cassette();
+        let cassette: Cassette =
+            serde_json::from_slice(&serde_json::to_vec(&cassette).unwrap()).unwrap();
+        let replay = Replay::new(cassette);
+
+        let err = replay
+            .clone()
+            .oneshot(get("/api?symbol=BTC"))
+            .await
+            .unwrap_err();
+        assert!(err.is::<Unmatched>());
+        let resp = replay.clone().oneshot(get("/api")).await.unwrap();
+        let buf = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(buf.as_ref(), [0xff, 0xfe]);
+
+        let resp = replay
+            .clone()
+            .oneshot(post("timestamp=2&symbol=ETH"))
+            .await
+            .unwrap();
+        assert_eq!(body(resp).await, "symbol=ETH&timestamp=1");
+        let resp = replay
+            .clone()
+            .oneshot(post(r#"{"timestamp":2,"symbol":"BTC"}"#))
+            .await
+            .unwrap();
+        assert_eq!(body(resp).await, r#"{"symbol":"BTC","timestamp":1}"#);
+        assert!(replay.pending().is_empty());
+    }
+}
@@ -0,0 +1,289 @@
+//! Per-request timeouts with [`TimeoutLayer`].
+//!
+//! Requests declare their timeouts with [`Deadline`], usually with the `timeout = "800ms"`
+//! argument of the HTTP attribute macros(e.g. [`nerf_macros::get`]). [`TimeoutLayer`] enforces
+//! them, and can override them at runtime.
+//!
+//! A timed-out request may still be processed by the server. Requests with side effects, i.e.
+//! [`Deadline::has_side_effects`], fail with [`Error::OutcomeUnknown`] so that the caller queries
+//! the state(e.g. of the order) before sending it again.
+
+use std::{
+    any::TypeId,
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use pin_project::pin_project;
+use thiserror::Error;
+use tokio::time::{sleep, Sleep};
+use tower::{Layer, Service};
+
+use crate::{Classify, ErrorClass};
+
+/// Requests with a declared timeout and side effects.
+///
+/// Implemented by the HTTP attribute macros, e.g. [`nerf_macros::get`], and by [`nerf_macros::rpc`].
+/// Other requests can implement it with the defaults, `impl Deadline for MyRequest {}`.
+pub trait Deadline {
+    /// Returns the timeout of the request, or `None` to use the default of [`TimeoutLayer`].
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns `true` if the request may change the state of the server, e.g. places an order.
+    ///
+    /// Defaults to `true`, so that a timeout of an undeclared request is
+    /// [`Error::OutcomeUnknown`].
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+}
+
+/// Errors of [`TimeoutService`].
+#[derive(Error, Debug)]
+pub enum Error<E> {
+    #[error("{0}")]
+    Inner(E),
+    /// The request timed out. It has no side effects, so it is safe to be sent again.
+    #[error("request timed out after {0:?}")]
+    Elapsed(Duration),
+    /// The request with side effects timed out, so it may or may not have taken effect.
+    #[error("request timed out after {0:?}, its outcome is unknown")]
+    OutcomeUnknown(Duration),
+}
+
+impl<E: Classify> Classify for Error<E> {
+    fn class(&self) -> ErrorClass {
+        match self {
+            Self::Inner(e) => e.class(),
+            Self::Elapsed(_) => ErrorClass::Retryable,
+            Self::OutcomeUnknown(_) => ErrorClass::OrderStateUnknown,
+        }
+    }
+}
+
+/// A [`Layer`] enforcing timeouts of [`Deadline`] requests.
+///
+/// The timeout of a request is, in order of precedence, its override set with
+/// [`TimeoutLayer::with_override`], its declared [`Deadline::timeout`] and the default set with
+/// [`TimeoutLayer::with_default`]. Requests without any of them never time out.
+///
+/// Place this inside of [`tower::retry::RetryLayer`] with [`RetryPolicy`](crate::retry::RetryPolicy)
+/// to time each attempt.
+#[derive(Clone, Debug, Default)]
+pub struct TimeoutLayer {
+    default: Option<Duration>,
+    overrides: Arc<HashMap<TypeId, Duration>>,
+}
+
+impl TimeoutLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the timeout of requests without a declared timeout.
+    pub fn with_default(self, timeout: Duration) -> Self {
+        Self {
+            default: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Overrides the timeout of `Req`, whether it is declared or not.
+    pub fn with_override<Req: 'static>(mut self, timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.overrides).insert(TypeId::of::<Req>(), timeout);
+        self
+    }
+}
+
+impl<S> Layer<S> for TimeoutLayer {
+    type Service = TimeoutService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimeoutService {
+            inner,
+            default: self.default,
+            overrides: Arc::clone(&self.overrides),
+        }
+    }
+}
+
+/// A [`Service`] enforcing timeouts of [`Deadline`] requests. See [`TimeoutLayer`].
+#[derive(Clone, Debug)]
+pub struct TimeoutService<S> {
+    inner: S,
+    default: Option<Duration>,
+    overrides: Arc<HashMap<TypeId, Duration>>,
+}
+
+impl<S, Req> Service<Req> for TimeoutService<S>
+where
+    S: Service<Req>,
+    Req: Deadline + 'static,
+{
+    type Response = S::Response;
+
+    type Error = Error<S::Error>;
+
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Error::Inner)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let timeout = self
+            .overrides
+            .get(&TypeId::of::<Req>())
+            .copied()
+            .or_else(|| req.timeout())
+            .or(self.default);
+        let side_effects = req.has_side_effects();
+        ResponseFuture {
+            inner: self.inner.call(req),
+            sleep: timeout.map(sleep),
+            timeout: timeout.unwrap_or_default(),
+            side_effects,
+        }
+    }
+}
+
+/// A [`Future`] returned by [`TimeoutService`].
+#[pin_project]
+pub struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    #[pin]
+    sleep: Option<Sleep>,
+    timeout: Duration,
+    side_effects: bool,
+}
+
+impl<F, T, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    type Output = Result<T, Error<E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Poll::Ready(x) = this.inner.poll(cx) {
+            return Poll::Ready(x.map_err(Error::Inner));
+        }
+        if let Some(sleep) = this.sleep.as_pin_mut() {
+            ready!(sleep.poll(cx));
+            let timeout = *this.timeout;
+            return Poll::Ready(Err(if *this.side_effects {
+                Error::OutcomeUnknown(timeout)
+            } else {
+                Error::Elapsed(timeout)
+            }));
+        }
+        Poll::Pending
+    }
+}
+
+impl<F> std::fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseFuture")
+            .field("timeout", &self.timeout)
+            .field("side_effects", &self.side_effects)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E> Error<E> {
+    /// Returns `true` if the request may have taken effect.
+    pub fn is_outcome_unknown(&self) -> bool {
+        matches!(self, Self::OutcomeUnknown(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{NoErrorResponse, Request};
+
+    struct GetTicker;
+
+    impl Request for GetTicker {
+        type Response = ();
+        type ErrorResponse = NoErrorResponse;
+    }
+
+    impl Deadline for GetTicker {
+        fn timeout(&self) -> Option<Duration> {
+            Some(Duration::from_millis(100))
+        }
+
+        fn has_side_effects(&self) -> bool {
+            false
+        }
+    }
+
+    struct PlaceOrder;
+
+    impl Request for PlaceOrder {
+        type Response = ();
+        type ErrorResponse = NoErrorResponse;
+    }
+
+    // No declaration, which is assumed to have side effects
+    impl Deadline for PlaceOrder {}
+
+    /// Responds after a second.
+    #[derive(Clone)]
+    struct Slow;
+
+    impl<Req> Service<Req> for Slow {
+        type Response = ();
+
+        type Error = Infallible;
+
+        type Future = Pin<Box<dyn Future<Output = Result<(), Infallible>> + Send>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: Req) -> Self::Future {
+            Box::pin(async {
+                sleep(Duration::from_secs(1)).await;
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn enforces_declared_timeouts() {
+        let svc = TimeoutLayer::new().layer(Slow);
+        let err = svc.clone().oneshot(GetTicker).await.unwrap_err();
+        assert!(matches!(err, Error::Elapsed(x) if x == Duration::from_millis(100)));
+        assert_eq!(err.class(), ErrorClass::Retryable);
+
+        // No timeout is declared
+        svc.oneshot(PlaceOrder).await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overrides_at_runtime() {
+        let svc = TimeoutLayer::new()
+            .with_default(Duration::from_millis(500))
+            .with_override::<GetTicker>(Duration::from_secs(2))
+            .layer(Slow);
+        svc.clone().oneshot(GetTicker).await.unwrap();
+
+        let err = svc.oneshot(PlaceOrder).await.unwrap_err();
+        assert!(err.is_outcome_unknown());
+        assert_eq!(err.class(), ErrorClass::OrderStateUnknown);
+    }
+}
@@ -0,0 +1,693 @@
+//! WebSocket subscriptions.
+//!
+//! [`Subscription`]s are typed like [`Request`](crate::Request)s, usually with
+//! [`nerf_macros::subscription`]. A [`Connection`] keeps a WebSocket to the server in a background
+//! task: it pings the server, reconnects on failures and subscribes again, and demultiplexes
+//! pushed messages by their topic into [`Subscribed`] streams. How subscriptions and pushed
+//! messages are framed is up to the [`Protocol`] of the server.
+//!
+//! Each [`Subscribed`] stream buffers a bounded number of messages. Messages to a stream which is
+//! not polled fast enough are dropped, and reported with [`Error::Lagged`].
+//!
+//! Enabled with the `ws` feature.
+
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use futures::{SinkExt, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+use tokio::{
+    sync::mpsc::{
+        channel, error::TrySendError, unbounded_channel, Receiver, Sender, UnboundedReceiver,
+        UnboundedSender,
+    },
+    time::{interval_at, sleep, timeout, Instant},
+};
+use tokio_tungstenite::connect_async;
+use tracing::{debug, warn};
+
+pub use tokio_tungstenite::tungstenite::Message;
+
+/// Errors of WebSocket connections and their subscriptions.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    WebSocket(#[from] Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("cannot serialize subscription: {0}")]
+    SerializeSubscription(serde_json::Error),
+    #[error("cannot deserialize message: {0}, payload: {1}")]
+    DeserializeMessage(serde_json::Error, String),
+    #[error("server did not respond for {0:?}")]
+    Timeout(Duration),
+    #[error("connection is closed by server")]
+    Closed,
+    /// The connection is lost, so messages may have been missed until it is subscribed again.
+    #[error("connection is lost, resubscribing")]
+    Disconnected,
+    #[error("connection manager is stopped")]
+    Stopped,
+    /// The subscriber did not keep up with pushed messages, so the number of messages are dropped.
+    #[error("subscriber is lagging behind, {0} messages are dropped")]
+    Lagged(u64),
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(x: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::WebSocket(Box::new(x))
+    }
+}
+
+/// Subscriptions to messages pushed by a WebSocket server.
+///
+/// Usually implemented with [`nerf_macros::subscription`].
+pub trait Subscription: Serialize {
+    /// Messages pushed on the subscription.
+    type Message: DeserializeOwned;
+
+    /// Returns the topic of the subscription, e.g. `btcusdt@trade`.
+    ///
+    /// Pushed messages are demultiplexed with topics returned by [`Protocol::demux`], so
+    /// subscriptions with the same topic share the subscription on the server.
+    fn topic(&self) -> String;
+}
+
+/// The framing of a WebSocket API.
+pub trait Protocol: Send + 'static {
+    /// Returns the message subscribing to `topic`. `params` is the serialized [`Subscription`].
+    fn subscribe(&mut self, topic: &str, params: &Value) -> Message;
+
+    /// Returns the message unsubscribing from `topic`, or `None` if the server cannot unsubscribe.
+    fn unsubscribe(&mut self, topic: &str, params: &Value) -> Option<Message>;
+
+    /// Returns the topic and the payload of a pushed message, or `None` if `text` is not a pushed
+    /// message, e.g. an acknowledgement of subscription.
+    fn demux(&self, text: &str) -> Option<(String, Value)>;
+
+    /// Returns the keep-alive message, which is a ping frame by default.
+    ///
+    /// Every message from the server, including pongs, proves the connection is alive.
+    fn ping(&self) -> Message {
+        Message::Ping(Vec::new())
+    }
+}
+
+/// Configures and spawns a [`Connection`].
+#[derive(Clone, Debug)]
+pub struct Connector<P> {
+    url: String,
+    protocol: P,
+    ping_interval: Duration,
+    timeout: Duration,
+    base_delay: Duration,
+    max_delay: Duration,
+    buffer: usize,
+}
+
+impl<P: Protocol> Connector<P> {
+    /// Creates a new [`Connector`] to `url`.
+    ///
+    /// The server is pinged every 20 seconds and the connection is dropped if the server does not
+    /// respond for a minute, including the opening handshake. Reconnection is delayed from 1
+    /// second up to 30 seconds. Each subscriber buffers up to 1024 messages.
+    pub fn new(url: impl Into<String>, protocol: P) -> Self {
+        Self {
+            url: url.into(),
+            protocol,
+            ping_interval: Duration::from_secs(20),
+            timeout: Duration::from_secs(60),
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            buffer: 1024,
+        }
+    }
+
+    /// Sets the interval of [`Protocol::ping`] and the timeout of the server's response, which
+    /// applies to the opening handshake too.
+    pub fn with_ping(self, interval: Duration, timeout: Duration) -> Self {
+        Self {
+            ping_interval: interval,
+            timeout,
+            ..self
+        }
+    }
+
+    /// Sets the initial delay of reconnection and its upper bound. The delay is doubled on every
+    /// failure and reset after a successful connection.
+    pub fn with_backoff(self, base_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            base_delay,
+            max_delay,
+            ..self
+        }
+    }
+
+    /// Sets the number of messages buffered for each subscriber.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer` is zero.
+    pub fn with_buffer(self, buffer: usize) -> Self {
+        assert!(buffer > 0, "buffer must be positive");
+        Self { buffer, ..self }
+    }
+
+    /// Spawns the connection manager onto the current Tokio runtime.
+    ///
+    /// The server is connected in the background, so subscriptions can be made right away. The
+    /// manager stops when the [`Connection`] and every [`Subscribed`] stream are dropped.
+    pub fn connect(self) -> Connection {
+        let (commands, rx) = unbounded_channel();
+        let buffer = self.buffer;
+        let manager = Manager {
+            connector: self,
+            commands: rx,
+            topics: HashMap::new(),
+        };
+        tokio::spawn(manager.run());
+        Connection { commands, buffer }
+    }
+}
+
+/// A handle of a WebSocket connection spawned with [`Connector::connect`].
+#[derive(Clone, Debug)]
+pub struct Connection {
+    commands: UnboundedSender<Command>,
+    buffer: usize,
+}
+
+impl Connection {
+    /// Subscribes to `subscription`, returning a [`Stream`] of its messages.
+    ///
+    /// The subscription is made again whenever the connection is reestablished, and
+    /// [`Error::Disconnected`] is yielded in between. [`Error::Lagged`] is yielded if messages are
+    /// dropped because the stream is not polled fast enough. Dropping the stream unsubscribes.
+    ///
+    /// Returns [`Error::Stopped`] if the connection manager has panicked.
+    pub fn subscribe<T: Subscription>(
+        &self,
+        subscription: &T,
+    ) -> Result<Subscribed<T::Message>, Error> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let topic = subscription.topic();
+        let params = serde_json::to_value(subscription).map_err(Error::SerializeSubscription)?;
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = channel(self.buffer);
+        self.commands
+            .send(Command::Subscribe {
+                topic: topic.clone(),
+                params,
+                id,
+                tx,
+            })
+            .map_err(|_| Error::Stopped)?;
+        Ok(Subscribed {
+            topic,
+            id,
+            messages: rx,
+            commands: self.commands.clone(),
+            _message: PhantomData,
+        })
+    }
+}
+
+/// A [`Stream`] of messages of a subscription, returned by [`Connection::subscribe`].
+#[derive(Debug)]
+pub struct Subscribed<M> {
+    topic: String,
+    id: u64,
+    messages: Receiver<Result<Value, Error>>,
+    commands: UnboundedSender<Command>,
+    _message: PhantomData<fn() -> M>,
+}
+
+impl<M> Subscribed<M> {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+impl<M: DeserializeOwned> Stream for Subscribed<M> {
+    type Item = Result<M, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let item = match ready!(this.messages.poll_recv(cx)) {
+            Some(Ok(x)) => {
+                M::deserialize(&x).map_err(|e| Error::DeserializeMessage(e, x.to_string()))
+            }
+            Some(Err(e)) => Err(e),
+            None => return Poll::Ready(None),
+        };
+        Poll::Ready(Some(item))
+    }
+}
+
+impl<M> Drop for Subscribed<M> {
+    fn drop(&mut self) {
+        let _ = self.commands.send(Command::Unsubscribe {
+            topic: std::mem::take(&mut self.topic),
+            id: self.id,
+        });
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Subscribe {
+        topic: String,
+        params: Value,
+        id: u64,
+        tx: Sender<Result<Value, Error>>,
+    },
+    Unsubscribe {
+        topic: String,
+        id: u64,
+    },
+}
+
+/// Subscribers of a topic.
+struct Topic {
+    params: Value,
+    subscribers: Vec<Subscriber>,
+}
+
+struct Subscriber {
+    id: u64,
+    tx: Sender<Result<Value, Error>>,
+    /// The number of messages dropped since the buffer is full, to be reported with
+    /// [`Error::Lagged`] once it has room.
+    dropped: u64,
+}
+
+impl Subscriber {
+    fn new(id: u64, tx: Sender<Result<Value, Error>>) -> Self {
+        Self { id, tx, dropped: 0 }
+    }
+
+    /// Sends `x` without waiting for the subscriber, dropping it if the buffer is full.
+    ///
+    /// Returns `false` if the subscriber is gone.
+    fn send(&mut self, x: Result<Value, Error>) -> bool {
+        if self.dropped > 0 {
+            match self.tx.try_send(Err(Error::Lagged(self.dropped))) {
+                Ok(()) => self.dropped = 0,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += 1;
+                    return true;
+                }
+                Err(TrySendError::Closed(_)) => return false,
+            }
+        }
+        match self.tx.try_send(x) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped += 1;
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// The background task of a [`Connection`].
+struct Manager<P> {
+    connector: Connector<P>,
+    commands: UnboundedReceiver<Command>,
+    topics: HashMap<String, Topic>,
+}
+
+impl<P: Protocol> Manager<P> {
+    async fn run(mut self) {
+        let mut delay = self.connector.base_delay;
+        loop {
+            let mut connected = false;
+            match self.session(&mut connected).await {
+                Ok(()) => return,
+                Err(e) => warn!(url = %self.connector.url, %e, "websocket connection lost"),
+            }
+            for topic in self.topics.values_mut() {
+                topic
+                    .subscribers
+                    .retain_mut(|x| x.send(Err(Error::Disconnected)));
+            }
+
+            if connected {
+                delay = self.connector.base_delay;
+            }
+            let backoff = sleep(delay);
+            tokio::pin!(backoff);
+            delay = (delay * 2).min(self.connector.max_delay);
+            // Subscriptions in between are made after reconnection
+            loop {
+                tokio::select! {
+                    _ = &mut backoff => break,
+                    command = self.commands.recv() => match command {
+                        Some(command) => {
+                            self.apply(command);
+                        }
+                        None => return,
+                    },
+                }
+            }
+        }
+    }
+
+    /// Runs a connection until every handle is dropped or an error occurs.
+    async fn session(&mut self, connected: &mut bool) -> Result<(), Error> {
+        let (mut socket, _) = timeout(
+            self.connector.timeout,
+            connect_async(self.connector.url.as_str()),
+        )
+        .await
+        .map_err(|_| Error::Timeout(self.connector.timeout))??;
+        *connected = true;
+        debug!(url = %self.connector.url, "websocket connected");
+        for (topic, x) in &self.topics {
+            let message = self.connector.protocol.subscribe(topic, &x.params);
+            socket.send(message).await?;
+        }
+
+        let period = self.connector.ping_interval;
+        let mut ping = interval_at(Instant::now() + period, period);
+        let mut last_seen = Instant::now();
+        loop {
+            tokio::select! {
+                command = self.commands.recv() => {
+                    let command = match command {
+                        Some(x) => x,
+                        None => {
+                            let _ = socket.close(None).await;
+                            return Ok(());
+                        }
+                    };
+                    if let Some(message) = self.apply(command) {
+                        socket.send(message).await?;
+                    }
+                }
+                message = socket.next() => {
+                    last_seen = Instant::now();
+                    match message.ok_or(Error::Closed)?? {
+                        Message::Text(x) => self.dispatch(&x),
+                        Message::Binary(x) => self.dispatch(&String::from_utf8_lossy(&x)),
+                        Message::Close(_) => return Err(Error::Closed),
+                        // Pings are answered by the socket itself
+                        Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+                    }
+                }
+                _ = ping.tick() => {
+                    if last_seen.elapsed() > self.connector.timeout {
+                        return Err(Error::Timeout(self.connector.timeout));
+                    }
+                    socket.send(self.connector.protocol.ping()).await?;
+                }
+            }
+        }
+    }
+
+    /// Applies `command` to the subscriptions, returning the message to be sent if any.
+    fn apply(&mut self, command: Command) -> Option<Message> {
+        match command {
+            Command::Subscribe {
+                topic,
+                params,
+                id,
+                tx,
+            } => {
+                if let Some(x) = self.topics.get_mut(&topic) {
+                    x.subscribers.push(Subscriber::new(id, tx));
+                    return None;
+                }
+                let message = self.connector.protocol.subscribe(&topic, &params);
+                self.topics.insert(
+                    topic,
+                    Topic {
+                        params,
+                        subscribers: vec![Subscriber::new(id, tx)],
+                    },
+                );
+                Some(message)
+            }
+            Command::Unsubscribe { topic, id } => {
+                let x = self.topics.get_mut(&topic)?;
+                x.subscribers.retain(|x| x.id != id);
+                if !x.subscribers.is_empty() {
+                    return None;
+                }
+                let x = self.topics.remove(&topic).expect("checked above");
+                self.connector.protocol.unsubscribe(&topic, &x.params)
+            }
+        }
+    }
+
+    fn dispatch(&mut self, text: &str) {
+        let (topic, payload) = match self.connector.protocol.demux(text) {
+            Some(x) => x,
+            None => return,
+        };
+        match self.topics.get_mut(&topic) {
+            Some(x) => x.subscribers.retain_mut(|x| x.send(Ok(payload.clone()))),
+            None => debug!(topic, "message of unknown topic"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    use super::*;
+
+    /// Subscribes with `{"subscribe": topic}` and pushes `{"topic": topic, "data": payload}`.
+    ///
+    /// Pinged with a `ping` text message.
+    struct Simple;
+
+    impl Protocol for Simple {
+        fn subscribe(&mut self, topic: &str, _params: &Value) -> Message {
+            Message::Text(serde_json::json!({ "subscribe": topic }).to_string())
+        }
+
+        fn unsubscribe(&mut self, topic: &str, _params: &Value) -> Option<Message> {
+            Some(Message::Text(
+                serde_json::json!({ "unsubscribe": topic }).to_string(),
+            ))
+        }
+
+        fn demux(&self, text: &str) -> Option<(String, Value)> {
+            let mut x: Value = serde_json::from_str(text).ok()?;
+            Some((x["topic"].as_str()?.to_string(), x["data"].take()))
+        }
+
+        fn ping(&self) -> Message {
+            Message::Text("ping".to_string())
+        }
+    }
+
+    #[derive(Serialize)]
+    struct Ticks {
+        symbol: String,
+    }
+
+    impl Subscription for Ticks {
+        type Message = Tick;
+
+        fn topic(&self) -> String {
+            format!("{}@tick", self.symbol)
+        }
+    }
+
+    #[derive(Debug, PartialEq, Eq, Deserialize)]
+    struct Tick {
+        price: u32,
+    }
+
+    fn text(x: Value) -> Message {
+        Message::Text(x.to_string())
+    }
+
+    #[tokio::test]
+    async fn demultiplexes_subscriptions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+            let mut topics = Vec::new();
+            while topics.len() < 2 {
+                let x: Value = match socket.next().await.unwrap().unwrap() {
+                    Message::Text(x) => serde_json::from_str(&x).unwrap(),
+                    _ => continue,
+                };
+                topics.push(x["subscribe"].as_str().unwrap().to_string());
+            }
+            for price in 0..2 {
+                for topic in ["a@tick", "b@tick", "c@tick"] {
+                    let x = serde_json::json!({ "topic": topic, "data": { "price": price } });
+                    socket.send(text(x)).await.unwrap();
+                }
+            }
+            let x = socket.next().await.unwrap().unwrap();
+            (topics, x)
+        });
+
+        let connection = Connector::new(url, Simple).connect();
+        let mut a = connection
+            .subscribe(&Ticks {
+                symbol: "a".to_string(),
+            })
+            .unwrap();
+        let mut b = connection
+            .subscribe(&Ticks {
+                symbol: "b".to_string(),
+            })
+            .unwrap();
+        for price in 0..2 {
+            assert_eq!(a.next().await.unwrap().unwrap(), Tick { price });
+            assert_eq!(b.next().await.unwrap().unwrap(), Tick { price });
+        }
+        drop(a);
+
+        let (mut topics, unsubscribe) = server.await.unwrap();
+        topics.sort();
+        assert_eq!(topics, ["a@tick", "b@tick"]);
+        assert_eq!(
+            unsubscribe,
+            text(serde_json::json!({ "unsubscribe": "a@tick" }))
+        );
+    }
+
+    #[tokio::test]
+    async fn resubscribes_after_reconnection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            for price in 0..2 {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut socket = accept_async(stream).await.unwrap();
+                let subscribe = socket.next().await.unwrap().unwrap();
+                assert_eq!(
+                    subscribe,
+                    text(serde_json::json!({ "subscribe": "a@tick" }))
+                );
+                let x = serde_json::json!({ "topic": "a@tick", "data": { "price": price } });
+                socket.send(text(x)).await.unwrap();
+                // Drops the connection without closing handshake
+            }
+        });
+
+        let connection = Connector::new(url, Simple)
+            .with_backoff(Duration::from_millis(10), Duration::from_millis(10))
+            .connect();
+        let mut a = connection
+            .subscribe(&Ticks {
+                symbol: "a".to_string(),
+            })
+            .unwrap();
+        assert_eq!(a.next().await.unwrap().unwrap(), Tick { price: 0 });
+        assert!(matches!(a.next().await.unwrap(), Err(Error::Disconnected)));
+        assert_eq!(a.next().await.unwrap().unwrap(), Tick { price: 1 });
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn pings_and_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+            let _subscribe = socket.next().await.unwrap().unwrap();
+            // Pings are received, but never answered
+            let ping = socket.next().await.unwrap().unwrap();
+            assert_eq!(ping, Message::Text("ping".to_string()));
+            std::future::pending::<()>().await;
+        });
+
+        let connection = Connector::new(url, Simple)
+            .with_ping(Duration::from_millis(50), Duration::from_millis(120))
+            .with_backoff(Duration::from_secs(60), Duration::from_secs(60))
+            .connect();
+        let mut a = connection
+            .subscribe(&Ticks {
+                symbol: "a".to_string(),
+            })
+            .unwrap();
+        assert!(matches!(a.next().await.unwrap(), Err(Error::Disconnected)));
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn reports_lagging_subscribers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (resume, resumed) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+            let _a = socket.next().await.unwrap().unwrap();
+            let _b = socket.next().await.unwrap().unwrap();
+            for (topic, price) in [("a", 0), ("a", 1), ("a", 2), ("a", 3), ("b", 0)] {
+                let x = serde_json::json!({ "topic": format!("{topic}@tick"), "data": { "price": price } });
+                socket.send(text(x)).await.unwrap();
+            }
+            resumed.await.unwrap();
+            let x = serde_json::json!({ "topic": "a@tick", "data": { "price": 4 } });
+            socket.send(text(x)).await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let connection = Connector::new(url, Simple).with_buffer(2).connect();
+        let mut a = connection
+            .subscribe(&Ticks {
+                symbol: "a".to_string(),
+            })
+            .unwrap();
+        let mut b = connection
+            .subscribe(&Ticks {
+                symbol: "b".to_string(),
+            })
+            .unwrap();
+        // Every message of `a` has been dispatched once `b` receives one
+        assert_eq!(b.next().await.unwrap().unwrap(), Tick { price: 0 });
+        assert_eq!(a.next().await.unwrap().unwrap(), Tick { price: 0 });
+        assert_eq!(a.next().await.unwrap().unwrap(), Tick { price: 1 });
+        resume.send(()).unwrap();
+        assert!(matches!(a.next().await.unwrap(), Err(Error::Lagged(2))));
+        assert_eq!(a.next().await.unwrap().unwrap(), Tick { price: 4 });
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn times_out_handshakes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            // Accepts the TCP connection, but never completes the handshake
+            let (_stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let connection = Connector::new(url, Simple)
+            .with_ping(Duration::from_millis(50), Duration::from_millis(100))
+            .with_backoff(Duration::from_millis(10), Duration::from_millis(10))
+            .connect();
+        let mut a = connection
+            .subscribe(&Ticks {
+                symbol: "a".to_string(),
+            })
+            .unwrap();
+        assert!(matches!(a.next().await.unwrap(), Err(Error::Disconnected)));
+        server.abort();
+    }
+}
@@ -10,14 +10,11 @@ use dashmap::DashMap;
 use http::Method;
 use http_body::Body;
 use nerf::{Client, HttpRequest, IntoService, ReadyCall, Request};
-use nerf_macros::{get, put};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio_stream::wrappers::ReceiverStream;
 use tower::ServiceExt;
 use tracing::{debug, trace};
 
-use self::__private::Sealed;
-
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 struct Item {
     name: String,
@@ -25,16 +22,16 @@ struct Item {
     precedence: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-#[get("/api", response = GetItemsResponse)]
+#[derive(Serialize, Deserialize, Debug, HttpRequest)]
+#[http(get = "/api", response = GetItemsResponse)]
 struct GetItems;
 
 #[derive(Serialize, Deserialize)]
 #[serde(transparent)]
 struct GetItemsResponse(Vec<Item>);
 
-#[derive(Serialize, Deserialize, Debug)]
-#[put("/api", response = PutItemResponse)]
+#[derive(Serialize, Deserialize, Debug, HttpRequest)]
+#[http(put = "/api", response = PutItemResponse)]
 #[serde(transparent)]
 struct PutItem(Item);
 
@@ -47,13 +44,15 @@ struct LocalTestClient<S> {
 
 impl<T, S> Client<T> for LocalTestClient<S>
 where
-    T: Request + HttpRequest + Sealed + Serialize + Debug,
+    T: Request + HttpRequest + Serialize + Debug,
     T::Response: DeserializeOwned,
 {
     type Service = S;
 
     type Error = Box<dyn Error + Send + Sync + 'static>;
 
+    type Context = Method;
+
     type TryFromResponseFuture =
         Pin<Box<dyn Future<Output = Result<T::Response, Self::Error>> + Send + Sync + 'static>>;
 
@@ -61,8 +60,12 @@ where
         &mut self.inner
     }
 
+    fn context(&mut self, x: &T) -> Self::Context {
+        x.method()
+    }
+
     fn try_into_request(&mut self, x: T) -> Result<hyper::Request<hyper::Body>, Self::Error> {
-        let uri = x.uri();
+        let uri = x.uri()?;
         if x.method() == Method::GET {
             Ok(hyper::Request::builder()
                 .uri(format!("{uri}"))
@@ -80,8 +83,11 @@ where
         }
     }
 
-    fn try_from_response(x: hyper::Response<hyper::Body>) -> Self::TryFromResponseFuture {
-        debug!(status = ?x.status());
+    fn try_from_response(
+        cx: Self::Context,
+        x: hyper::Response<hyper::Body>,
+    ) -> Self::TryFromResponseFuture {
+        debug!(method = ?cx, status = ?x.status());
         Box::pin(async move {
             let mut s = String::new();
             hyper::body::aggregate(x)
@@ -159,7 +165,3 @@ async fn test() {
         &[item]
     );
 }
-
-mod __private {
-    pub trait Sealed {}
-}